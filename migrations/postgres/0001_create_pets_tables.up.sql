@@ -0,0 +1,30 @@
+-- Initial schema. Uses IF NOT EXISTS so databases created before migrations
+-- existed are adopted as-is.
+CREATE TABLE IF NOT EXISTS apts (
+    id SERIAL PRIMARY KEY,
+    apt INTEGER NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS dogs (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    breed VARCHAR NOT NULL,
+    apt INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS cats (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    hair BOOLEAN NOT NULL,
+    apt INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS birds (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    species VARCHAR NOT NULL,
+    apt INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS apt_idx ON apts USING HASH(apt);
+CREATE INDEX IF NOT EXISTS dogs_apt_idx ON dogs(apt);
+CREATE INDEX IF NOT EXISTS cats_apt_idx ON cats(apt);
+CREATE INDEX IF NOT EXISTS birds_apt_idx ON birds(apt);
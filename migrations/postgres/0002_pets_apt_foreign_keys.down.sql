@@ -0,0 +1,3 @@
+ALTER TABLE birds DROP CONSTRAINT IF EXISTS birds_apt_fkey;
+ALTER TABLE cats DROP CONSTRAINT IF EXISTS cats_apt_fkey;
+ALTER TABLE dogs DROP CONSTRAINT IF EXISTS dogs_apt_fkey;
@@ -0,0 +1,8 @@
+-- Pets left behind by apartments that no longer exist would violate the new constraints
+DELETE FROM dogs WHERE apt NOT IN (SELECT apt FROM apts);
+DELETE FROM cats WHERE apt NOT IN (SELECT apt FROM apts);
+DELETE FROM birds WHERE apt NOT IN (SELECT apt FROM apts);
+
+ALTER TABLE dogs ADD CONSTRAINT dogs_apt_fkey FOREIGN KEY (apt) REFERENCES apts(apt) ON DELETE CASCADE;
+ALTER TABLE cats ADD CONSTRAINT cats_apt_fkey FOREIGN KEY (apt) REFERENCES apts(apt) ON DELETE CASCADE;
+ALTER TABLE birds ADD CONSTRAINT birds_apt_fkey FOREIGN KEY (apt) REFERENCES apts(apt) ON DELETE CASCADE;
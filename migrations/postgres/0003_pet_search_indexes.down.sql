@@ -0,0 +1,8 @@
+-- pg_trgm is left installed, other objects in the database may rely on it
+DROP INDEX IF EXISTS cats_weight_idx;
+DROP INDEX IF EXISTS dogs_weight_idx;
+DROP INDEX IF EXISTS birds_species_idx;
+DROP INDEX IF EXISTS dogs_breed_idx;
+DROP INDEX IF EXISTS birds_name_trgm_idx;
+DROP INDEX IF EXISTS cats_name_trgm_idx;
+DROP INDEX IF EXISTS dogs_name_trgm_idx;
@@ -0,0 +1,10 @@
+-- Trigram indexes serve both prefix and substring name searches
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX dogs_name_trgm_idx ON dogs USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX cats_name_trgm_idx ON cats USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX birds_name_trgm_idx ON birds USING GIN (lower(name) gin_trgm_ops);
+
+CREATE INDEX dogs_breed_idx ON dogs (lower(breed));
+CREATE INDEX birds_species_idx ON birds (lower(species));
+CREATE INDEX dogs_weight_idx ON dogs (weight);
+CREATE INDEX cats_weight_idx ON cats (weight);
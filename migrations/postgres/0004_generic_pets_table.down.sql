@@ -0,0 +1,45 @@
+-- Only the original species have tables to go back to, pets of species added through the
+-- registry are dropped
+CREATE TABLE dogs (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    breed VARCHAR NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+CREATE TABLE cats (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    hair BOOLEAN NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+CREATE TABLE birds (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    species VARCHAR NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+
+INSERT INTO dogs (id, name, weight, breed, apt)
+    SELECT id, name, (attributes->>'weight')::INTEGER, attributes->>'breed', apt FROM pets WHERE animal = 'Dog';
+INSERT INTO cats (id, name, weight, hair, apt)
+    SELECT id, name, (attributes->>'weight')::INTEGER, attributes->>'hair' = 'LongHaired', apt FROM pets WHERE animal = 'Cat';
+INSERT INTO birds (id, name, species, apt)
+    SELECT id, name, attributes->>'species', apt FROM pets WHERE animal = 'Bird';
+SELECT setval('dogs_id_seq', (SELECT last_value FROM pets_id_seq));
+SELECT setval('cats_id_seq', (SELECT last_value FROM pets_id_seq));
+SELECT setval('birds_id_seq', (SELECT last_value FROM pets_id_seq));
+
+DROP TABLE pets;
+
+CREATE INDEX dogs_apt_idx ON dogs(apt);
+CREATE INDEX cats_apt_idx ON cats(apt);
+CREATE INDEX birds_apt_idx ON birds(apt);
+CREATE INDEX dogs_name_trgm_idx ON dogs USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX cats_name_trgm_idx ON cats USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX birds_name_trgm_idx ON birds USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX dogs_breed_idx ON dogs (lower(breed));
+CREATE INDEX birds_species_idx ON birds (lower(species));
+CREATE INDEX dogs_weight_idx ON dogs (weight);
+CREATE INDEX cats_weight_idx ON cats (weight);
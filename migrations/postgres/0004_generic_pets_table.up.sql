@@ -0,0 +1,35 @@
+-- Every species shares one table, fields other than the name are kept in attributes and
+-- validated against the species registry. Existing pets keep their ids, which are only unique
+-- per species, so the key is (animal, id). New pets draw from one sequence started past every
+-- existing id.
+CREATE SEQUENCE pets_id_seq;
+CREATE TABLE pets (
+    id INTEGER NOT NULL DEFAULT nextval('pets_id_seq'),
+    animal VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    attributes JSONB NOT NULL DEFAULT '{}',
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE,
+    PRIMARY KEY (animal, id)
+);
+ALTER SEQUENCE pets_id_seq OWNED BY pets.id;
+
+INSERT INTO pets (id, animal, name, attributes, apt)
+    SELECT id, 'Dog', name, jsonb_build_object('weight', weight, 'breed', breed), apt FROM dogs;
+INSERT INTO pets (id, animal, name, attributes, apt)
+    SELECT id, 'Cat', name, jsonb_build_object('weight', weight, 'hair', CASE WHEN hair THEN 'LongHaired' ELSE 'ShortHaired' END), apt FROM cats;
+INSERT INTO pets (id, animal, name, attributes, apt)
+    SELECT id, 'Bird', name, jsonb_build_object('species', species), apt FROM birds;
+SELECT setval('pets_id_seq', GREATEST(
+    (SELECT last_value FROM dogs_id_seq),
+    (SELECT last_value FROM cats_id_seq),
+    (SELECT last_value FROM birds_id_seq),
+    (SELECT MAX(id) FROM pets)
+));
+
+DROP TABLE dogs;
+DROP TABLE cats;
+DROP TABLE birds;
+
+CREATE INDEX pets_apt_idx ON pets (apt);
+CREATE INDEX pets_name_trgm_idx ON pets USING GIN (lower(name) gin_trgm_ops);
+CREATE INDEX pets_attributes_idx ON pets USING GIN (attributes);
@@ -0,0 +1,24 @@
+-- Vaccinations and licenses belong to a pet and go with it
+CREATE TABLE vaccinations (
+    id SERIAL PRIMARY KEY,
+    animal VARCHAR NOT NULL,
+    pet_id INTEGER NOT NULL,
+    vaccine VARCHAR NOT NULL,
+    given_on DATE NOT NULL,
+    expires_on DATE NOT NULL,
+    vet VARCHAR NOT NULL,
+    FOREIGN KEY (animal, pet_id) REFERENCES pets(animal, id) ON DELETE CASCADE
+);
+CREATE TABLE licenses (
+    id SERIAL PRIMARY KEY,
+    animal VARCHAR NOT NULL,
+    pet_id INTEGER NOT NULL,
+    number VARCHAR NOT NULL UNIQUE,
+    issued_on DATE NOT NULL,
+    expires_on DATE NOT NULL,
+    FOREIGN KEY (animal, pet_id) REFERENCES pets(animal, id) ON DELETE CASCADE
+);
+CREATE INDEX vaccinations_pet_idx ON vaccinations (animal, pet_id);
+CREATE INDEX vaccinations_expires_idx ON vaccinations (expires_on);
+CREATE INDEX licenses_pet_idx ON licenses (animal, pet_id);
+CREATE INDEX licenses_expires_idx ON licenses (expires_on);
@@ -0,0 +1,3 @@
+ALTER TABLE pets DROP COLUMN owner_id;
+DROP TABLE tenancies;
+DROP TABLE tenants;
@@ -0,0 +1,20 @@
+-- Tenants live in apartments for a stretch of time, a tenancy without move_out is ongoing.
+-- Apartments are only numbers here, a tenancy can start before the apartment registers pets.
+CREATE TABLE tenants (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    email VARCHAR,
+    phone VARCHAR
+);
+CREATE TABLE tenancies (
+    tenant_id INTEGER NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    apt INTEGER NOT NULL,
+    move_in DATE NOT NULL,
+    move_out DATE
+);
+CREATE INDEX tenancies_tenant_idx ON tenancies (tenant_id);
+CREATE INDEX tenancies_apt_idx ON tenancies (apt);
+
+-- The tenant responsible for the pet, cleared when the tenant is removed
+ALTER TABLE pets ADD COLUMN owner_id INTEGER REFERENCES tenants(id) ON DELETE SET NULL;
+CREATE INDEX pets_owner_idx ON pets (owner_id);
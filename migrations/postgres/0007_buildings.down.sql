@@ -0,0 +1,24 @@
+-- Only numbered units of the default building fit back into an integer apartment, every other
+-- unit is dropped along with its pets and tenancies
+DELETE FROM apts WHERE building <> 'default' OR unit !~ '^-?[0-9]{1,9}$';
+DELETE FROM tenancies WHERE building <> 'default' OR unit !~ '^-?[0-9]{1,9}$';
+
+-- Apartments get their number back as the key
+ALTER TABLE pets DROP CONSTRAINT pets_apt_fkey;
+UPDATE pets SET apt = apts.unit::INTEGER FROM apts WHERE pets.apt = apts.apt;
+ALTER TABLE apts DROP CONSTRAINT apts_apt_key;
+ALTER TABLE apts ALTER COLUMN apt DROP DEFAULT;
+DROP SEQUENCE apts_apt_seq;
+UPDATE apts SET apt = unit::INTEGER;
+ALTER TABLE apts ADD CONSTRAINT apts_apt_key UNIQUE (apt);
+ALTER TABLE pets ADD CONSTRAINT pets_apt_fkey FOREIGN KEY (apt) REFERENCES apts(apt) ON DELETE CASCADE;
+ALTER TABLE apts DROP COLUMN building, DROP COLUMN unit;
+
+ALTER TABLE tenancies ADD COLUMN apt INTEGER;
+UPDATE tenancies SET apt = unit::INTEGER;
+ALTER TABLE tenancies ALTER COLUMN apt SET NOT NULL;
+ALTER TABLE tenancies DROP COLUMN building, DROP COLUMN unit;
+CREATE INDEX tenancies_apt_idx ON tenancies (apt);
+
+DROP TABLE buildings;
+DROP TABLE properties;
@@ -0,0 +1,34 @@
+-- Apartments become units of a building and buildings belong to a property. Units are named by
+-- strings that are unique within their building. Apartments registered before buildings existed
+-- become units of the default building, named after their number.
+CREATE TABLE properties (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE
+);
+CREATE TABLE buildings (
+    id SERIAL PRIMARY KEY,
+    property_id INTEGER NOT NULL REFERENCES properties(id),
+    code VARCHAR COLLATE "C" NOT NULL UNIQUE,
+    name VARCHAR NOT NULL
+);
+INSERT INTO properties (name) VALUES ('Default');
+INSERT INTO buildings (property_id, code, name) SELECT id, 'default', 'Default' FROM properties;
+
+-- apts.apt stays the key pets refer to. Migrated apartments keep their number as the key, new
+-- ones draw from apts_apt_seq. Codes and units sort bytewise, the same as in SQLite.
+ALTER TABLE apts ADD COLUMN building VARCHAR COLLATE "C" REFERENCES buildings(code);
+ALTER TABLE apts ADD COLUMN unit VARCHAR COLLATE "C";
+UPDATE apts SET building = 'default', unit = apt::TEXT;
+ALTER TABLE apts ALTER COLUMN building SET NOT NULL, ALTER COLUMN unit SET NOT NULL;
+ALTER TABLE apts ADD CONSTRAINT apts_building_unit_key UNIQUE (building, unit);
+CREATE SEQUENCE apts_apt_seq OWNED BY apts.apt;
+SELECT setval('apts_apt_seq', GREATEST((SELECT MAX(apt) FROM apts), 0) + 1, false);
+ALTER TABLE apts ALTER COLUMN apt SET DEFAULT nextval('apts_apt_seq');
+
+-- Tenancies name their unit the same way, the unit doesn't have to be registered
+ALTER TABLE tenancies ADD COLUMN building VARCHAR COLLATE "C" REFERENCES buildings(code);
+ALTER TABLE tenancies ADD COLUMN unit VARCHAR COLLATE "C";
+UPDATE tenancies SET building = 'default', unit = apt::TEXT;
+ALTER TABLE tenancies ALTER COLUMN building SET NOT NULL, ALTER COLUMN unit SET NOT NULL;
+ALTER TABLE tenancies DROP COLUMN apt;
+CREATE INDEX tenancies_unit_idx ON tenancies (building, unit);
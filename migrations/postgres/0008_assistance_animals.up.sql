@@ -0,0 +1,2 @@
+-- Service and emotional support animal designation with its documentation and verification
+ALTER TABLE pets ADD COLUMN assistance JSONB;
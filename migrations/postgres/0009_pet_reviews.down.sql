@@ -0,0 +1,7 @@
+DROP INDEX pets_status_idx;
+ALTER TABLE pets
+    DROP COLUMN status,
+    DROP COLUMN submitted_at,
+    DROP COLUMN reviewed_by,
+    DROP COLUMN reviewed_at,
+    DROP COLUMN reason;
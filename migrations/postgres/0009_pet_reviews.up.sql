@@ -0,0 +1,10 @@
+-- Registration review of each pet. Pets registered before reviews existed count as approved,
+-- new pets are inserted as pending.
+ALTER TABLE pets
+    ADD COLUMN status VARCHAR NOT NULL DEFAULT 'approved' CHECK (status IN ('pending', 'approved', 'rejected', 'removed')),
+    ADD COLUMN submitted_at TIMESTAMPTZ,
+    ADD COLUMN reviewed_by VARCHAR,
+    ADD COLUMN reviewed_at TIMESTAMPTZ,
+    ADD COLUMN reason VARCHAR;
+ALTER TABLE pets ALTER COLUMN status SET DEFAULT 'pending';
+CREATE INDEX pets_status_idx ON pets (status);
@@ -0,0 +1,37 @@
+-- Append-only record of every change to apartments and their pets. Pets are kept as json the
+-- way the store reads them: a change to a whole apartment has all of its pets, a change to one
+-- pet just that pet. Entries are keyed by building and unit so they outlive the apartment.
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    at TIMESTAMPTZ NOT NULL,
+    actor VARCHAR NOT NULL,
+    manager BOOLEAN NOT NULL,
+    action VARCHAR NOT NULL CHECK (action IN ('create', 'update', 'delete')),
+    building VARCHAR NOT NULL,
+    unit VARCHAR NOT NULL,
+    animal VARCHAR,
+    pet_id INTEGER,
+    before JSONB,
+    after JSONB
+);
+CREATE INDEX audit_log_apt_idx ON audit_log (building, unit, at);
+
+CREATE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER audit_log_append_only BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+-- Apartments registered before the log existed start from what they hold today
+INSERT INTO audit_log (at, actor, manager, action, building, unit, after)
+    SELECT NOW(), 'migration', TRUE, 'create', apts.building, apts.unit,
+        (SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                'id', id, 'animal', animal, 'name', name, 'attributes', attributes, 'owner', owner_id, 'assistance', assistance,
+                'review', jsonb_build_object(
+                    'status', status, 'submitted_at', submitted_at, 'reviewed_by', reviewed_by, 'reviewed_at', reviewed_at, 'reason', reason
+                )
+            ) ORDER BY id, animal), '[]')
+            FROM pets WHERE pets.apt = apts.apt)
+    FROM apts ORDER BY building, length(unit), unit;
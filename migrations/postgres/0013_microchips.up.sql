@@ -0,0 +1,4 @@
+-- Optional microchip number, unique among pets that haven't been deleted so a replaced pet
+-- can be registered again with its chip
+ALTER TABLE pets ADD COLUMN microchip VARCHAR;
+CREATE UNIQUE INDEX pets_microchip_idx ON pets (microchip) WHERE deleted_at IS NULL;
@@ -0,0 +1,5 @@
+ALTER TABLE audit_log DISABLE TRIGGER audit_log_append_only;
+UPDATE audit_log SET actor = claimed_actor WHERE claimed_actor IS NOT NULL;
+ALTER TABLE audit_log ENABLE TRIGGER audit_log_append_only;
+
+ALTER TABLE audit_log DROP COLUMN claimed_actor;
@@ -0,0 +1,9 @@
+-- The actor is who the request was authenticated as, the manager token or anonymous. A name the
+-- requester gave without proof is kept apart as their claimed_actor. Entries logged before
+-- this only had that name, the trigger is lifted to move it.
+ALTER TABLE audit_log ADD COLUMN claimed_actor VARCHAR;
+
+ALTER TABLE audit_log DISABLE TRIGGER audit_log_append_only;
+UPDATE audit_log SET claimed_actor = actor, actor = CASE WHEN manager THEN 'manager' ELSE 'anonymous' END
+    WHERE actor NOT IN ('manager', 'anonymous', 'migration');
+ALTER TABLE audit_log ENABLE TRIGGER audit_log_append_only;
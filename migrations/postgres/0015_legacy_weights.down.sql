@@ -0,0 +1 @@
+-- The dropped weights were never valid, there is nothing to put back
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS birds;
+DROP TABLE IF EXISTS cats;
+DROP TABLE IF EXISTS dogs;
+DROP TABLE IF EXISTS apts;
@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS apts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    apt INTEGER NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS dogs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    breed VARCHAR NOT NULL,
+    apt INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS cats (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    hair BOOLEAN NOT NULL,
+    apt INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS birds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    species VARCHAR NOT NULL,
+    apt INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS dogs_apt_idx ON dogs(apt);
+CREATE INDEX IF NOT EXISTS cats_apt_idx ON cats(apt);
+CREATE INDEX IF NOT EXISTS birds_apt_idx ON birds(apt);
@@ -0,0 +1,34 @@
+CREATE TABLE dogs_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    breed VARCHAR NOT NULL,
+    apt INTEGER NOT NULL
+);
+INSERT INTO dogs_old SELECT id, name, weight, breed, apt FROM dogs;
+DROP TABLE dogs;
+ALTER TABLE dogs_old RENAME TO dogs;
+CREATE INDEX dogs_apt_idx ON dogs(apt);
+
+CREATE TABLE cats_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    hair BOOLEAN NOT NULL,
+    apt INTEGER NOT NULL
+);
+INSERT INTO cats_old SELECT id, name, weight, hair, apt FROM cats;
+DROP TABLE cats;
+ALTER TABLE cats_old RENAME TO cats;
+CREATE INDEX cats_apt_idx ON cats(apt);
+
+CREATE TABLE birds_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    species VARCHAR NOT NULL,
+    apt INTEGER NOT NULL
+);
+INSERT INTO birds_old SELECT id, name, species, apt FROM birds;
+DROP TABLE birds;
+ALTER TABLE birds_old RENAME TO birds;
+CREATE INDEX birds_apt_idx ON birds(apt);
@@ -0,0 +1,36 @@
+-- SQLite can't add constraints to existing tables, so each pet table is rebuilt
+-- with the foreign key and only the pets whose apartment still exists are copied over
+CREATE TABLE dogs_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    breed VARCHAR NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+INSERT INTO dogs_new SELECT id, name, weight, breed, apt FROM dogs WHERE apt IN (SELECT apt FROM apts);
+DROP TABLE dogs;
+ALTER TABLE dogs_new RENAME TO dogs;
+CREATE INDEX dogs_apt_idx ON dogs(apt);
+
+CREATE TABLE cats_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    hair BOOLEAN NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+INSERT INTO cats_new SELECT id, name, weight, hair, apt FROM cats WHERE apt IN (SELECT apt FROM apts);
+DROP TABLE cats;
+ALTER TABLE cats_new RENAME TO cats;
+CREATE INDEX cats_apt_idx ON cats(apt);
+
+CREATE TABLE birds_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    species VARCHAR NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+INSERT INTO birds_new SELECT id, name, species, apt FROM birds WHERE apt IN (SELECT apt FROM apts);
+DROP TABLE birds;
+ALTER TABLE birds_new RENAME TO birds;
+CREATE INDEX birds_apt_idx ON birds(apt);
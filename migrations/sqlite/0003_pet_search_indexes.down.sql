@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS cats_weight_idx;
+DROP INDEX IF EXISTS dogs_weight_idx;
+DROP INDEX IF EXISTS birds_species_idx;
+DROP INDEX IF EXISTS dogs_breed_idx;
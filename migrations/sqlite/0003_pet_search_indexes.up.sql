@@ -0,0 +1,5 @@
+-- SQLite can't index LIKE '%...%', name searches scan the pet tables
+CREATE INDEX dogs_breed_idx ON dogs (lower(breed));
+CREATE INDEX birds_species_idx ON birds (lower(species));
+CREATE INDEX dogs_weight_idx ON dogs (weight);
+CREATE INDEX cats_weight_idx ON cats (weight);
@@ -0,0 +1,45 @@
+-- Only the original species have tables to go back to, pets of species added through the
+-- registry are dropped
+CREATE TABLE dogs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    breed VARCHAR NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+CREATE TABLE cats (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    weight INTEGER NOT NULL,
+    hair BOOLEAN NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+CREATE TABLE birds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL,
+    species VARCHAR NOT NULL,
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE
+);
+
+INSERT INTO dogs (id, name, weight, breed, apt)
+    SELECT id, name, json_extract(attributes, '$.weight'), json_extract(attributes, '$.breed'), apt FROM pets WHERE animal = 'Dog';
+INSERT INTO cats (id, name, weight, hair, apt)
+    SELECT id, name, json_extract(attributes, '$.weight'), json_extract(attributes, '$.hair') = 'LongHaired', apt FROM pets WHERE animal = 'Cat';
+INSERT INTO birds (id, name, species, apt)
+    SELECT id, name, json_extract(attributes, '$.species'), apt FROM pets WHERE animal = 'Bird';
+-- Keeps AUTOINCREMENT from handing out ids that were already used
+DELETE FROM sqlite_sequence WHERE name IN ('dogs', 'cats', 'birds');
+INSERT INTO sqlite_sequence (name, seq) SELECT 'dogs', last_id FROM pets_id_seq;
+INSERT INTO sqlite_sequence (name, seq) SELECT 'cats', last_id FROM pets_id_seq;
+INSERT INTO sqlite_sequence (name, seq) SELECT 'birds', last_id FROM pets_id_seq;
+
+DROP TABLE pets;
+DROP TABLE pets_id_seq;
+
+CREATE INDEX dogs_apt_idx ON dogs(apt);
+CREATE INDEX cats_apt_idx ON cats(apt);
+CREATE INDEX birds_apt_idx ON birds(apt);
+CREATE INDEX dogs_breed_idx ON dogs (lower(breed));
+CREATE INDEX birds_species_idx ON birds (lower(species));
+CREATE INDEX dogs_weight_idx ON dogs (weight);
+CREATE INDEX cats_weight_idx ON cats (weight);
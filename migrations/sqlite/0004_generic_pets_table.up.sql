@@ -0,0 +1,32 @@
+-- Every species shares one table, fields other than the name are kept in attributes as json.
+-- Existing pets keep their ids, which are only unique per species, so the key is (animal, id).
+-- SQLite has no sequences, pets_id_seq holds the last id handed out and starts past every
+-- id the old tables used.
+CREATE TABLE pets (
+    id INTEGER NOT NULL,
+    animal VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    attributes TEXT NOT NULL DEFAULT '{}',
+    apt INTEGER NOT NULL REFERENCES apts(apt) ON DELETE CASCADE,
+    PRIMARY KEY (animal, id)
+);
+CREATE TABLE pets_id_seq (
+    last_id INTEGER NOT NULL
+);
+
+INSERT INTO pets (id, animal, name, attributes, apt)
+    SELECT id, 'Dog', name, json_object('weight', weight, 'breed', breed), apt FROM dogs;
+INSERT INTO pets (id, animal, name, attributes, apt)
+    SELECT id, 'Cat', name, json_object('weight', weight, 'hair', CASE WHEN hair THEN 'LongHaired' ELSE 'ShortHaired' END), apt FROM cats;
+INSERT INTO pets (id, animal, name, attributes, apt)
+    SELECT id, 'Bird', name, json_object('species', species), apt FROM birds;
+INSERT INTO pets_id_seq (last_id)
+    SELECT MAX(COALESCE((SELECT MAX(seq) FROM sqlite_sequence WHERE name IN ('dogs', 'cats', 'birds')), 0),
+               COALESCE((SELECT MAX(id) FROM pets), 0));
+
+DROP TABLE dogs;
+DROP TABLE cats;
+DROP TABLE birds;
+
+CREATE INDEX pets_apt_idx ON pets (apt);
+CREATE INDEX pets_name_idx ON pets (lower(name));
@@ -0,0 +1,4 @@
+DROP INDEX pets_owner_idx;
+ALTER TABLE pets DROP COLUMN owner_id;
+DROP TABLE tenancies;
+DROP TABLE tenants;
@@ -0,0 +1,26 @@
+-- Only numbered units of the default building fit back into an integer apartment, every other
+-- unit is dropped along with its pets and tenancies
+DELETE FROM apts WHERE building <> 'default' OR unit = '' OR unit GLOB '*[^0-9-]*' OR length(unit) > 10;
+DELETE FROM tenancies WHERE building <> 'default' OR unit = '' OR unit GLOB '*[^0-9-]*' OR length(unit) > 10;
+
+-- Apartments get their number back as the key. Keys are moved out of the way first so the
+-- unique constraint holds row by row, pets are only checked against apts at commit.
+PRAGMA defer_foreign_keys = ON;
+UPDATE pets SET apt = apt + 10000000000;
+UPDATE apts SET apt = apt + 10000000000;
+UPDATE pets SET apt = (SELECT CAST(unit AS INTEGER) FROM apts WHERE apts.apt = pets.apt);
+UPDATE apts SET apt = CAST(unit AS INTEGER);
+DROP TABLE apts_apt_seq;
+DROP INDEX apts_building_unit_idx;
+ALTER TABLE apts DROP COLUMN building;
+ALTER TABLE apts DROP COLUMN unit;
+
+DROP INDEX tenancies_unit_idx;
+ALTER TABLE tenancies ADD COLUMN apt INTEGER NOT NULL DEFAULT 0;
+UPDATE tenancies SET apt = CAST(unit AS INTEGER);
+ALTER TABLE tenancies DROP COLUMN building;
+ALTER TABLE tenancies DROP COLUMN unit;
+CREATE INDEX tenancies_apt_idx ON tenancies (apt);
+
+DROP TABLE buildings;
+DROP TABLE properties;
@@ -0,0 +1,35 @@
+-- Apartments become units of a building and buildings belong to a property. Units are named by
+-- strings that are unique within their building. Apartments registered before buildings existed
+-- become units of the default building, named after their number.
+CREATE TABLE properties (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR NOT NULL UNIQUE
+);
+CREATE TABLE buildings (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    property_id INTEGER NOT NULL REFERENCES properties(id),
+    code VARCHAR NOT NULL UNIQUE,
+    name VARCHAR NOT NULL
+);
+INSERT INTO properties (name) VALUES ('Default');
+INSERT INTO buildings (property_id, code, name) SELECT id, 'default', 'Default' FROM properties;
+
+-- apts.apt stays the key pets refer to. Migrated apartments keep their number as the key, new
+-- ones draw from apts_apt_seq. SQLite can't add a NOT NULL column with a foreign key, building
+-- is always set by the store.
+ALTER TABLE apts ADD COLUMN building VARCHAR REFERENCES buildings(code);
+ALTER TABLE apts ADD COLUMN unit VARCHAR NOT NULL DEFAULT '';
+UPDATE apts SET building = 'default', unit = CAST(apt AS TEXT);
+CREATE UNIQUE INDEX apts_building_unit_idx ON apts (building, unit);
+CREATE TABLE apts_apt_seq (
+    last_apt INTEGER NOT NULL
+);
+INSERT INTO apts_apt_seq (last_apt) SELECT MAX(COALESCE((SELECT MAX(apt) FROM apts), 0), 0);
+
+-- Tenancies name their unit the same way, the unit doesn't have to be registered
+DROP INDEX tenancies_apt_idx;
+ALTER TABLE tenancies ADD COLUMN building VARCHAR REFERENCES buildings(code);
+ALTER TABLE tenancies ADD COLUMN unit VARCHAR NOT NULL DEFAULT '';
+UPDATE tenancies SET building = 'default', unit = CAST(apt AS TEXT);
+ALTER TABLE tenancies DROP COLUMN apt;
+CREATE INDEX tenancies_unit_idx ON tenancies (building, unit);
@@ -0,0 +1,3 @@
+-- Service and emotional support animal designation with its documentation and verification,
+-- stored as JSON text
+ALTER TABLE pets ADD COLUMN assistance TEXT;
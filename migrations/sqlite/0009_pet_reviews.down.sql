@@ -0,0 +1,6 @@
+DROP INDEX pets_status_idx;
+ALTER TABLE pets DROP COLUMN reason;
+ALTER TABLE pets DROP COLUMN reviewed_at;
+ALTER TABLE pets DROP COLUMN reviewed_by;
+ALTER TABLE pets DROP COLUMN submitted_at;
+ALTER TABLE pets DROP COLUMN status;
@@ -0,0 +1,9 @@
+-- Registration review of each pet. Pets registered before reviews existed count as approved,
+-- SQLite can't change a column default afterwards so the store always sets the status.
+-- Timestamps are stored as text.
+ALTER TABLE pets ADD COLUMN status VARCHAR NOT NULL DEFAULT 'approved' CHECK (status IN ('pending', 'approved', 'rejected', 'removed'));
+ALTER TABLE pets ADD COLUMN submitted_at TEXT;
+ALTER TABLE pets ADD COLUMN reviewed_by VARCHAR;
+ALTER TABLE pets ADD COLUMN reviewed_at TEXT;
+ALTER TABLE pets ADD COLUMN reason VARCHAR;
+CREATE INDEX pets_status_idx ON pets (status);
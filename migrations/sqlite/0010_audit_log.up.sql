@@ -0,0 +1,40 @@
+-- Append-only record of every change to apartments and their pets. Pets are kept as json the
+-- way the store reads them: a change to a whole apartment has all of its pets, a change to one
+-- pet just that pet. Entries are keyed by building and unit so they outlive the apartment.
+-- Timestamps are stored as text.
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at TEXT NOT NULL,
+    actor VARCHAR NOT NULL,
+    manager BOOLEAN NOT NULL,
+    action VARCHAR NOT NULL CHECK (action IN ('create', 'update', 'delete')),
+    building VARCHAR NOT NULL,
+    unit VARCHAR NOT NULL,
+    animal VARCHAR,
+    pet_id INTEGER,
+    before TEXT,
+    after TEXT
+);
+CREATE INDEX audit_log_apt_idx ON audit_log (building, unit, at);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+-- Apartments registered before the log existed start from what they hold today
+INSERT INTO audit_log (at, actor, manager, action, building, unit, after)
+    SELECT strftime('%Y-%m-%d %H:%M:%S+00:00', 'now'), 'migration', TRUE, 'create', apts.building, apts.unit,
+        (SELECT json_group_array(json(pet)) FROM (
+            SELECT json_object(
+                'id', id, 'animal', animal, 'name', name, 'attributes', json(attributes), 'owner', owner_id, 'assistance', json(assistance),
+                'review', json_object(
+                    'status', status, 'submitted_at', submitted_at, 'reviewed_by', reviewed_by, 'reviewed_at', reviewed_at, 'reason', reason
+                )
+            ) AS pet
+            FROM pets WHERE pets.apt = apts.apt ORDER BY id, animal))
+    FROM apts ORDER BY building, length(unit), unit;
@@ -0,0 +1,9 @@
+-- Whatever is still waiting to be purged is deleted for good
+DELETE FROM pets WHERE deleted_at IS NOT NULL;
+DELETE FROM apts WHERE deleted_at IS NOT NULL;
+DROP INDEX pets_deleted_idx;
+DROP INDEX apts_deleted_idx;
+DROP INDEX apts_building_unit_idx;
+CREATE UNIQUE INDEX apts_building_unit_idx ON apts (building, unit);
+ALTER TABLE pets DROP COLUMN deleted_at;
+ALTER TABLE apts DROP COLUMN deleted_at;
@@ -0,0 +1,10 @@
+-- Deleted apartments and pets are kept, marked with when they were deleted, until the retention
+-- job purges them. Pets deleted along with their apartment share its deleted_at. A unit can be
+-- registered again while a deleted apartment of the same name is kept, only one can be live.
+-- Timestamps are stored as text.
+ALTER TABLE apts ADD COLUMN deleted_at TEXT;
+ALTER TABLE pets ADD COLUMN deleted_at TEXT;
+DROP INDEX apts_building_unit_idx;
+CREATE UNIQUE INDEX apts_building_unit_idx ON apts (building, unit) WHERE deleted_at IS NULL;
+CREATE INDEX apts_deleted_idx ON apts (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX pets_deleted_idx ON pets (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,20 @@
+-- Incidents are reported in a unit and may be about one of its pets. They stay with the unit
+-- where they happened, a pet's incidents follow it to another apartment and become the unit's
+-- once the pet is purged. Dates are stored as YYYY-MM-DD text.
+CREATE TABLE incidents (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    building VARCHAR NOT NULL,
+    unit VARCHAR NOT NULL,
+    animal VARCHAR,
+    pet_id INTEGER,
+    type VARCHAR NOT NULL CHECK (type IN ('noise', 'bite', 'damage', 'other')),
+    severity VARCHAR NOT NULL CHECK (severity IN ('low', 'medium', 'high')),
+    occurred_on TEXT NOT NULL,
+    description VARCHAR NOT NULL,
+    reporter VARCHAR NOT NULL,
+    resolution VARCHAR,
+    resolved_on TEXT,
+    FOREIGN KEY (animal, pet_id) REFERENCES pets(animal, id) ON DELETE SET NULL
+);
+CREATE INDEX incidents_unit_idx ON incidents (building, unit);
+CREATE INDEX incidents_pet_idx ON incidents (animal, pet_id);
@@ -0,0 +1,2 @@
+DROP INDEX pets_microchip_idx;
+ALTER TABLE pets DROP COLUMN microchip;
@@ -0,0 +1,8 @@
+DROP TRIGGER audit_log_no_update;
+UPDATE audit_log SET actor = claimed_actor WHERE claimed_actor IS NOT NULL;
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+ALTER TABLE audit_log DROP COLUMN claimed_actor;
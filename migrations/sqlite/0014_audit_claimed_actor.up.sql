@@ -0,0 +1,12 @@
+-- The actor is who the request was authenticated as, the manager token or anonymous. A name the
+-- requester gave without proof is kept apart as their claimed_actor. Entries logged before
+-- this only had that name, the trigger is dropped while it is moved.
+ALTER TABLE audit_log ADD COLUMN claimed_actor VARCHAR;
+
+DROP TRIGGER audit_log_no_update;
+UPDATE audit_log SET claimed_actor = actor, actor = CASE WHEN manager THEN 'manager' ELSE 'anonymous' END
+    WHERE actor NOT IN ('manager', 'anonymous', 'migration');
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
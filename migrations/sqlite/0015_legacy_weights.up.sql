@@ -0,0 +1,5 @@
+-- The dogs and cats tables took any weight, pets are validated now and zero or negative
+-- weights are rejected. Those the generic pets table inherited are dropped, the pet has no
+-- weight until it is sent again with a real one.
+UPDATE pets SET attributes = json_remove(attributes, '$.weight')
+    WHERE json_type(attributes, '$.weight') IN ('integer', 'real') AND json_extract(attributes, '$.weight') <= 0;
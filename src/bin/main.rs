@@ -1,36 +1,19 @@
 use apt_pets::ThreadPool;
+use apt_pets::catalog::Catalog;
+use apt_pets::fees::FeeSchedule;
+use apt_pets::handlers::{handle_binary_request, purge_deleted, read_request, App, PAYLOAD_TOO_LARGE};
+use apt_pets::migrations::{self, Migrate, MigrationError};
+use apt_pets::photos::{self, PhotoStore};
+use apt_pets::policy::Policy;
+use apt_pets::species::SpeciesRegistry;
+use apt_pets::store;
+use chrono::Days;
 use postgres::{Client, NoTls};
-use postgres::Error as PostgresError;
 use std::net::{ TcpListener, TcpStream };
-use std::io::{ Read, Write };
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[macro_use]
-extern crate serde_derive;
-
-#[derive(Serialize, Deserialize, Debug)]
-struct Dog {
-    name: String,
-    weight: i32,
-    breed: String,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct Cat {
-    name: String,
-    weight: i32,
-    hair: bool,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct Bird {
-    name: String,
-    species: String,
-}
-
-// Binary constants
-const OK_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n";
-const NOT_FOUND: &str = "HTTP/1.1 404 NOT FOUND\r\n\r\n";
-const INTERNAL_SERVER_ERROR: &str = "HTTP/1.1 500 INTERNAL SERVER ERROR\r\n\r\n";
 fn main() {
     // Environment constants
     const DB_URL: &str = match option_env!("DB_URL") {
@@ -50,11 +33,123 @@ fn main() {
         None => 10
     };
 
-    if let Err(e) = set_database(DB_URL) {
-        println!("Error: {}", e);
-        return;
+    // Set at runtime to pick a different backend without rebuilding, e.g. DB_URL=memory://
+    let db_url = std::env::var("DB_URL").unwrap_or_else(|_| DB_URL.to_string());
+
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        Some("migrate") => {
+            if let Err(e) = migrate_command(&args[2..], &db_url) {
+                println!("Error: {}", e);
+            }
+            return;
+        },
+        Some(command) => {
+            println!("Unknown command: {}", command);
+            println!("Usage: apt-pets [migrate up | migrate down [steps] | migrate status]");
+            return;
+        },
+        None => {}
     }
 
+    let store = match store::open(&db_url) {
+        Ok(store) => store,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+
+    // Lease rules, without a file every payload is accepted
+    let policy = match std::env::var("POLICY_FILE") {
+        Ok(path) => match Policy::load(&path) {
+            Ok(policy) => policy,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        },
+        Err(_) => Policy::default()
+    };
+
+    // Pet rent and deposits, without a file pets are free
+    let fees = match std::env::var("FEES_FILE") {
+        Ok(path) => match FeeSchedule::load(&path) {
+            Ok(fees) => fees,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        },
+        Err(_) => FeeSchedule::default()
+    };
+
+    // Extra species on top of Dog, Cat and Bird
+    let species = match std::env::var("SPECIES_FILE") {
+        Ok(path) => match SpeciesRegistry::load(&path) {
+            Ok(species) => species,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        },
+        Err(_) => SpeciesRegistry::default()
+    };
+
+    // Breeds and bird species on top of the bundled catalog
+    let catalog = match std::env::var("CATALOG_FILE") {
+        Ok(path) => match Catalog::load(&path) {
+            Ok(catalog) => catalog,
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        },
+        Err(_) => Catalog::default()
+    };
+
+    // Uploaded photos and their thumbnails
+    let photo_dir = std::env::var("PHOTO_DIR").unwrap_or_else(|_| "photos".to_string());
+    let photo_max_bytes = match std::env::var("PHOTO_MAX_BYTES") {
+        Ok(max) => match max.parse::<usize>() {
+            Ok(max) => max,
+            Err(_) => {
+                println!("Error: PHOTO_MAX_BYTES must be an integer");
+                return;
+            }
+        },
+        Err(_) => photos::DEFAULT_MAX_BYTES
+    };
+    let photos = PhotoStore::new(photo_dir, photo_max_bytes);
+
+    // Managers see assistance animal documentation and verify it
+    let manager_token = std::env::var("MANAGER_TOKEN").ok().filter(|token| !token.is_empty());
+
+    // Deleted apartments and pets can be restored for this long before they are purged
+    let retention_days = match std::env::var("RETENTION_DAYS") {
+        Ok(days) => match days.parse::<u64>() {
+            Ok(days) => days,
+            Err(_) => {
+                println!("Error: RETENTION_DAYS must be an integer");
+                return;
+            }
+        },
+        Err(_) => 30
+    };
+
+    let app = Arc::new(App { store, policy, fees, species, catalog, photos, manager_token });
+
+    // Purges hourly on its own thread, a failed purge is retried at the next one
+    let purger = Arc::clone(&app);
+    std::thread::spawn(move || loop {
+        match purge_deleted(&purger, Days::new(retention_days)) {
+            Ok(0) => {},
+            Ok(purged) => println!("Purged {} deleted pets", purged),
+            Err(e) => println!("Error: {}", e)
+        }
+        std::thread::sleep(Duration::from_secs(60 * 60));
+    });
+
     let listener = TcpListener::bind(SERVER_ADDR).unwrap();
     println!("Listening on {}", SERVER_ADDR);
 
@@ -63,8 +158,9 @@ fn main() {
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                pool.execute(|| {
-                    handle_connection(stream, DB_URL);
+                let app = Arc::clone(&app);
+                pool.execute(move || {
+                    handle_connection(stream, &app);
                 });
             },
             Err(e) => {
@@ -75,351 +171,81 @@ fn main() {
 
 }
 
-fn set_database(db_url: &str) -> Result<(), PostgresError> {
-    let mut client = Client::connect(db_url, NoTls)?;
+fn migrate_command(args: &[String], db_url: &str) -> Result<(), MigrationError> {
+    let mut db: Box<dyn Migrate> = match db_url.split("://").next() {
+        Some("sqlite") => Box::new(store::open_sqlite(db_url)?),
+        _ => Box::new(Client::connect(db_url, NoTls)?)
+    };
+    let db = db.as_mut();
 
-    client.batch_execute(
-        "CREATE TABLE IF NOT EXISTS apts (
-            id SERIAL PRIMARY KEY,
-            apt INTEGER NOT NULL UNIQUE
-        );
-        CREATE TABLE IF NOT EXISTS dogs (
-            id SERIAL PRIMARY KEY,
-            name VARCHAR NOT NULL,
-            weight INTEGER NOT NULL,
-            breed VARCHAR NOT NULL,
-            apt INTEGER NOT NULL
-        );
-        CREATE TABLE IF NOT EXISTS cats (
-            id SERIAL PRIMARY KEY,
-            name VARCHAR NOT NULL,
-            weight INTEGER NOT NULL,
-            hair BOOLEAN NOT NULL,
-            apt INTEGER NOT NULL
-        );
-        CREATE TABLE IF NOT EXISTS birds (
-            id SERIAL PRIMARY KEY,
-            name VARCHAR NOT NULL,
-            species VARCHAR NOT NULL,
-            apt INTEGER NOT NULL
-        );
-        CREATE INDEX IF NOT EXISTS apt_idx ON apts USING HASH(apt);
-        CREATE INDEX IF NOT EXISTS dogs_apt_idx ON dogs(apt);
-        CREATE INDEX IF NOT EXISTS cats_apt_idx ON cats(apt);
-        CREATE INDEX IF NOT EXISTS birds_apt_idx ON birds(apt);"
-    )?;
+    match args.first().map(|a| a.as_str()) {
+        None | Some("up") => {
+            let applied = migrations::migrate(db)?;
+            if applied.is_empty() {
+                println!("No pending migrations");
+            }
+            for version in applied {
+                println!("Applied migration {}", migration_name(db, version));
+            }
+        },
+        Some("down") => {
+            let steps = match args.get(1) {
+                Some(steps) => match steps.parse::<usize>() {
+                    Ok(steps) => steps,
+                    Err(_) => {
+                        println!("Steps must be an integer");
+                        return Ok(());
+                    }
+                },
+                None => 1
+            };
+            let reverted = migrations::rollback(db, steps)?;
+            if reverted.is_empty() {
+                println!("No migrations to revert");
+            }
+            for version in reverted {
+                println!("Reverted migration {}", migration_name(db, version));
+            }
+        },
+        Some("status") => {
+            for m in migrations::status(db)? {
+                match m.applied_at {
+                    Some(at) => println!("{:04}_{}  applied {}", m.version, m.name, at),
+                    None => println!("{:04}_{}  pending", m.version, m.name)
+                }
+            }
+        },
+        Some(command) => println!("Unknown migrate command: {}", command)
+    }
     Ok(())
 }
 
-fn get_apt(request: &str) -> &str {
-    request.split("/").nth(2).unwrap_or_default().split_whitespace().next().unwrap_or_default()
-}
-
-fn get_request_body(request: &str) -> Result<serde_json::Value, serde_json::Error> {
-    println!("Request: {}", request);
-    let res = serde_json::from_str(request.split("\r\n\r\n").last().unwrap_or_default());
-    println!("Result: {:?}", res);
-    res
+fn migration_name(db: &dyn Migrate, version: i64) -> String {
+    match db.migrations().iter().find(|m| m.version == version) {
+        Some(m) => format!("{:04}_{}", m.version, m.name),
+        None => format!("{:04}", version)
+    }
 }
 
-fn handle_connection(mut stream: TcpStream, db_url: &str) {
-    let mut buffer = [0; 1024];
-    let mut request = String::new();
-
-    match stream.read(&mut buffer) {
-        Ok(size) => {
-            request.push_str(String::from_utf8_lossy(&buffer[0..size]).as_ref());
+fn handle_connection(mut stream: TcpStream, app: &App) {
+    // A client that stops sending mid-body would otherwise hold a worker forever
+    if let Err(e) = stream.set_read_timeout(Some(Duration::from_secs(30))) {
+        println!("Error: {}", e);
+    }
 
-            let (status_line, content) = match &*request {
-                r if r.starts_with("POST /pets/") => handle_post_request(r, db_url),
-                r if r.starts_with("GET /pets/") => handle_get_request(r, db_url),
-                _ => (NOT_FOUND.to_string(), "404 NOT FOUND".to_string()),
+    match read_request(&mut stream, app.photos.max_bytes()) {
+        Ok(request) => {
+            let (status_line, content) = match request {
+                Some(request) => handle_binary_request(&request, app),
+                None => (PAYLOAD_TOO_LARGE.to_string(), b"Request body too large".to_vec())
             };
 
-            stream.write_all(format!("{}{}", status_line, content).as_bytes()).unwrap();
+            let mut response = status_line.into_bytes();
+            response.extend_from_slice(&content);
+            stream.write_all(&response).unwrap();
         }
         Err(e) => {
             println!("Error: {}", e);
         }
     }
 }
-
-fn handle_post_request(request: &str, db_url: &str) -> (String, String) {
-    println!("Received POST request: {}", request);
-    match (get_apt(&request).parse::<i32>(), Client::connect(db_url, NoTls)) {
-        (Ok(apt), Ok(mut client)) =>
-            match client.query("SELECT * FROM apts WHERE apt = $1", &[&apt]) {
-                Ok(rows) => {
-                    match rows.len() {
-                        0 => {
-                            match get_request_body(&request) {
-                                Ok(body) => {
-                                    match get_pets_vecs(body) {
-                                        Ok(pets) => {
-                                            println!("Pets: {:?}", pets);
-                                            client.execute("INSERT INTO apts (apt) VALUES ($1)", &[&apt]).unwrap();
-
-                                            if pets.0.len() > 0 {
-                                                for dog in pets.0 {
-                                                    client.execute(
-                                                        "INSERT INTO dogs (name, weight, breed, apt)
-                                                            VALUES ($1, $2, $3, $4)",
-                                                        &[&dog.name, &dog.weight, &dog.breed, &apt]
-                                                    ).unwrap();
-                                                }
-                                            }
-
-                                            if pets.1.len() > 0 {
-                                                for cat in pets.1 {
-                                                    client.execute(
-                                                        "INSERT INTO cats (name, weight, hair, apt)
-                                                            VALUES ($1, $2, $3, $4)",
-                                                        &[&cat.name, &cat.weight, &cat.hair, &apt]
-                                                    ).unwrap();
-                                                }
-                                            }
-
-                                            if pets.2.len() > 0 {
-                                                for bird in pets.2 {
-                                                    client.execute(
-                                                        "INSERT INTO birds (name, species, apt)
-                                                            VALUES ($1, $2, $3)",
-                                                        &[&bird.name, &bird.species, &apt]
-                                                    ).unwrap();
-                                                }
-                                            }
-
-                                            (OK_RESPONSE.to_string(), "Pets created".to_string())
-                                        },
-                                        Err(e) => (INTERNAL_SERVER_ERROR.to_string(), e)
-                                    }
-                                },
-                                Err(e) => (INTERNAL_SERVER_ERROR.to_string(), e.to_string())
-                            }
-                        },
-                        1 => (INTERNAL_SERVER_ERROR.to_string(), "Pets already registered to this apartment".to_string()),
-                        _ => (INTERNAL_SERVER_ERROR.to_string(), "Unexpected query value".to_string()) // Should never happen since apt numbers are unique
-                    }
-                },
-                Err(e) => (INTERNAL_SERVER_ERROR.to_string(), e.to_string())
-            },
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error: Bad apartment".to_string())
-    }
-}
-
-fn handle_get_request(request: &str, db_url: &str) -> (String, String) {
-    println!("Received GET request: {}", request);
-    match (get_apt(&request).parse::<i32>(), Client::connect(db_url, NoTls)) {
-        (Ok(apt), Ok(mut client)) => {
-            match client.query("SELECT * FROM apts WHERE apt = $1", &[&apt]) {
-                Ok(rows) => {
-                    match rows.len() {
-                        0 => (INTERNAL_SERVER_ERROR.to_string(), "Pets not registered to this apartment".to_string()),
-                        1 => {
-                            let mut pets: Vec<serde_json::value::Value> = Vec::new();
-                            match client.query("SELECT * FROM dogs WHERE apt = $1", &[&apt]) {
-                                Ok(rows) => {
-                                    for row in rows {
-                                        let dog = Dog {
-                                            name: row.get("name"),
-                                            weight: row.get("weight"),
-                                            breed: row.get("breed")
-                                        };
-                                        let mut pet = serde_json::Map::new();
-                                        pet.insert("animal".to_string(), serde_json::value::Value::String("Dog".to_string()));
-                                        pet.insert("name".to_string(), serde_json::value::Value::String(dog.name));
-                                        pet.insert("weight".to_string(), serde_json::value::Value::String(dog.weight.to_string()));
-                                        pet.insert("breed".to_string(), serde_json::value::Value::String(dog.breed));
-                                        pets.push(serde_json::value::Value::Object(pet));
-                                    }
-
-                                    match client.query("SELECT * FROM cats WHERE apt = $1", &[&apt]) {
-                                        Ok(rows) => {
-                                            for row in rows {
-                                                let cat = Cat {
-                                                    name: row.get("name"),
-                                                    weight: row.get("weight"),
-                                                    hair: row.get("hair")
-                                                };
-                                                let mut pet = serde_json::Map::new();
-                                                pet.insert("animal".to_string(), serde_json::value::Value::String("Cat".to_string()));
-                                                pet.insert("name".to_string(), serde_json::value::Value::String(cat.name));
-                                                pet.insert("weight".to_string(), serde_json::value::Value::String(cat.weight.to_string()));
-                                                let mut hair = "".to_string();
-                                                if cat.hair {
-                                                    hair.push_str("LongHaired");
-                                                } else {
-                                                    hair.push_str("ShortHaired");
-                                                }
-                                                pet.insert("hair".to_string(), serde_json::value::Value::String(hair));
-                                                pets.push(serde_json::value::Value::Object(pet));
-                                            };
-                                            
-                                            match client.query("SELECT * FROM birds WHERE apt = $1", &[&apt]) {
-                                                Ok(rows) => {
-                                                    for row in rows {
-                                                        let bird = Bird {
-                                                            name: row.get("name"),
-                                                            species: row.get("species")
-                                                        };
-                                                        let mut pet = serde_json::Map::new();
-                                                        pet.insert("animal".to_string(), serde_json::value::Value::String("Bird".to_string()));
-                                                        pet.insert("name".to_string(), serde_json::value::Value::String(bird.name));
-                                                        pet.insert("species".to_string(), serde_json::value::Value::String(bird.species));                                                        
-                                                        pets.push(serde_json::value::Value::Object(pet));
-                                                    };
-                                                    
-                                                    (OK_RESPONSE.to_string(), serde_json::to_string(&pets).unwrap())
-                                                },
-                                                Err(e) => (INTERNAL_SERVER_ERROR.to_string(), e.to_string())
-                                            }
-                                        },
-                                        Err(e) => (INTERNAL_SERVER_ERROR.to_string(), e.to_string())
-                                    }
-                                },
-                                Err(e) => (INTERNAL_SERVER_ERROR.to_string(), e.to_string())
-                            }
-                        },
-                        _ => (INTERNAL_SERVER_ERROR.to_string(), "Unexpected query value".to_string()) // Should never happen since apt numbers are unique
-                    }
-                },
-                Err(e) => (INTERNAL_SERVER_ERROR.to_string(), e.to_string())
-            }
-        },
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error".to_string()),
-    }
-}
-
-fn get_pets_vecs(a: serde_json::Value) -> Result<(Vec<Dog>, Vec<Cat>, Vec<Bird>), String> {
-    match a {
-        serde_json::Value::Array(a) => {
-            let mut dogs: Vec<Dog> = Vec::new();
-            let mut cats: Vec<Cat> = Vec::new();
-            let mut birds: Vec<Bird> = Vec::new();
-            for v in a {
-                match v {
-                    serde_json::Value::Object(pet) => {
-                        match pet.get("animal") {
-                            Some(animal) => {
-                                match animal.as_str() {
-                                    Some("Dog") => {
-                                        let name: &str;
-                                        let weight: i32;
-                                        let breed: &str;
-                                        match pet.get("name") {
-                                            Some(n) => {
-                                                match n.as_str() {
-                                                    Some(n) => name = n,
-                                                    None => return Err("Name field must be string".to_string())
-                                                }
-                                            },
-                                            None => return Err("Dogs require name field".to_string())
-                                        };
-                                        match pet.get("weight") {
-                                            Some(w) => {
-                                                match w.as_i64() {
-                                                    Some(i) => weight = i as i32,
-                                                    None => return Err("Weight field must be integer".to_string())
-                                                }
-                                            },
-                                            None => return Err("Dogs require weight field".to_string())
-                                        };
-                                        match pet.get("breed") {
-                                            Some(b) => { 
-                                                match b.as_str() {
-                                                    Some(b) => breed = b,
-                                                    None => return Err("Breed field must be string".to_string())
-                                                }
-                                            },
-                                            None => return Err("Dogs require breed field".to_string())
-                                        };
-                                        dogs.push(Dog {
-                                            name: name.to_string(),
-                                            weight: weight,
-                                            breed: breed.to_string(),
-                                        });
-                                    },
-                                    Some("Cat") => {
-                                        let name: &str;
-                                        let weight: i32;
-                                        let hair: bool;
-                                        match pet.get("name") {
-                                            Some(n) => {
-                                                match n.as_str() {
-                                                    Some(n) => name = n,
-                                                    None => return Err("Name field must be string".to_string())
-                                                }
-                                            },
-                                            None => return Err("Cats require name field".to_string())
-                                        };
-                                        match pet.get("weight") {
-                                            Some(w) => {
-                                                match w.as_i64() {
-                                                    Some(i) => weight = i as i32,
-                                                    None => return Err("Weight field must be integer".to_string())
-                                                }
-                                            },
-                                            None => return Err("Cats require weight field".to_string())
-                                        };
-                                        match pet.get("hair") {
-                                            Some(h) => { 
-                                                match h.as_str() {
-                                                    Some(h) => {
-                                                        match h {
-                                                            "LongHaired" => hair = true,
-                                                            "ShortHaired" => hair = false,
-                                                            _ => return Err("Hair field must be either LongHaired or ShortHaired".to_string())
-                                                        }
-                                                    },
-                                                    None => return Err("Hair field must be string".to_string())
-                                                }
-                                            },
-                                            None => return Err("Cats require hair field".to_string())
-                                        };
-                                        cats.push(Cat {
-                                            name: name.to_string(),
-                                            weight: weight,
-                                            hair: hair,
-                                        });
-                                    },
-                                    Some("Bird") => {
-                                        let name: &str;
-                                        let species: &str;
-                                        match pet.get("name") {
-                                            Some(n) => {
-                                                match n.as_str() {
-                                                    Some(n) => name = n,
-                                                    None => return Err("Name field must be string".to_string())
-                                                }
-                                            },
-                                            None => return Err("Birds require name field".to_string())
-                                        };
-                                        match pet.get("species") {
-                                            Some(s) => { 
-                                                match s.as_str() {
-                                                    Some(s) => species = s,
-                                                    None => return Err("Species field must be string".to_string())
-                                                }
-                                            },
-                                            None => return Err("Birds require species field".to_string())
-                                        };
-                                        birds.push(Bird {
-                                            name: name.to_string(),
-                                            species: species.to_string(),
-                                        });
-                                    },
-                                    _ => return Err("Invalid pet type".to_string())
-                                }
-                            },
-                            None => return Err("Each pet requires animal field".to_string())
-                        } 
-                    },
-                    _ => return Err("Object is not pet".to_string())
-                }
-            }
-
-            Ok((dogs, cats, birds))
-        },
-        _ => Err("Json must be array".to_string())
-    }
-}
\ No newline at end of file
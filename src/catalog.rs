@@ -0,0 +1,275 @@
+use crate::models::Pets;
+use std::fmt;
+
+// The breeds and bird species the API knows, edited in catalog.json and more can be added through a
+// catalog file
+const BUNDLED_CATALOG: &str = include_str!("../catalog.json");
+
+// Suggestions offered for a value the catalog doesn't know
+const MAX_SUGGESTIONS: usize = 3;
+
+// Canonical names for the free text fields of a species, each with the other names it goes by.
+// Values of a listed field are stored under their canonical name.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    lists: Vec<CatalogList>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogList {
+    pub animal: String,
+    pub field: String,
+    pub entries: Vec<CatalogEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogEntry {
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+// An entry as the catalog endpoint lists it
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CatalogMatch<'a> {
+    pub animal: &'a str,
+    pub field: &'a str,
+    #[serde(flatten)]
+    pub entry: &'a CatalogEntry,
+}
+
+#[derive(Debug)]
+pub enum CatalogError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatalogError::Io(e) => write!(f, "Could not read catalog file: {}", e),
+            CatalogError::Parse(e) => write!(f, "Invalid catalog file: {}", e),
+            CatalogError::Invalid(e) => write!(f, "Invalid catalog file: {}", e),
+        }
+    }
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Catalog { lists: serde_json::from_str(BUNDLED_CATALOG).unwrap() }
+    }
+}
+
+impl Catalog {
+    pub fn load(path: &str) -> Result<Catalog, CatalogError> {
+        Catalog::from_json(&std::fs::read_to_string(path).map_err(CatalogError::Io)?)
+    }
+
+    // Adds the entries to the bundled ones, an entry with a bundled name replaces it
+    pub fn from_json(json: &str) -> Result<Catalog, CatalogError> {
+        let mut catalog = Catalog::default();
+        for list in serde_json::from_str::<Vec<CatalogList>>(json).map_err(CatalogError::Parse)? {
+            match catalog.lists.iter_mut().find(|l| l.animal == list.animal && l.field == list.field) {
+                Some(existing) => {
+                    for entry in list.entries {
+                        match existing.entries.iter_mut().find(|e| e.name == entry.name) {
+                            Some(bundled) => *bundled = entry,
+                            None => existing.entries.push(entry)
+                        }
+                    }
+                },
+                None => catalog.lists.push(list)
+            }
+        }
+        for list in &catalog.lists {
+            list.check()?;
+        }
+        Ok(catalog)
+    }
+
+    // The canonical name of a name or alias, ignoring case, spacing and punctuation
+    pub fn canonical(&self, animal: Option<&str>, field: &str, value: &str) -> Option<&str> {
+        let value = key(value);
+        self.lists.iter()
+            .filter(|l| l.field == field && animal.is_none_or(|a| a == l.animal))
+            .find_map(|l| l.find(&value))
+            .map(|e| e.name.as_str())
+    }
+
+    // Stores every listed field under its canonical name, a value the catalog doesn't know is
+    // rejected with the closest names
+    pub fn normalize(&self, mut pets: Pets) -> Result<Pets, String> {
+        for pet in pets.0.iter_mut() {
+            for list in self.lists.iter().filter(|l| l.animal == pet.animal) {
+                let value = match pet.text(&list.field) {
+                    Some(value) => value,
+                    None => continue
+                };
+                match list.find(&key(value)) {
+                    Some(entry) => {
+                        pet.attributes.insert(list.field.clone(), entry.name.clone().into());
+                    },
+                    None => return Err(list.unknown(value))
+                }
+            }
+        }
+        Ok(pets)
+    }
+
+    // Entries whose name or an alias contains the query, or the closest ones when none does
+    pub fn search(&self, animal: Option<&str>, field: Option<&str>, query: Option<&str>) -> Vec<CatalogMatch<'_>> {
+        let lists = || self.lists.iter()
+            .filter(|l| animal.is_none_or(|a| a == l.animal) && field.is_none_or(|f| f == l.field));
+        let query = query.map(key).unwrap_or_default();
+        let mut matches: Vec<CatalogMatch> = lists()
+            .flat_map(|l| l.entries.iter().filter(|e| e.keys().any(|k| k.contains(&query))).map(move |e| l.matched(e)))
+            .collect();
+        if matches.is_empty() {
+            matches = lists().flat_map(|l| l.closest(&query).into_iter().map(move |e| l.matched(e))).collect();
+        }
+        matches.sort_by(|a, b| (a.animal, a.field, &a.entry.name).cmp(&(b.animal, b.field, &b.entry.name)));
+        matches
+    }
+}
+
+impl CatalogList {
+    fn check(&self) -> Result<(), CatalogError> {
+        let mut seen = std::collections::HashMap::new();
+        for entry in &self.entries {
+            if key(&entry.name).is_empty() {
+                return Err(CatalogError::Invalid(format!("{} {} names can't be empty", self.animal, self.field)));
+            }
+            for k in entry.keys() {
+                if let Some(other) = seen.insert(k, &entry.name).filter(|&other| *other != entry.name) {
+                    return Err(CatalogError::Invalid(format!("{} and {} share a name", other, entry.name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn find(&self, value: &str) -> Option<&CatalogEntry> {
+        self.entries.iter().find(|e| e.keys().any(|k| k == value))
+    }
+
+    fn matched<'a>(&'a self, entry: &'a CatalogEntry) -> CatalogMatch<'a> {
+        CatalogMatch { animal: &self.animal, field: &self.field, entry }
+    }
+
+    // Entries a few typos away from the value, or that it is the start of, closest first
+    fn closest(&self, value: &str) -> Vec<&CatalogEntry> {
+        let max = (value.chars().count() / 3).max(2);
+        let mut close: Vec<(usize, &CatalogEntry)> = self.entries.iter()
+            .filter_map(|e| e.keys()
+                .map(|k| match value.chars().count() >= 3 && k.starts_with(value) {
+                    true => 1,
+                    false => distance(value, &k)
+                })
+                .min()
+                .filter(|&d| d <= max)
+                .map(|d| (d, e)))
+            .collect();
+        close.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.name.cmp(&b.1.name)));
+        close.into_iter().take(MAX_SUGGESTIONS).map(|(_, e)| e).collect()
+    }
+
+    // "Unknown breed: Labradr. Did you mean Labrador Retriever?"
+    fn unknown(&self, value: &str) -> String {
+        let names: Vec<&str> = self.closest(&key(value)).into_iter().map(|e| e.name.as_str()).collect();
+        match names.as_slice() {
+            [] => format!("Unknown {}: {}", self.field, value),
+            [name] => format!("Unknown {}: {}. Did you mean {}?", self.field, value, name),
+            [rest @ .., last] => format!("Unknown {}: {}. Did you mean {} or {}?", self.field, value, rest.join(", "), last),
+        }
+    }
+}
+
+impl CatalogEntry {
+    fn keys(&self) -> impl Iterator<Item = String> + '_ {
+        std::iter::once(&self.name).chain(&self.aliases).map(|n| key(n))
+    }
+}
+
+// Lowercase words separated by single spaces, so "St. Bernard" and "st bernard" are the same
+fn key(value: &str) -> String {
+    value.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Edits between two strings, by character
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Pet;
+    use serde_json::json;
+
+    #[test]
+    fn values_are_stored_by_canonical_name() {
+        let catalog = Catalog::default();
+        let pets = catalog.normalize(Pets(vec![
+            Pet::new("Dog", "Sunny", json!({"weight": 70, "breed": "lab"})),
+            Pet::new("Dog", "Paris", json!({"weight": 60, "breed": "labrador-retriever"})),
+            Pet::new("Bird", "Polly", json!({"species": "budgie"})),
+            Pet::new("Cat", "Nova", json!({"weight": 13, "hair": "LongHaired"})),
+        ])).unwrap();
+        assert_eq!(pets.0[0].text("breed"), Some("Labrador Retriever"));
+        assert_eq!(pets.0[1].text("breed"), Some("Labrador Retriever"));
+        assert_eq!(pets.0[2].text("species"), Some("Budgerigar"));
+        assert_eq!(pets.0[3].text("hair"), Some("LongHaired"));
+
+        let unknown = |breed: &str| catalog.normalize(Pets(vec![Pet::new("Dog", "Rex", json!({"weight": 50, "breed": breed}))])).unwrap_err();
+        assert_eq!(unknown("Labradr"), "Unknown breed: Labradr. Did you mean Labrador Retriever?");
+        assert_eq!(unknown("Pugg"), "Unknown breed: Pugg. Did you mean Pug?");
+        assert_eq!(unknown("Wolf"), "Unknown breed: Wolf");
+    }
+
+    #[test]
+    fn catalog_is_searched_by_name_and_alias() {
+        let catalog = Catalog::default();
+        let names = |query: &str| catalog.search(Some("Dog"), None, Some(query)).iter().map(|m| m.entry.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names("retriever"), vec!["Golden Retriever", "Labrador Retriever"]);
+        assert_eq!(names("yorkie"), vec!["Yorkshire Terrier"]);
+        assert_eq!(names("rotweiler"), vec!["Rottweiler"]);
+        assert!(catalog.search(Some("Cat"), None, None).is_empty());
+        assert_eq!(catalog.canonical(None, "species", "Parakeet"), Some("Budgerigar"));
+    }
+
+    #[test]
+    fn catalog_files_extend_the_bundled_one() {
+        let catalog = Catalog::from_json(r#"[
+            {"animal": "Dog", "field": "breed", "entries": [{"name": "Pit Bull", "aliases": ["Bully"]}, {"name": "Xoloitzcuintli", "aliases": ["Xolo"]}]},
+            {"animal": "Rabbit", "field": "color", "entries": [{"name": "Grey", "aliases": ["Gray"]}]}
+        ]"#).unwrap();
+        assert_eq!(catalog.canonical(Some("Dog"), "breed", "xolo"), Some("Xoloitzcuintli"));
+        assert_eq!(catalog.canonical(Some("Dog"), "breed", "bully"), Some("Pit Bull"));
+        assert_eq!(catalog.canonical(Some("Dog"), "breed", "pitbull"), None);
+        assert_eq!(catalog.canonical(Some("Rabbit"), "color", "gray"), Some("Grey"));
+
+        assert!(matches!(
+            Catalog::from_json(r#"[{"animal": "Dog", "field": "breed", "entries": [{"name": "Labrador", "aliases": ["Lab"]}]}]"#),
+            Err(CatalogError::Invalid(_))
+        ));
+        assert!(matches!(Catalog::from_json(r#"[{"animal": "Dog", "entries": []}]"#), Err(CatalogError::Parse(_))));
+    }
+}
@@ -0,0 +1,266 @@
+use crate::models::{ApartmentPets, Apt, Pet, Pets, Status};
+use std::collections::HashMap;
+use std::fmt;
+
+// What pets are charged, an empty schedule charges nothing. Amounts are in cents, per-species
+// fees are keyed by animal, e.g. "Dog", and a species without fees is free.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeSchedule {
+    pub species: HashMap<String, SpeciesFees>,
+    pub per_pet_cap: FeeCap,
+    pub per_unit_cap: FeeCap,
+    // Pets of these breeds or bird species are never charged, nor are assistance animals or
+    // pets that aren't approved
+    pub exempt_breeds: Vec<String>,
+    pub exempt_species: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpeciesFees {
+    pub monthly: i64,
+    pub one_time: i64,
+    // The heaviest tier a pet weighs more pounds than replaces the base fees it sets
+    pub weight_tiers: Vec<WeightTier>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WeightTier {
+    pub over: i64,
+    pub monthly: Option<i64>,
+    pub one_time: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeCap {
+    pub monthly: Option<i64>,
+    pub one_time: Option<i64>,
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Charges {
+    pub monthly: i64,
+    pub one_time: i64,
+}
+
+impl Charges {
+    fn capped(self, cap: FeeCap) -> Charges {
+        Charges {
+            monthly: cap.monthly.map_or(self.monthly, |max| self.monthly.min(max)),
+            one_time: cap.one_time.map_or(self.one_time, |max| self.one_time.min(max)),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PetFee {
+    pub animal: String,
+    pub id: Option<i32>,
+    pub name: String,
+    #[serde(flatten)]
+    pub charges: Charges,
+    // The weight tier the fees came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub over: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exempt: Option<String>,
+}
+
+// One apartment's fees, the total is the subtotal of its pets after the per-unit cap
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Fees {
+    pub pets: Vec<PetFee>,
+    pub subtotal: Charges,
+    pub total: Charges,
+}
+
+// A line of the building roll-up
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ApartmentFees {
+    #[serde(flatten)]
+    pub apt: Apt,
+    pub pets: usize,
+    #[serde(flatten)]
+    pub charges: Charges,
+}
+
+#[derive(Debug)]
+pub enum FeeError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for FeeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeeError::Io(e) => write!(f, "Could not read fee schedule: {}", e),
+            FeeError::Parse(e) => write!(f, "Invalid fee schedule: {}", e),
+        }
+    }
+}
+
+impl FeeSchedule {
+    pub fn load(path: &str) -> Result<FeeSchedule, FeeError> {
+        FeeSchedule::from_json(&std::fs::read_to_string(path).map_err(FeeError::Io)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<FeeSchedule, FeeError> {
+        serde_json::from_str(json).map_err(FeeError::Parse)
+    }
+
+    pub fn compute(&self, pets: &Pets) -> Fees {
+        let pets: Vec<PetFee> = pets.0.iter().map(|pet| self.pet_fee(pet)).collect();
+        let subtotal = pets.iter().fold(Charges::default(), |sum, pet| Charges {
+            monthly: sum.monthly + pet.charges.monthly,
+            one_time: sum.one_time + pet.charges.one_time,
+        });
+        Fees { pets, subtotal, total: subtotal.capped(self.per_unit_cap) }
+    }
+
+    // Totals per apartment, in the order the apartments are given
+    pub fn rollup(&self, apartments: &[ApartmentPets]) -> Vec<ApartmentFees> {
+        apartments.iter().map(|apartment| ApartmentFees {
+            apt: apartment.apt.clone(),
+            pets: apartment.pets.0.len(),
+            charges: self.compute(&apartment.pets).total,
+        }).collect()
+    }
+
+    fn pet_fee(&self, pet: &Pet) -> PetFee {
+        let mut fee = PetFee {
+            animal: pet.animal.clone(),
+            id: pet.id,
+            name: pet.name.clone(),
+            charges: Charges::default(),
+            over: None,
+            exempt: self.exemption(pet),
+        };
+        let fees = match self.species.get(&pet.animal) {
+            Some(fees) if fee.exempt.is_none() => fees,
+            _ => return fee
+        };
+
+        let tier = pet.number("weight").and_then(|weight| fees.weight_tiers.iter()
+            .filter(|tier| weight > tier.over as f64)
+            .max_by_key(|tier| tier.over));
+        let charges = match tier {
+            Some(tier) => Charges {
+                monthly: tier.monthly.unwrap_or(fees.monthly),
+                one_time: tier.one_time.unwrap_or(fees.one_time),
+            },
+            None => Charges { monthly: fees.monthly, one_time: fees.one_time }
+        };
+        fee.charges = charges.capped(self.per_pet_cap);
+        fee.over = tier.map(|tier| tier.over);
+        fee
+    }
+
+    fn exemption(&self, pet: &Pet) -> Option<String> {
+        if pet.review.status != Status::Approved {
+            return Some(format!("{} is {}", pet.name, pet.review.status));
+        }
+        if pet.is_assistance() {
+            return Some(format!("{} is an assistance animal", pet.name));
+        }
+        if let Some(breed) = pet.text("breed").filter(|b| is_listed(&self.exempt_breeds, b)) {
+            return Some(format!("{} is an exempt breed", breed));
+        }
+        pet.text("species").filter(|s| is_listed(&self.exempt_species, s))
+            .map(|species| format!("{} is an exempt species", species))
+    }
+}
+
+// Billing export, one row per apartment
+pub fn to_csv(fees: &[ApartmentFees]) -> String {
+    let mut csv = String::from("building,unit,pets,monthly,one_time\n");
+    for apartment in fees {
+        // Building codes and units are letters, digits and dashes so nothing needs quoting
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            apartment.apt.building, apartment.apt.unit, apartment.pets, apartment.charges.monthly, apartment.charges.one_time
+        ));
+    }
+    csv
+}
+
+fn is_listed(list: &[String], value: &str) -> bool {
+    list.iter().any(|item| item.eq_ignore_ascii_case(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Assistance, AssistanceKind, Verification};
+    use serde_json::json;
+
+    const FEES: &str = r#"{
+        "species": {
+            "Dog": {"monthly": 2500, "one_time": 30000, "weight_tiers": [{"over": 40, "monthly": 3500}, {"over": 80, "monthly": 5000, "one_time": 40000}]},
+            "Cat": {"monthly": 1500, "one_time": 20000}
+        },
+        "per_pet_cap": {"one_time": 35000},
+        "per_unit_cap": {"monthly": 6000},
+        "exempt_breeds": ["Greyhound"],
+        "exempt_species": ["Finch"]
+    }"#;
+
+    fn approved(pets: Vec<Pet>) -> Pets {
+        Pets(pets.into_iter().map(|mut pet| {
+            pet.review.status = Status::Approved;
+            pet
+        }).collect())
+    }
+
+    fn dog(name: &str, weight: i64, breed: &str) -> Pet {
+        Pet::new("Dog", name, json!({"weight": weight, "breed": breed}))
+    }
+
+    fn charges(fees: &Fees) -> Vec<(i64, i64, Option<i64>)> {
+        fees.pets.iter().map(|p| (p.charges.monthly, p.charges.one_time, p.over)).collect()
+    }
+
+    #[test]
+    fn charges_by_species_and_weight() {
+        let schedule = FeeSchedule::from_json(FEES).unwrap();
+        let fees = schedule.compute(&approved(vec![
+            dog("Sunny", 30, "Labrador"),
+            dog("Bruno", 95, "Mastiff"),
+            Pet::new("Cat", "Nova", json!({"weight": 13, "hair": "LongHaired"})),
+            Pet::new("Bird", "Polly", json!({"species": "Parrot"})),
+        ]));
+
+        assert_eq!(charges(&fees), vec![(2500, 30000, None), (5000, 35000, Some(80)), (1500, 20000, None), (0, 0, None)]);
+        assert_eq!(fees.subtotal, Charges { monthly: 9000, one_time: 85000 });
+        assert_eq!(fees.total, Charges { monthly: 6000, one_time: 85000 });
+    }
+
+    #[test]
+    fn exempt_pets_are_free() {
+        let schedule = FeeSchedule::from_json(FEES).unwrap();
+        let fees = schedule.compute(&Pets(vec![dog("Sunny", 30, "Labrador")]));
+        assert_eq!(fees.pets[0].exempt.as_deref(), Some("Sunny is pending"));
+
+        let fees = schedule.compute(&approved(vec![
+            dog("Dash", 60, "greyhound"),
+            Pet::new("Bird", "Kiwi", json!({"species": "Finch"})),
+        ]));
+        assert_eq!(charges(&fees), vec![(0, 0, None), (0, 0, None)]);
+        assert_eq!(fees.pets[0].exempt.as_deref(), Some("greyhound is an exempt breed"));
+        assert_eq!(fees.total, Charges::default());
+
+        let service = Assistance { kind: AssistanceKind::Service, verification: Verification::Verified, documentation: None };
+        let fees = schedule.compute(&approved(vec![Pet { assistance: Some(service), ..dog("Sunny", 30, "Labrador") }]));
+        assert_eq!(fees.pets[0].exempt.as_deref(), Some("Sunny is an assistance animal"));
+        assert_eq!(fees.total, Charges::default());
+        assert_eq!(FeeSchedule::default().compute(&approved(vec![dog("Sunny", 30, "Labrador")])).total, Charges::default());
+    }
+
+    #[test]
+    fn rejects_invalid_schedule() {
+        assert!(matches!(FeeSchedule::from_json(r#"{"per_apt_cap": {}}"#), Err(FeeError::Parse(_))));
+        assert!(matches!(FeeSchedule::from_json(r#"{"species": {"Dog": {"weight_tiers": [{"monthly": 1}]}}}"#), Err(FeeError::Parse(_))));
+    }
+}
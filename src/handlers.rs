@@ -0,0 +1,2199 @@
+use crate::models::{
+    get_pets_vecs, is_valid_name, parse_microchip, Actor, ApartmentPets, Apt, AuditEntry, Building, Incident, License, NameMatch, Page, PetId, PetRef, PetSearch,
+    Pets, Resolution, Review, ReviewDecision, Status, Tenant, Transfer, Vaccination, Verification, Weight, WeightUnit, DEFAULT_BUILDING
+};
+use chrono::{DateTime, Days, Local, NaiveDate, SubsecRound, Utc};
+use serde::de::DeserializeOwned;
+use crate::catalog::Catalog;
+use crate::fees::{self, FeeSchedule};
+use crate::photos::{self, PhotoError, PhotoStore};
+use crate::policy::Policy;
+use crate::species::{capitalize, FieldType, SpeciesRegistry};
+use crate::stats;
+use crate::store::{PetStore, StoreError};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+// Response constants
+pub const OK_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n";
+pub const CSV_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/csv\r\nContent-Disposition: attachment; filename=\"fees.csv\"\r\n\r\n";
+pub const BAD_REQUEST: &str = "HTTP/1.1 400 BAD REQUEST\r\n\r\n";
+pub const FORBIDDEN: &str = "HTTP/1.1 403 FORBIDDEN\r\n\r\n";
+pub const NOT_FOUND: &str = "HTTP/1.1 404 NOT FOUND\r\n\r\n";
+pub const CONFLICT: &str = "HTTP/1.1 409 CONFLICT\r\n\r\n";
+pub const PAYLOAD_TOO_LARGE: &str = "HTTP/1.1 413 PAYLOAD TOO LARGE\r\n\r\n";
+pub const UNSUPPORTED_MEDIA_TYPE: &str = "HTTP/1.1 415 UNSUPPORTED MEDIA TYPE\r\n\r\n";
+pub const UNPROCESSABLE_ENTITY: &str = "HTTP/1.1 422 UNPROCESSABLE ENTITY\r\nContent-Type: application/json\r\n\r\n";
+pub const INTERNAL_SERVER_ERROR: &str = "HTTP/1.1 500 INTERNAL SERVER ERROR\r\n\r\n";
+
+// Everything a request handler needs, shared by the worker threads
+pub struct App {
+    pub store: Box<dyn PetStore>,
+    pub policy: Policy,
+    pub fees: FeeSchedule,
+    pub species: SpeciesRegistry,
+    pub catalog: Catalog,
+    pub photos: PhotoStore,
+    // Bearer token of property managers, without one nobody is a manager
+    pub manager_token: Option<String>,
+}
+
+// Room for the multipart framing around a photo
+const MULTIPART_OVERHEAD: usize = 64 * 1024;
+// Longest request head read before giving up on finding its end
+const MAX_HEAD: usize = 64 * 1024;
+
+// Reads the request head and as many body bytes as Content-Length announces. None when the body
+// is larger than a photo upload may be, without reading it.
+pub fn read_request(stream: &mut impl Read, max_photo: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let size = stream.read(&mut buffer)?;
+        request.extend_from_slice(&buffer[0..size]);
+        match photos::find(&request, b"\r\n\r\n", 0) {
+            Some(end) => {
+                let length = get_header(&String::from_utf8_lossy(&request[0..end]), "content-length")
+                    .and_then(|length| length.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if length > max_photo + MULTIPART_OVERHEAD {
+                    return Ok(None);
+                }
+                while request.len() < end + 4 + length {
+                    let size = stream.read(&mut buffer)?;
+                    if size == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[0..size]);
+                }
+                return Ok(Some(request));
+            },
+            None if size == 0 || request.len() > MAX_HEAD => return Ok(Some(request)),
+            None => {}
+        }
+    }
+}
+
+// Photos are sent and returned as bytes, every other request is handled as text
+pub fn handle_binary_request(request: &[u8], app: &App) -> (String, Vec<u8>) {
+    let (head, body) = match photos::find(request, b"\r\n\r\n", 0) {
+        Some(end) => (String::from_utf8_lossy(&request[0..end]), &request[end + 4..]),
+        None => (String::from_utf8_lossy(request), &[][..])
+    };
+    let method = head.split_whitespace().next().unwrap_or_default();
+    let segments = get_segments(get_path(&head));
+
+    match (method, segments.as_slice()) {
+        ("POST", ["pets", _, _, _, "photo"]) => {
+            let (status, content) = handle_photo_upload_request(&head, body, app);
+            (status, content.into_bytes())
+        },
+        ("GET", ["pets", _, _, _, "photo"]) => handle_photo_request(&head, app, false),
+        ("GET", ["pets", _, _, _, "photo", "thumbnail"]) => handle_photo_request(&head, app, true),
+        _ => {
+            let (status, content) = handle_request(&String::from_utf8_lossy(request), app);
+            (status, content.into_bytes())
+        }
+    }
+}
+
+// Routes a raw request, returns the status line with headers and the response body
+pub fn handle_request(request: &str, app: &App) -> (String, String) {
+    let method = request.split_whitespace().next().unwrap_or_default();
+    let segments = get_segments(get_path(request));
+    if let Err(e) = get_weight_unit(&get_query(request)) {
+        return (BAD_REQUEST.to_string(), e);
+    }
+
+    match (method, segments.as_slice()) {
+        ("GET", ["pets"]) => handle_list_request(request, app),
+        ("GET", ["search", "pets"]) => handle_search_request(request, app),
+        ("GET", ["stats"]) => handle_stats_request(request, app),
+        ("GET", ["catalog"]) => handle_catalog_request(request, app),
+        ("GET", ["fees"]) => handle_fee_rollup_request(request, app),
+        ("POST", ["pets", _]) => handle_post_request(request, app),
+        ("GET", ["pets", _]) => handle_get_request(request, app),
+        ("PUT", ["pets", _]) => handle_put_request(request, app),
+        ("DELETE", ["pets", _]) => handle_delete_request(request, app),
+        ("GET", ["pets", _, "deleted"]) => handle_list_deleted_request(request, app),
+        ("POST", ["pets", _, "restore"]) => handle_restore_request(request, app),
+        ("POST", ["pets", _, "transfer"]) => handle_transfer_request(request, app),
+        ("GET", ["pets", _, "fees"]) => handle_fees_request(request, app),
+        ("GET", ["pets", _, "history"]) => handle_history_request(request, app),
+        ("POST", ["policy", "check"]) => handle_policy_check_request(request, app),
+        ("GET", ["pets", _, _, _, "records"]) => handle_records_request(request, app),
+        ("POST", ["pets", _, _, _, "vaccinations"]) => handle_add_vaccination_request(request, app),
+        ("POST", ["pets", _, _, _, "licenses"]) => handle_add_license_request(request, app),
+        ("POST", ["pets", _, _, _, "review"]) => handle_review_request(request, app),
+        ("DELETE", ["pets", _, _, _]) => handle_delete_pet_request(request, app),
+        ("POST", ["pets", _, _, _, "restore"]) => handle_restore_pet_request(request, app),
+        ("GET", ["reviews"]) => handle_list_reviews_request(request, app),
+        ("POST", ["pets", _, "incidents"]) => handle_add_incident_request(request, app),
+        ("GET", ["pets", _, "incidents"]) => handle_list_incidents_request(request, app),
+        ("POST", ["pets", _, _, _, "incidents"]) => handle_add_incident_request(request, app),
+        ("GET", ["pets", _, _, _, "incidents"]) => handle_list_incidents_request(request, app),
+        ("POST", ["incidents", _, "resolve"]) => handle_resolve_incident_request(request, app),
+        ("GET", ["records", "expiring"]) => handle_expiring_request(request, app),
+        ("POST", ["tenants"]) => handle_create_tenant_request(request, app),
+        ("GET", ["tenants"]) => handle_list_tenants_request(request, app),
+        ("GET", ["tenants", _]) => handle_get_tenant_request(request, app),
+        ("PUT", ["tenants", _]) => handle_update_tenant_request(request, app),
+        ("DELETE", ["tenants", _]) => handle_delete_tenant_request(request, app),
+        ("POST", ["buildings"]) => handle_create_building_request(request, app),
+        ("GET", ["buildings"]) => handle_list_buildings_request(request, app),
+        ("GET", ["buildings", _, "units"]) => handle_list_request(request, app),
+        ("GET", ["lookup", "microchip", _]) => handle_microchip_request(request, app),
+        _ => (NOT_FOUND.to_string(), "404 NOT FOUND".to_string()),
+    }
+}
+
+// Request target without the query string. Requests are logged by their path alone, headers carry
+// the manager token and bodies the assistance documentation.
+fn get_path(request: &str) -> &str {
+    request.split_whitespace().nth(1).unwrap_or_default().split('?').next().unwrap_or_default()
+}
+
+// Path segments to route on. /buildings/{building}/units/{unit}/pets/... is routed like
+// /pets/{apt}/..., get_apt tells the two apart.
+fn get_segments(path: &str) -> Vec<&str> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match segments.as_slice() {
+        ["buildings", _, "units", unit, "pets", rest @ ..] => [&["pets", *unit][..], rest].concat(),
+        _ => segments
+    }
+}
+
+// The unit of /buildings/{building}/units/{unit}/..., or the numbered apartment of /pets/{apt}/...
+// in the default building
+fn get_apt(request: &str) -> Option<Apt> {
+    let segments: Vec<&str> = get_path(request).split('/').filter(|s| !s.is_empty()).collect();
+    match segments.as_slice() {
+        ["buildings", building, "units", unit, ..] if is_valid_name(building) && is_valid_name(unit) => Some(Apt::new(building, unit)),
+        ["pets", apt, ..] => apt.parse::<i32>().ok().map(Apt::numbered),
+        _ => None
+    }
+}
+
+// The pet in /pets/{apt}/{animal}/{id}/...
+fn get_pet_ref(request: &str) -> Result<PetRef, String> {
+    let segments = get_segments(get_path(request));
+    match (get_apt(request), segments[3].parse::<i32>()) {
+        (Some(apt), Ok(id)) => Ok(PetRef { apt, animal: segments[2].to_string(), id }),
+        (None, _) => Err("Bad apartment".to_string()),
+        (_, Err(_)) => Err("Bad pet id".to_string())
+    }
+}
+
+// Value of a request header, names are case-insensitive
+fn get_header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+fn get_tenant_id(request: &str) -> Result<i32, String> {
+    get_path(request).split('/').nth(2).unwrap_or_default().parse::<i32>().map_err(|_| "Bad tenant id".to_string())
+}
+
+// Chips are looked up as they are stored, so "985-112-003-456-789" finds 985112003456789
+fn get_microchip(request: &str) -> Result<String, String> {
+    let microchip = decode_component(get_path(request).split('/').nth(3).unwrap_or_default());
+    parse_microchip(&microchip).ok_or_else(|| "Bad microchip id".to_string())
+}
+
+fn get_query(request: &str) -> HashMap<String, String> {
+    request.split_whitespace().nth(1).unwrap_or_default()
+        .split_once('?').map(|(_, query)| query).unwrap_or_default()
+        .split('&')
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, value)| (decode_component(key), decode_component(value)))
+        .collect()
+}
+
+// Undoes form encoding: '+' for spaces and %XX escapes
+fn decode_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match bytes.get(i + 1..i + 3).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit)) {
+                Some(hex) => {
+                    decoded.push(u8::from_str_radix(std::str::from_utf8(hex).unwrap(), 16).unwrap());
+                    i += 2;
+                },
+                None => decoded.push(b'%')
+            },
+            b => decoded.push(b)
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn get_page(query: &HashMap<String, String>) -> Result<Page, String> {
+    let mut page = Page::default();
+    if let Some(limit) = query.get("limit") {
+        match limit.parse::<i64>() {
+            Ok(limit) if (1..=100).contains(&limit) => page.limit = limit,
+            _ => return Err("Limit must be an integer between 1 and 100".to_string())
+        }
+    }
+    if let Some(offset) = query.get("offset") {
+        match offset.parse::<i64>() {
+            Ok(offset) if offset >= 0 => page.offset = offset,
+            _ => return Err("Offset must be a non-negative integer".to_string())
+        }
+    }
+    match query.get("order").map(String::as_str) {
+        None | Some("asc") => {},
+        Some("desc") => page.descending = true,
+        _ => return Err("Order must be either asc or desc".to_string())
+    }
+    Ok(page)
+}
+
+// Besides the fixed filters, any field declared by a species can be searched: text and enum
+// fields by value, integer and weight fields with min_<field> and max_<field>. Weight bounds are
+// in the weight_unit the results are shown in, and catalog aliases find the pets stored under
+// the canonical name.
+fn get_search(query: &HashMap<String, String>, species: &SpeciesRegistry, catalog: &Catalog) -> Result<PetSearch, String> {
+    let mut search = PetSearch::default();
+    let unit = get_weight_unit(query)?;
+    for (key, value) in query {
+        match key.as_str() {
+            "weight_unit" => {},
+            "animal" => match species.get(value) {
+                Some(_) => search.animal = Some(value.clone()),
+                None => return Err("Invalid pet type".to_string())
+            },
+            "name" => search.name = Some(value.clone()),
+            "match" => match value.as_str() {
+                "prefix" => search.name_match = NameMatch::Prefix,
+                "substring" => search.name_match = NameMatch::Substring,
+                _ => return Err("Match must be either prefix or substring".to_string())
+            },
+            "building" => search.building = Some(value.clone()),
+            "from_apt" | "to_apt" => search = get_apt_range(search, key, value)?,
+            "status" => search.status = Some(get_status(value)?),
+            _ => {
+                let bound = key.strip_prefix("min_").map(|f| (f, true))
+                    .or_else(|| key.strip_prefix("max_").map(|f| (f, false)));
+                match (species.field(key), bound.and_then(|(f, min)| species.field(f).map(|field| (field, min)))) {
+                    (Some(field), _) if field.kind == FieldType::String => {
+                        let value = catalog.canonical(query.get("animal").map(String::as_str), &field.name, value).unwrap_or(value);
+                        search.equals.push((field.name.clone(), value.to_string()))
+                    },
+                    (Some(field), _) if field.kind == FieldType::Enum => match field.values.iter().find(|v| v.eq_ignore_ascii_case(value)) {
+                        Some(v) => search.equals.push((field.name.clone(), v.clone())),
+                        None => return Err(format!("{} must be {}", capitalize(key), field.allowed_values()))
+                    },
+                    (_, Some((field, min))) if field.kind == FieldType::Integer => match value.parse::<i64>() {
+                        Ok(bound) if min => search.min.push((field.name.clone(), bound as f64)),
+                        Ok(bound) => search.max.push((field.name.clone(), bound as f64)),
+                        Err(_) => return Err(format!("{} must be an integer", key))
+                    },
+                    (_, Some((field, min))) if field.kind == FieldType::Weight => match value.parse::<f64>() {
+                        Ok(bound) if bound.is_finite() => {
+                            let bound = Weight { value: bound, unit }.to_pounds();
+                            match min {
+                                true => search.min.push((field.name.clone(), bound)),
+                                false => search.max.push((field.name.clone(), bound))
+                            }
+                        },
+                        _ => return Err(format!("{} must be a number", key))
+                    },
+                    _ => return Err(format!("Unknown search filter: {}", key))
+                }
+            }
+        }
+    }
+    Ok(search)
+}
+
+// Pounds unless the caller asks for kilograms
+fn get_weight_unit(query: &HashMap<String, String>) -> Result<WeightUnit, String> {
+    match query.get("weight_unit") {
+        Some(unit) => WeightUnit::parse(unit).ok_or_else(|| "Weight unit must be either lb or kg".to_string()),
+        None => Ok(WeightUnit::Lb)
+    }
+}
+
+fn get_status(status: &str) -> Result<Status, String> {
+    Status::parse(status).ok_or_else(|| "Status must be pending, approved, rejected or removed".to_string())
+}
+
+// A timestamp, or a date meaning the end of that day in UTC
+fn get_as_of(as_of: &str) -> Result<DateTime<Utc>, String> {
+    match (DateTime::parse_from_rfc3339(as_of), as_of.parse::<NaiveDate>()) {
+        (Ok(at), _) => Ok(at.with_timezone(&Utc)),
+        (_, Ok(date)) => Ok(date.and_hms_micro_opt(23, 59, 59, 999_999).unwrap().and_utc()),
+        _ => Err("As of must be a date or an RFC 3339 timestamp".to_string())
+    }
+}
+
+fn get_apt_range(search: PetSearch, key: &str, value: &str) -> Result<PetSearch, String> {
+    match value.parse::<i32>() {
+        Ok(apt) if key == "from_apt" => Ok(PetSearch { from_apt: Some(apt), ..search }),
+        Ok(apt) => Ok(PetSearch { to_apt: Some(apt), ..search }),
+        Err(_) => Err(format!("{} must be an integer", key))
+    }
+}
+
+// The roll-up takes an optional building and the export format, JSON unless csv is asked for
+fn get_fee_rollup_query(query: &HashMap<String, String>) -> Result<(PetSearch, bool), String> {
+    let mut search = PetSearch::default();
+    let mut csv = false;
+    for (key, value) in query {
+        match key.as_str() {
+            "building" => search.building = Some(value.clone()),
+            "format" => match value.as_str() {
+                "json" => csv = false,
+                "csv" => csv = true,
+                _ => return Err("Format must be either json or csv".to_string())
+            },
+            _ => return Err(format!("Unknown fees parameter: {}", key))
+        }
+    }
+    Ok((search, csv))
+}
+
+// Only the building, the apartment range filters and the length of the top lists apply to stats
+fn get_stats_query(query: &HashMap<String, String>) -> Result<(PetSearch, usize), String> {
+    let mut search = PetSearch::default();
+    let mut top = 5;
+    for (key, value) in query {
+        match key.as_str() {
+            "building" => search.building = Some(value.clone()),
+            "from_apt" | "to_apt" => search = get_apt_range(search, key, value)?,
+            "top" => match value.parse::<usize>() {
+                Ok(n) if (1..=100).contains(&n) => top = n,
+                _ => return Err("Top must be an integer between 1 and 100".to_string())
+            },
+            _ => return Err(format!("Unknown stats parameter: {}", key))
+        }
+    }
+    Ok((search, top))
+}
+
+// Link header pointing at the neighbouring pages, if there are any
+fn page_links(path: &str, page: &Page, has_next: bool) -> Option<String> {
+    let link = |offset: i64, rel: &str| format!(
+        "<{}?limit={}&offset={}&order={}>; rel=\"{}\"",
+        path, page.limit, offset, if page.descending { "desc" } else { "asc" }, rel
+    );
+    let mut links = Vec::new();
+    if page.offset > 0 {
+        links.push(link((page.offset - page.limit).max(0), "prev"));
+    }
+    if has_next {
+        links.push(link(page.offset + page.limit, "next"));
+    }
+    match links.is_empty() {
+        true => None,
+        false => Some(links.join(", "))
+    }
+}
+
+fn get_request_body(request: &str) -> Result<serde_json::Value, serde_json::Error> {
+    serde_json::from_str(request.split("\r\n\r\n").last().unwrap_or_default())
+}
+
+fn store_error_response(e: StoreError) -> (String, String) {
+    match e {
+        StoreError::NotFound(e) => (NOT_FOUND.to_string(), e),
+        StoreError::Conflict(e) => (CONFLICT.to_string(), e),
+        StoreError::NotAllowed(e) => (UNPROCESSABLE_ENTITY.to_string(), e),
+        StoreError::Backend(e) => (INTERNAL_SERVER_ERROR.to_string(), e),
+    }
+}
+
+// Policy violations are returned as a JSON list naming the rule each pet broke
+fn check_policy(policy: &Policy, pets: &Pets) -> Result<(), (String, String)> {
+    let violations = policy.evaluate(pets);
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err((UNPROCESSABLE_ENTITY.to_string(), serde_json::to_string(&violations).unwrap()))
+    }
+}
+
+fn is_manager(request: &str, app: &App) -> bool {
+    let head = request.split("\r\n\r\n").next().unwrap_or_default();
+    match (&app.manager_token, get_header(head, "Authorization").and_then(|a| a.strip_prefix("Bearer "))) {
+        (Some(token), Some(bearer)) => token == bearer,
+        _ => false
+    }
+}
+
+// Pets as the requester may see them, assistance documentation is for managers only and weights
+// are in the weight_unit asked for
+fn shown(pets: &Pets, request: &str, app: &App) -> Pets {
+    let pets = app.species.in_unit(pets, get_weight_unit(&get_query(request)).unwrap_or(WeightUnit::Lb));
+    match is_manager(request, app) {
+        true => pets,
+        false => pets.redacted()
+    }
+}
+
+// Who the store records a change under. Managers are known by their token, anyone else is
+// anonymous and the X-Actor header they send is only kept as their claim.
+fn get_actor(request: &str, app: &App) -> Actor {
+    if is_manager(request, app) {
+        return Actor { name: "manager".to_string(), manager: true, claimed: None };
+    }
+    let head = request.split("\r\n\r\n").next().unwrap_or_default();
+    let claimed = get_header(head, "X-Actor").filter(|a| !a.is_empty()).map(str::to_string);
+    Actor { name: "anonymous".to_string(), manager: false, claimed }
+}
+
+// The apartment's pets as the audit log has them at the given time
+fn pets_as_of(apt: &Apt, as_of: DateTime<Utc>, app: &App) -> Result<Pets, StoreError> {
+    let history = app.store.history(apt, Some(as_of))?;
+    AuditEntry::replay(&history).ok_or_else(|| StoreError::NotFound("Pets not registered to this apartment at that time".to_string()))
+}
+
+// A designation sent back without the documentation tenants aren't shown keeps what is stored
+// for the pet of that name. Tenants can't verify their own assistance animals, the stored
+// verification is kept while the documentation is unchanged and anything new starts out pending.
+fn resolve_assistance(mut pets: Pets, stored: &Pets, manager: bool) -> Result<Pets, (String, String)> {
+    for pet in &mut pets.0 {
+        let assistance = match pet.assistance.as_mut() {
+            Some(assistance) => assistance,
+            None => continue
+        };
+        let previous = stored.of(&pet.animal)
+            .find(|p| p.name == pet.name)
+            .and_then(|p| p.assistance.as_ref())
+            .filter(|previous| previous.kind == assistance.kind);
+        if assistance.documentation.is_none() {
+            assistance.documentation = previous.and_then(|previous| previous.documentation.clone());
+        }
+        if !manager {
+            assistance.verification = match previous {
+                Some(previous) if previous.documentation == assistance.documentation => previous.verification,
+                _ => Verification::Pending
+            };
+        }
+        if assistance.documentation.is_none() {
+            return Err((BAD_REQUEST.to_string(), format!("{} needs documentation to be an assistance animal", pet.name)));
+        }
+    }
+    Ok(pets)
+}
+
+// Pets sent again unchanged keep their review, anything new or changed waits for a manager
+fn submit_pets(mut pets: Pets, stored: &Pets) -> Pets {
+    let now = Utc::now().trunc_subsecs(0);
+    for pet in &mut pets.0 {
+        pet.review = match stored.of(&pet.animal).find(|p| p.name == pet.name && p.attributes == pet.attributes) {
+            Some(previous) => previous.review.clone(),
+            None => Review { status: Status::Pending, submitted_at: Some(now), ..Review::default() }
+        };
+    }
+    pets
+}
+
+// A pet's owner has to live in the apartment today
+fn check_owners(apt: &Apt, pets: &Pets, app: &App) -> Result<(), (String, String)> {
+    if pets.0.iter().all(|p| p.owner.is_none()) {
+        return Ok(());
+    }
+    let today = Local::now().date_naive();
+    let tenants = app.store.list_tenants(Some(apt)).map_err(store_error_response)?;
+    for owner in pets.0.iter().filter_map(|p| p.owner) {
+        if !tenants.iter().any(|t| t.id == Some(owner) && t.lives_in(apt, today)) {
+            return Err((BAD_REQUEST.to_string(), format!("Owner {} is not a current tenant of apartment {}", owner, apt)));
+        }
+    }
+    Ok(())
+}
+
+// Replaces each pet's owner id with the tenant's name and contact details
+fn embed_owners(pets: &Pets, app: &App) -> Result<serde_json::Value, StoreError> {
+    let mut json = pets.to_json();
+    let mut owners: HashMap<i32, serde_json::Value> = HashMap::new();
+    for (pet, value) in pets.0.iter().zip(json.as_array_mut().unwrap()) {
+        if let Some(id) = pet.owner {
+            let owner = match owners.entry(id) {
+                Entry::Occupied(owner) => owner.into_mut(),
+                Entry::Vacant(owner) => {
+                    let tenant = app.store.get_tenant(id)?;
+                    owner.insert(serde_json::json!({"id": id, "name": tenant.name, "email": tenant.email, "phone": tenant.phone}))
+                }
+            };
+            value["owner"] = owner.clone();
+        }
+    }
+    Ok(json)
+}
+
+// Pets with open incidents get their count, the others are left without one
+fn add_open_incidents(json: &mut serde_json::Value, pets: &Pets, apt: &Apt, app: &App) -> Result<(), StoreError> {
+    let counts = app.store.open_incident_counts(apt)?;
+    for (pet, value) in pets.0.iter().zip(json.as_array_mut().unwrap()) {
+        let id = PetId { animal: pet.animal.clone(), id: pet.id.unwrap_or_default() };
+        if let Some(count) = counts.get(&id) {
+            value["open_incidents"] = serde_json::Value::from(*count);
+        }
+    }
+    Ok(())
+}
+
+// Every apartment for /pets, the units of one building for /buildings/{building}/units
+fn handle_list_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    let (path, building) = match get_segments(get_path(request)).as_slice() {
+        ["buildings", building, "units"] => (format!("/buildings/{}/units", building), Some(*building)),
+        _ => ("/pets".to_string(), None)
+    };
+    match get_page(&get_query(request)) {
+        Ok(page) => {
+            // One extra row tells whether there is a next page
+            match app.store.list_apartments(&Page { limit: page.limit + 1, ..page }, building) {
+                Ok(mut apartments) => {
+                    let has_next = apartments.len() as i64 > page.limit;
+                    apartments.truncate(page.limit as usize);
+                    let status_line = match page_links(&path, &page, has_next) {
+                        Some(links) => format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nLink: {}\r\n\r\n", links
+                        ),
+                        None => OK_RESPONSE.to_string()
+                    };
+                    (status_line, serde_json::to_string(&apartments).unwrap())
+                },
+                Err(e) => store_error_response(e)
+            }
+        },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_search_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match get_search(&get_query(request), &app.species, &app.catalog) {
+        Ok(search) =>
+            match app.store.search_pets(&search) {
+                Ok(apartments) => {
+                    let apartments: Vec<ApartmentPets> = apartments.into_iter()
+                        .map(|a| ApartmentPets { pets: shown(&a.pets, request, app), ..a })
+                        .collect();
+                    (OK_RESPONSE.to_string(), ApartmentPets::to_json(&apartments).to_string())
+                },
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+// Catalog entries for an animal and field, those matching q by name or alias
+fn handle_catalog_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    let query = get_query(request);
+    if let Some(key) = query.keys().find(|k| !matches!(k.as_str(), "animal" | "field" | "q")) {
+        return (BAD_REQUEST.to_string(), format!("Unknown catalog parameter: {}", key));
+    }
+    let get = |key: &str| query.get(key).map(String::as_str);
+    let entries = app.catalog.search(get("animal"), get("field"), get("q"));
+    (OK_RESPONSE.to_string(), serde_json::to_string(&entries).unwrap())
+}
+
+fn handle_stats_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match get_stats_query(&get_query(request)) {
+        Ok((search, top)) =>
+            match app.store.search_pets(&search) {
+                Ok(apartments) => (OK_RESPONSE.to_string(), serde_json::to_string(&stats::compute(&apartments, &app.species, &app.catalog, top)).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_post_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    match get_apt(request) {
+        Some(apt) =>
+            match get_request_body(request) {
+                Ok(body) => {
+                    let pets = get_pets_vecs(body, &app.species, &app.catalog)
+                        .map_err(|e| (BAD_REQUEST.to_string(), e))
+                        .and_then(|pets| resolve_assistance(pets, &Pets::default(), is_manager(request, app)))
+                        .map(|pets| submit_pets(pets, &Pets::default()));
+                    match pets {
+                        Ok(pets) => {
+                            match check_policy(&app.policy, &pets).and_then(|_| check_owners(&apt, &pets, app)) {
+                                Ok(()) =>
+                                    match app.store.register_apartment(&apt, &pets, &get_actor(request, app)) {
+                                        Ok(pets) => (OK_RESPONSE.to_string(), shown(&pets, request, app).to_json().to_string()),
+                                        Err(e) => store_error_response(e)
+                                    },
+                                Err(violations) => violations
+                            }
+                        },
+                        Err(e) => e
+                    }
+                },
+                Err(e) => (BAD_REQUEST.to_string(), e.to_string())
+            },
+        None => (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    }
+}
+
+// ?embed=owner includes each owner's name and contact details, ?status= only pets in that
+// state of their review and ?as_of= the pets as they were at that time
+fn handle_get_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    let query = get_query(request);
+    let embed_owner = match query.get("embed").map(String::as_str) {
+        None => false,
+        Some("owner") => true,
+        Some(_) => return (BAD_REQUEST.to_string(), "Embed must be owner".to_string())
+    };
+    let status = match query.get("status").map(|s| get_status(s)).transpose() {
+        Ok(status) => status,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    let as_of = match query.get("as_of").map(|a| get_as_of(a)).transpose() {
+        Ok(as_of) => as_of,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    match get_apt(request) {
+        Some(apt) => {
+            let pets = match as_of {
+                Some(as_of) => pets_as_of(&apt, as_of, app),
+                None => app.store.list_pets(&apt)
+            };
+            let pets = pets.map(|pets| with_status(pets, status)).and_then(|pets| {
+                let mut json = match embed_owner {
+                    true => embed_owners(&shown(&pets, request, app), app)?,
+                    false => shown(&pets, request, app).to_json()
+                };
+                // Incidents are only counted for the pets as they are now
+                if as_of.is_none() {
+                    add_open_incidents(&mut json, &pets, &apt, app)?;
+                }
+                Ok(json)
+            });
+            match pets {
+                Ok(pets) => (OK_RESPONSE.to_string(), pets.to_string()),
+                Err(e) => store_error_response(e)
+            }
+        },
+        None => (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    }
+}
+
+fn handle_put_request(request: &str, app: &App) -> (String, String) {
+    println!("Received PUT request: {}", get_path(request));
+    match get_apt(request) {
+        Some(apt) => {
+            // Reviews and verifications are carried over from these, the store makes sure they
+            // are still the ones replaced
+            let replaced = match app.store.list_pets(&apt) {
+                Ok(pets) => pets,
+                Err(StoreError::NotFound(_)) => Pets::default(),
+                Err(e) => return store_error_response(e)
+            };
+            match get_request_body(request) {
+                Ok(body) => {
+                    let pets = get_pets_vecs(body, &app.species, &app.catalog)
+                        .map_err(|e| (BAD_REQUEST.to_string(), e))
+                        .and_then(|pets| resolve_assistance(pets, &replaced, is_manager(request, app)))
+                        .map(|pets| submit_pets(pets, &replaced));
+                    match pets {
+                        Ok(pets) => {
+                            match check_policy(&app.policy, &pets).and_then(|_| check_owners(&apt, &pets, app)) {
+                                Ok(()) =>
+                                    match app.store.update_pets(&apt, &pets, &replaced, &get_actor(request, app)) {
+                                        Ok(pets) => (OK_RESPONSE.to_string(), shown(&pets, request, app).to_json().to_string()),
+                                        Err(e) => store_error_response(e)
+                                    },
+                                Err(violations) => violations
+                            }
+                        },
+                        Err(e) => e
+                    }
+                },
+                Err(e) => (BAD_REQUEST.to_string(), e.to_string())
+            }
+        },
+        None => (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    }
+}
+
+fn handle_delete_request(request: &str, app: &App) -> (String, String) {
+    println!("Received DELETE request: {}", get_path(request));
+    match get_apt(request) {
+        Some(apt) =>
+            match app.store.delete_apartment(&apt, &get_actor(request, app)) {
+                Ok(()) => (OK_RESPONSE.to_string(), "Pets deleted".to_string()),
+                Err(e) => store_error_response(e)
+            },
+        None => (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    }
+}
+
+// Moves pets to another apartment of the property, keeping their ids, records, photos and review.
+// The destination is registered if it isn't yet and has to allow the pets like a PUT would, the
+// store checks its policy against the pets there while the transfer holds its lock.
+fn handle_transfer_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    let from = match get_apt(request) {
+        Some(apt) => apt,
+        None => return (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    };
+    let transfer = match get_record::<Transfer>(request).and_then(|t| t.validate().map(|_| t)) {
+        Ok(transfer) => transfer,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    let to = transfer.to();
+    if to == from {
+        return (BAD_REQUEST.to_string(), "Pets can't be transferred to the apartment they are in".to_string());
+    }
+    let source = match app.store.list_pets(&from) {
+        Ok(pets) => pets,
+        Err(e) => return store_error_response(e)
+    };
+    let moving = Pets(source.0.iter().filter(|p| transfer.includes(p)).cloned().collect());
+    if moving.0.len() < transfer.pets.len() {
+        return (NOT_FOUND.to_string(), "Pet not found".to_string());
+    }
+    if let Err(e) = check_owners(&to, &moving, app) {
+        return e;
+    }
+    let allowed = |pets: &Pets| check_policy(&app.policy, pets).map_err(|(_, violations)| violations);
+    match app.store.transfer_pets(&from, &to, &transfer.pets, &allowed, &get_actor(request, app)) {
+        Ok(pets) => (OK_RESPONSE.to_string(), shown(&pets, request, app).to_json().to_string()),
+        Err(e) => store_error_response(e)
+    }
+}
+
+// Deleted pets are kept for the retention period, their photos and records with them
+fn handle_delete_pet_request(request: &str, app: &App) -> (String, String) {
+    println!("Received DELETE request: {}", get_path(request));
+    match get_pet_ref(request) {
+        Ok(pet) =>
+            match app.store.delete_pet(&pet, &get_actor(request, app)) {
+                Ok(_) => (OK_RESPONSE.to_string(), "Pet deleted".to_string()),
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+// Pets of the unit waiting to be purged, most recently deleted first
+fn handle_list_deleted_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match get_apt(request) {
+        Some(apt) =>
+            match app.store.list_deleted(&apt) {
+                Ok(pets) => (OK_RESPONSE.to_string(), shown(&pets, request, app).to_json().to_string()),
+                Err(e) => store_error_response(e)
+            },
+        None => (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    }
+}
+
+// Brings back the most recently deleted apartment of the unit with the pets it had
+fn handle_restore_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    match get_apt(request) {
+        Some(apt) =>
+            match app.store.restore_apartment(&apt, &get_actor(request, app)) {
+                Ok(pets) => (OK_RESPONSE.to_string(), shown(&pets, request, app).to_json().to_string()),
+                Err(e) => store_error_response(e)
+            },
+        None => (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    }
+}
+
+// A deleted pet comes back to the unit's registered apartment if the lease allows it next to
+// the pets there now, which the store checks while the apartment is locked
+fn handle_restore_pet_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    let pet = match get_pet_ref(request) {
+        Ok(pet) => pet,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    let allowed = |pets: &Pets| check_policy(&app.policy, pets).map_err(|(_, violations)| violations);
+    match app.store.restore_pet(&pet, &allowed, &get_actor(request, app)) {
+        Ok(restored) => (OK_RESPONSE.to_string(), shown(&Pets(vec![restored]), request, app).to_json()[0].to_string()),
+        Err(e) => store_error_response(e)
+    }
+}
+
+// Permanently removes what was deleted more than the retention period ago, photos included.
+// Returns how many pets were purged.
+pub fn purge_deleted(app: &App, retention: Days) -> Result<usize, StoreError> {
+    let before = Utc::now().checked_sub_days(retention).unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let purged = app.store.purge_deleted(before)?;
+    remove_photos(&purged, app);
+    Ok(purged.0.len())
+}
+
+fn handle_fees_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match get_apt(request) {
+        Some(apt) =>
+            match app.store.list_pets(&apt) {
+                Ok(pets) => (OK_RESPONSE.to_string(), serde_json::to_string(&app.fees.compute(&pets)).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        None => (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    }
+}
+
+// Every change to the apartment oldest first, or those up to ?as_of=. Weights are in the
+// weight_unit asked for, like GET shows them.
+fn handle_history_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    let as_of = match get_query(request).get("as_of").map(|a| get_as_of(a)).transpose() {
+        Ok(as_of) => as_of,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    match get_apt(request) {
+        Some(apt) =>
+            match app.store.history(&apt, as_of) {
+                Ok(history) if history.is_empty() => (NOT_FOUND.to_string(), "No history for this apartment".to_string()),
+                Ok(history) => {
+                    let unit = get_weight_unit(&get_query(request)).unwrap_or(WeightUnit::Lb);
+                    let history: Vec<AuditEntry> = history.into_iter().map(|entry| AuditEntry {
+                        before: entry.before.map(|pets| app.species.in_unit(&pets, unit)),
+                        after: entry.after.map(|pets| app.species.in_unit(&pets, unit)),
+                        ..entry
+                    }).collect();
+                    (OK_RESPONSE.to_string(), AuditEntry::to_json(&history, is_manager(request, app)).to_string())
+                },
+                Err(e) => store_error_response(e)
+            },
+        None => (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+    }
+}
+
+fn handle_fee_rollup_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match get_fee_rollup_query(&get_query(request)) {
+        Ok((search, csv)) =>
+            match app.store.search_pets(&search) {
+                Ok(apartments) => {
+                    let rollup = app.fees.rollup(&apartments);
+                    match csv {
+                        true => (CSV_RESPONSE.to_string(), fees::to_csv(&rollup)),
+                        false => (OK_RESPONSE.to_string(), serde_json::to_string(&rollup).unwrap())
+                    }
+                },
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn with_status(pets: Pets, status: Option<Status>) -> Pets {
+    match status {
+        Some(status) => Pets(pets.0.into_iter().filter(|p| p.review.status == status).collect()),
+        None => pets
+    }
+}
+
+// Managers approve or reject pending pets and remove approved ones
+fn handle_review_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    if !is_manager(request, app) {
+        return (FORBIDDEN.to_string(), "Only managers may review pets".to_string());
+    }
+    let pet = match get_pet_ref(request) {
+        Ok(pet) => pet,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    let decision = match get_record::<ReviewDecision>(request).and_then(|d| d.validate().map(|_| d)) {
+        Ok(decision) => decision,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    let stored = match app.store.list_pets(&pet.apt) {
+        Ok(pets) => pets.0.into_iter().find(|p| p.animal == pet.animal && p.id == Some(pet.id)),
+        Err(StoreError::NotFound(_)) => None,
+        Err(e) => return store_error_response(e)
+    };
+    match stored {
+        Some(stored) if stored.review.status.can_become(decision.status) => {
+            let review = Review {
+                status: decision.status,
+                submitted_at: stored.review.submitted_at,
+                reviewed_by: Some(decision.reviewer.trim().to_string()),
+                reviewed_at: Some(Utc::now().trunc_subsecs(0)),
+                reason: decision.reason.filter(|r| !r.trim().is_empty()),
+            };
+            match app.store.review_pet(&pet, &review, &get_actor(request, app)) {
+                Ok(reviewed) => (OK_RESPONSE.to_string(), shown(&Pets(vec![reviewed]), request, app).to_json()[0].to_string()),
+                Err(e) => store_error_response(e)
+            }
+        },
+        Some(stored) =>
+            (CONFLICT.to_string(), format!("A {} pet can't be {}", stored.review.status, decision.status)),
+        None => (NOT_FOUND.to_string(), "Pet not found".to_string())
+    }
+}
+
+// Pets waiting for a manager across the buildings, or those in another state with ?status=
+fn handle_list_reviews_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    if !is_manager(request, app) {
+        return (FORBIDDEN.to_string(), "Only managers may review pets".to_string());
+    }
+    let mut search = PetSearch { status: Some(Status::Pending), ..PetSearch::default() };
+    for (key, value) in get_query(request) {
+        match key.as_str() {
+            "building" => search.building = Some(value),
+            "status" => match get_status(&value) {
+                Ok(status) => search.status = Some(status),
+                Err(e) => return (BAD_REQUEST.to_string(), e)
+            },
+            "weight_unit" => {},
+            _ => return (BAD_REQUEST.to_string(), format!("Unknown reviews parameter: {}", key))
+        }
+    }
+    match app.store.search_pets(&search) {
+        Ok(apartments) => {
+            let apartments: Vec<ApartmentPets> = apartments.into_iter()
+                .map(|a| ApartmentPets { pets: shown(&a.pets, request, app), ..a })
+                .collect();
+            (OK_RESPONSE.to_string(), ApartmentPets::to_json(&apartments).to_string())
+        },
+        Err(e) => store_error_response(e)
+    }
+}
+
+// Body of a record request, with the serde error as the message when it doesn't fit
+fn get_record<T: DeserializeOwned>(request: &str) -> Result<T, String> {
+    match get_request_body(request) {
+        Ok(body) => serde_json::from_value(body).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string())
+    }
+}
+
+// The apartment of /pets/{apt}/incidents, and the pet too for /pets/{apt}/{animal}/{id}/incidents
+fn get_incident_subject(request: &str) -> Result<(Apt, Option<PetId>), String> {
+    match get_segments(get_path(request)).len() {
+        3 => get_apt(request).map(|apt| (apt, None)).ok_or_else(|| "Bad apartment".to_string()),
+        _ => get_pet_ref(request).map(|pet| (pet.apt, Some(PetId { animal: pet.animal, id: pet.id })))
+    }
+}
+
+fn handle_add_incident_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    let (apt, pet) = match get_incident_subject(request) {
+        Ok(subject) => subject,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    match get_record::<Incident>(request).and_then(|i| i.validate().map(|_| i)) {
+        Ok(incident) =>
+            match app.store.add_incident(&Incident { apt, pet, ..incident }) {
+                Ok(incident) => (OK_RESPONSE.to_string(), Incident::to_json(&[incident])[0].to_string()),
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+// Incidents reported in the apartment, or about the pet wherever it lived, oldest first. Only
+// open or resolved ones with ?status=.
+fn handle_list_incidents_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    let (apt, pet) = match get_incident_subject(request) {
+        Ok(subject) => subject,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    let open = match get_query(request).get("status").map(String::as_str) {
+        None => None,
+        Some("open") => Some(true),
+        Some("resolved") => Some(false),
+        Some(_) => return (BAD_REQUEST.to_string(), "Status must be open or resolved".to_string())
+    };
+    let found = match &pet {
+        Some(pet) => find_pet(&PetRef { apt: apt.clone(), animal: pet.animal.clone(), id: pet.id }, app),
+        None => app.store.list_pets(&apt).map(|_| ()).map_err(store_error_response)
+    };
+    if let Err(e) = found {
+        return e;
+    }
+    match app.store.list_incidents(&apt, pet.as_ref()) {
+        Ok(incidents) => {
+            let incidents: Vec<Incident> = incidents.into_iter().filter(|i| open.is_none_or(|open| i.is_open() == open)).collect();
+            (OK_RESPONSE.to_string(), Incident::to_json(&incidents).to_string())
+        },
+        Err(e) => store_error_response(e)
+    }
+}
+
+fn handle_resolve_incident_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    let id = match get_segments(get_path(request))[1].parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return (BAD_REQUEST.to_string(), "Bad incident id".to_string())
+    };
+    let resolution = match get_record::<Resolution>(request) {
+        Ok(resolution) => resolution,
+        Err(e) => return (BAD_REQUEST.to_string(), e)
+    };
+    let incident = match app.store.get_incident(id) {
+        Ok(incident) => incident,
+        Err(e) => return store_error_response(e)
+    };
+    if let Err(e) = resolution.validate(&incident) {
+        return (BAD_REQUEST.to_string(), e);
+    }
+    let resolved_on = resolution.resolved_on.unwrap_or_else(|| Local::now().date_naive());
+    match app.store.resolve_incident(id, resolution.resolution.trim(), resolved_on) {
+        Ok(incident) => (OK_RESPONSE.to_string(), Incident::to_json(&[incident])[0].to_string()),
+        Err(e) => store_error_response(e)
+    }
+}
+
+fn handle_records_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match get_pet_ref(request) {
+        Ok(pet) =>
+            match app.store.list_records(&pet) {
+                Ok(records) => (OK_RESPONSE.to_string(), serde_json::to_string(&records).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_add_vaccination_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    match (get_pet_ref(request), get_record::<Vaccination>(request).and_then(|v| v.validate().map(|_| v))) {
+        (Ok(pet), Ok(vaccination)) =>
+            match app.store.add_vaccination(&pet, &Vaccination { id: None, ..vaccination }) {
+                Ok(vaccination) => (OK_RESPONSE.to_string(), serde_json::to_string(&vaccination).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        (Err(e), _) | (_, Err(e)) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_add_license_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    match (get_pet_ref(request), get_record::<License>(request).and_then(|l| l.validate().map(|_| l))) {
+        (Ok(pet), Ok(license)) =>
+            match app.store.add_license(&pet, &License { id: None, ..license }) {
+                Ok(license) => (OK_RESPONSE.to_string(), serde_json::to_string(&license).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        (Err(e), _) | (_, Err(e)) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+// Records that have expired, or will within `days` (default 30), each flagged with whether
+// it already has
+fn handle_expiring_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    let days = match get_query(request).get("days") {
+        Some(days) => match days.parse::<u64>() {
+            Ok(days) if days <= 3650 => days,
+            _ => return (BAD_REQUEST.to_string(), "Days must be an integer between 0 and 3650".to_string())
+        },
+        None => 30
+    };
+    let today = Local::now().date_naive();
+    match app.store.expiring_records(today + Days::new(days)) {
+        Ok(records) => {
+            let records: Vec<serde_json::Value> = records.into_iter().map(|record| {
+                let expired = record.expires < today;
+                let mut record = serde_json::to_value(record).unwrap();
+                record["expired"] = serde_json::Value::Bool(expired);
+                record
+            }).collect();
+            (OK_RESPONSE.to_string(), serde_json::Value::Array(records).to_string())
+        },
+        Err(e) => store_error_response(e)
+    }
+}
+
+fn get_tenant(request: &str) -> Result<Tenant, String> {
+    get_record::<Tenant>(request).and_then(|tenant| tenant.validate().map(|_| tenant))
+}
+
+fn handle_create_tenant_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    match get_tenant(request) {
+        Ok(tenant) =>
+            match app.store.create_tenant(&tenant) {
+                Ok(tenant) => (OK_RESPONSE.to_string(), serde_json::to_string(&tenant).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+// ?apt=N, or ?building=B&unit=U, only lists tenants who have lived in the apartment
+fn handle_list_tenants_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    let query = get_query(request);
+    let apt = match (query.get("apt"), query.get("building"), query.get("unit")) {
+        (Some(apt), None, None) => match apt.parse::<i32>() {
+            Ok(apt) => Some(Apt::numbered(apt)),
+            Err(_) => return (BAD_REQUEST.to_string(), "Bad apartment".to_string())
+        },
+        (None, building, Some(unit)) => Some(Apt::new(building.map(String::as_str).unwrap_or(DEFAULT_BUILDING), unit)),
+        (None, None, None) => None,
+        _ => return (BAD_REQUEST.to_string(), "Filter by either apt or unit, with an optional building".to_string())
+    };
+    match app.store.list_tenants(apt.as_ref()) {
+        Ok(tenants) => (OK_RESPONSE.to_string(), serde_json::to_string(&tenants).unwrap()),
+        Err(e) => store_error_response(e)
+    }
+}
+
+fn handle_get_tenant_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match get_tenant_id(request) {
+        Ok(id) =>
+            match app.store.get_tenant(id) {
+                Ok(tenant) => (OK_RESPONSE.to_string(), serde_json::to_string(&tenant).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_microchip_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match get_microchip(request) {
+        Ok(microchip) =>
+            match app.store.find_microchip(&microchip) {
+                Ok((apt, pet)) => {
+                    let found = ApartmentPets { apt, pets: shown(&Pets(vec![pet]), request, app) };
+                    (OK_RESPONSE.to_string(), ApartmentPets::to_json(&[found])[0].to_string())
+                },
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_update_tenant_request(request: &str, app: &App) -> (String, String) {
+    println!("Received PUT request: {}", get_path(request));
+    match (get_tenant_id(request), get_tenant(request)) {
+        (Ok(id), Ok(tenant)) =>
+            match app.store.update_tenant(id, &tenant) {
+                Ok(tenant) => (OK_RESPONSE.to_string(), serde_json::to_string(&tenant).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        (Err(e), _) | (_, Err(e)) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_delete_tenant_request(request: &str, app: &App) -> (String, String) {
+    println!("Received DELETE request: {}", get_path(request));
+    match get_tenant_id(request) {
+        Ok(id) =>
+            match app.store.delete_tenant(id) {
+                Ok(()) => (OK_RESPONSE.to_string(), "Tenant deleted".to_string()),
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_create_building_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    match get_record::<Building>(request).and_then(|b| b.validate().map(|_| b)) {
+        Ok(building) =>
+            match app.store.create_building(&building) {
+                Ok(building) => (OK_RESPONSE.to_string(), serde_json::to_string(&building).unwrap()),
+                Err(e) => store_error_response(e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_list_buildings_request(request: &str, app: &App) -> (String, String) {
+    println!("Received GET request: {}", get_path(request));
+    match app.store.list_buildings() {
+        Ok(buildings) => (OK_RESPONSE.to_string(), serde_json::to_string(&buildings).unwrap()),
+        Err(e) => store_error_response(e)
+    }
+}
+
+// Photos can only be stored for pets that exist in the apartment
+fn find_pet(pet: &PetRef, app: &App) -> Result<(), (String, String)> {
+    match app.store.list_pets(&pet.apt) {
+        Ok(pets) if pets.of(&pet.animal).any(|p| p.id == Some(pet.id)) => Ok(()),
+        Ok(_) | Err(StoreError::NotFound(_)) => Err((NOT_FOUND.to_string(), "Pet not found".to_string())),
+        Err(e) => Err(store_error_response(e))
+    }
+}
+
+// Photo files of purged pets, failing to remove one only leaves an unreachable file
+fn remove_photos(pets: &Pets, app: &App) {
+    for pet in &pets.0 {
+        if let Err(e) = app.photos.remove(&pet.animal, pet.id.unwrap_or_default()) {
+            println!("Error: {}", e);
+        }
+    }
+}
+
+fn photo_error_response(e: PhotoError) -> (String, String) {
+    match e {
+        PhotoError::TooLarge(_) => (PAYLOAD_TOO_LARGE.to_string(), e.to_string()),
+        PhotoError::Unsupported => (UNSUPPORTED_MEDIA_TYPE.to_string(), e.to_string()),
+        PhotoError::Invalid(_) => (BAD_REQUEST.to_string(), e.to_string()),
+        PhotoError::Io(_) => (INTERNAL_SERVER_ERROR.to_string(), e.to_string()),
+    }
+}
+
+fn handle_photo_upload_request(head: &str, body: &[u8], app: &App) -> (String, String) {
+    println!("Received POST request: {} ({} bytes)", get_path(head), body.len());
+    let content_type = get_header(head, "content-type").unwrap_or_default();
+    match (get_pet_ref(head), photos::multipart_field(content_type, body, "photo")) {
+        (Ok(pet), Ok(photo)) =>
+            match find_pet(&pet, app) {
+                Ok(()) =>
+                    match app.photos.save(&pet, photo) {
+                        Ok(photo) => (OK_RESPONSE.to_string(), serde_json::to_string(&photo).unwrap()),
+                        Err(e) => photo_error_response(e)
+                    },
+                Err(e) => e
+            },
+        (Err(e), _) | (_, Err(e)) => (BAD_REQUEST.to_string(), e)
+    }
+}
+
+fn handle_photo_request(head: &str, app: &App, thumbnail: bool) -> (String, Vec<u8>) {
+    println!("Received GET request: {}", get_path(head));
+    let result = get_pet_ref(head).map_err(|e| (BAD_REQUEST.to_string(), e))
+        .and_then(|pet| find_pet(&pet, app).map(|_| pet))
+        .and_then(|pet| app.photos.open(&pet, thumbnail).map_err(photo_error_response));
+    match result {
+        Ok(Some((content_type, data))) => (
+            format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n", content_type, data.len()),
+            data
+        ),
+        Ok(None) => (NOT_FOUND.to_string(), b"Photo not found".to_vec()),
+        Err((status, content)) => (status, content.into_bytes())
+    }
+}
+
+// Evaluates a payload against the policy without saving anything
+fn handle_policy_check_request(request: &str, app: &App) -> (String, String) {
+    println!("Received POST request: {}", get_path(request));
+    match get_request_body(request) {
+        Ok(body) =>
+            match get_pets_vecs(body, &app.species, &app.catalog) {
+                Ok(pets) => {
+                    let violations = app.policy.evaluate(&pets);
+                    (OK_RESPONSE.to_string(), serde_json::json!({
+                        "allowed": violations.is_empty(),
+                        "violations": violations,
+                    }).to_string())
+                },
+                Err(e) => (BAD_REQUEST.to_string(), e)
+            },
+        Err(e) => (BAD_REQUEST.to_string(), e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn app() -> App {
+        App {
+            store: Box::new(MemoryStore::new()),
+            policy: Policy::default(),
+            fees: FeeSchedule::default(),
+            species: SpeciesRegistry::default(),
+            catalog: Catalog::default(),
+            photos: PhotoStore::new(photo_dir(), 64 * 1024),
+            manager_token: Some(MANAGER_TOKEN.to_string()),
+        }
+    }
+
+    // Each test gets its own photo directory, it is only created on the first upload
+    fn photo_dir() -> std::path::PathBuf {
+        static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        std::env::temp_dir().join(format!("apt-pets-photos-{}-{}", std::process::id(), n))
+    }
+
+    const PETS: &str = r#"[
+        {"animal": "Dog", "name": "Sunny", "weight": 70, "breed": "Labrador"},
+        {"animal": "Cat", "name": "Nova", "weight": 13, "hair": "LongHaired"},
+        {"animal": "Bird", "name": "Polly", "species": "Parrot"}
+    ]"#;
+
+    fn request(method: &str, path: &str, body: &str) -> String {
+        format!("{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\r\n{}", method, path, body)
+    }
+
+    const MANAGER_TOKEN: &str = "manager-secret";
+
+    fn approve(app: &App, pet: &str) -> (String, String) {
+        handle_request(&manager_request("POST", &format!("{}/review", pet), r#"{"status": "approved", "reviewer": "Jo"}"#), app)
+    }
+
+    fn manager_request(method: &str, path: &str, body: &str) -> String {
+        request(method, path, body).replacen("\r\n\r\n", &format!("\r\nAuthorization: Bearer {}\r\n\r\n", MANAGER_TOKEN), 1)
+    }
+
+    #[test]
+    fn post_then_get_returns_pets() {
+        let app = app();
+        let (status, created) = handle_request(&request("POST", "/pets/123", PETS), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let created: serde_json::Value = serde_json::from_str(&created).unwrap();
+        assert_eq!(created[0]["id"], 1);
+        assert_eq!(created[2]["id"], 3);
+
+        let (status, content) = handle_request(&request("GET", "/pets/123", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets, created);
+        assert_eq!(pets[0]["name"], "Sunny");
+        assert_eq!(pets[0]["weight"], serde_json::json!({"value": 70.0, "unit": "lb"}));
+        assert_eq!(pets[1]["hair"], "LongHaired");
+        assert_eq!(pets[2]["species"], "Parrot");
+    }
+
+    #[test]
+    fn weights_are_shown_in_the_unit_asked_for() {
+        let app = app();
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let (_, content) = handle_request(&request("GET", "/pets/123?weight_unit=kg", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets[0]["weight"], serde_json::json!({"value": 31.75, "unit": "kg"}));
+        assert_eq!(pets[2].get("weight"), None);
+
+        let (_, content) = handle_request(&request("GET", "/search/pets?min_weight=30&weight_unit=kg", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets.as_array().unwrap().iter().map(|p| p["name"].as_str().unwrap()).collect::<Vec<_>>(), vec!["Sunny"]);
+
+        let (status, content) = handle_request(&request("GET", "/pets/123?weight_unit=stone", ""), &app);
+        assert_eq!((status.as_str(), content.as_str()), (BAD_REQUEST, "Weight unit must be either lb or kg"));
+    }
+
+    #[test]
+    fn post_twice_conflicts() {
+        let app = app();
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let (status, content) = handle_request(&request("POST", "/pets/123", PETS), &app);
+        assert_eq!(status, CONFLICT);
+        assert_eq!(content, "Pets already registered to this apartment");
+    }
+
+    #[test]
+    fn invalid_pets_are_not_registered() {
+        let app = app();
+        let (status, content) = handle_request(&request("POST", "/pets/123", r#"[{"animal": "Dog", "name": "Rex"}]"#), &app);
+        assert_eq!(status, BAD_REQUEST);
+        assert_eq!(content, "Dogs require weight field");
+
+        let (status, _) = handle_request(&request("GET", "/pets/123", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+    }
+
+    #[test]
+    fn malformed_json_is_a_bad_request() {
+        let app = app();
+        handle_request(&request("POST", "/pets/124", PETS), &app);
+        for (method, path) in [("POST", "/pets/123"), ("PUT", "/pets/124"), ("POST", "/policy/check")] {
+            let (status, content) = handle_request(&request(method, path, "{not json"), &app);
+            assert_eq!(status, BAD_REQUEST, "{} {}", method, path);
+            assert!(content.starts_with("key must be a string"), "{} {}: {}", method, path, content);
+        }
+    }
+
+    #[test]
+    fn bad_apartment_is_rejected() {
+        let app = app();
+        for method in ["GET", "POST", "PUT", "DELETE"] {
+            let (status, content) = handle_request(&request(method, "/pets/abc", PETS), &app);
+            assert_eq!((status.as_str(), content.as_str()), (BAD_REQUEST, "Bad apartment"), "{}", method);
+        }
+    }
+
+    #[test]
+    fn put_replaces_pets() {
+        let app = app();
+        let (status, _) = handle_request(&request("PUT", "/pets/123", PETS), &app);
+        assert_eq!(status, NOT_FOUND);
+
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let (status, _) = handle_request(
+            &request("PUT", "/pets/123", r#"[{"animal": "Bird", "name": "Kiwi", "species": "Finch"}]"#),
+            &app
+        );
+        assert_eq!(status, OK_RESPONSE);
+
+        let (_, content) = handle_request(&request("GET", "/pets/123", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets.as_array().unwrap().len(), 1);
+        assert_eq!((&pets[0]["id"], &pets[0]["name"], &pets[0]["species"]), (&serde_json::json!(4), &serde_json::json!("Kiwi"), &serde_json::json!("Finch")));
+    }
+
+    #[test]
+    fn delete_removes_apartment() {
+        let app = app();
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let (status, _) = handle_request(&request("DELETE", "/pets/123", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+
+        let (status, _) = handle_request(&request("GET", "/pets/123", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (status, _) = handle_request(&request("DELETE", "/pets/123", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+    }
+
+    #[test]
+    fn list_paginates_apartments() {
+        let app = app();
+        for apt in [101, 102, 103] {
+            handle_request(&request("POST", &format!("/pets/{}", apt), PETS), &app);
+        }
+
+        let (status, content) = handle_request(&request("GET", "/pets", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let apartments: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(apartments.as_array().unwrap().len(), 3);
+        assert_eq!(apartments[0], serde_json::json!({"building": "default", "unit": "101", "pets": {"Dog": 1, "Cat": 1, "Bird": 1}}));
+
+        let (status, content) = handle_request(&request("GET", "/pets?limit=1&offset=1&order=desc", ""), &app);
+        assert!(status.contains(
+            "Link: </pets?limit=1&offset=0&order=desc>; rel=\"prev\", </pets?limit=1&offset=2&order=desc>; rel=\"next\"\r\n"
+        ));
+        let apartments: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(apartments[0]["unit"], "102");
+
+        let (status, _) = handle_request(&request("GET", "/pets/?limit=2&offset=2", ""), &app);
+        assert!(status.contains("Link: </pets?limit=2&offset=0&order=asc>; rel=\"prev\"\r\n"));
+
+        let (status, content) = handle_request(&request("GET", "/pets?limit=0", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+        assert_eq!(content, "Limit must be an integer between 1 and 100");
+    }
+
+    #[test]
+    fn search_filters_pets_across_apartments() {
+        let app = app();
+        handle_request(&request("POST", "/pets/101", PETS), &app);
+        handle_request(&request("POST", "/pets/102", r#"[
+            {"animal": "Dog", "name": "Bruno", "weight": 55, "breed": "Pit Bull"},
+            {"animal": "Bird", "name": "Pollyanna", "species": "parrot"}
+        ]"#), &app);
+
+        let (status, content) = handle_request(&request("GET", "/search/pets?breed=pit+bull", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets.as_array().unwrap().len(), 1);
+        assert_eq!(pets[0]["unit"], "102");
+        assert_eq!(pets[0]["name"], "Bruno");
+
+        let (_, content) = handle_request(&request("GET", "/search/pets?name=POLLY&match=prefix&species=Parrot", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets.as_array().unwrap().iter().map(|p| p["unit"].as_str().unwrap()).collect::<Vec<_>>(), vec!["101", "102"]);
+
+        let (_, content) = handle_request(&request("GET", "/search/pets?name=anna", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets[0]["name"], "Pollyanna");
+
+        // Birds have no weight so a weight range only finds dogs and cats
+        let (_, content) = handle_request(&request("GET", "/search/pets?min_weight=10&max_weight=60", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets.as_array().unwrap().iter().map(|p| p["name"].as_str().unwrap()).collect::<Vec<_>>(), vec!["Nova", "Bruno"]);
+
+        let (status, content) = handle_request(&request("GET", "/search/pets?hair=Curly", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+        assert_eq!(content, "Hair must be either LongHaired or ShortHaired");
+    }
+
+    #[test]
+    fn breeds_are_normalized_by_the_catalog() {
+        let app = app();
+        let (status, content) = handle_request(&request("POST", "/pets/123", r#"[{"animal": "Dog", "name": "Rex", "weight": 50, "breed": "Labradr"}]"#), &app);
+        assert_eq!((status.as_str(), content.as_str()), (BAD_REQUEST, "Unknown breed: Labradr. Did you mean Labrador Retriever?"));
+
+        let (_, content) = handle_request(&request("POST", "/pets/123", PETS), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets[0]["breed"], "Labrador Retriever");
+        for (method, path) in [("PUT", "/pets/123"), ("POST", "/policy/check")] {
+            let (status, _) = handle_request(&request(method, path, &PETS.replace("Labrador", "Labradr")), &app);
+            assert_eq!(status, BAD_REQUEST, "{} {}", method, path);
+        }
+        let (_, content) = handle_request(&request("GET", "/search/pets?breed=lab", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets[0]["name"], "Sunny");
+
+        let (status, content) = handle_request(&request("GET", "/catalog?animal=Bird&q=parakeet", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let entries: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(entries.as_array().unwrap().iter().map(|e| e["name"].as_str().unwrap()).collect::<Vec<_>>(), vec!["Budgerigar", "Quaker Parrot", "Ringneck Parakeet"]);
+        assert_eq!(entries[0]["field"], "species");
+        let (status, _) = handle_request(&request("GET", "/catalog?breed=lab", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+    }
+
+    #[test]
+    fn stats_summarize_apartment_range() {
+        let app = app();
+        handle_request(&request("POST", "/pets/101", PETS), &app);
+        handle_request(&request("POST", "/pets/102", r#"[{"animal": "Dog", "name": "Bruno", "weight": 55, "breed": "Pit Bull"}]"#), &app);
+        handle_request(&request("POST", "/pets/103", "[]"), &app);
+
+        let (status, content) = handle_request(&request("GET", "/stats", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let stats: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(stats["apartments"], 2);
+        assert_eq!(stats["animals"]["Dog"], 2);
+        assert_eq!(stats["weight"]["Dog"]["max"], 70.0);
+
+        let (_, content) = handle_request(&request("GET", "/stats?from_apt=102&to_apt=103", ""), &app);
+        let stats: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(stats["apartments"], 1);
+        assert_eq!(stats["top_breeds"], serde_json::json!([{"name": "Pit Bull", "count": 1}]));
+        assert_eq!(stats["weight"]["Cat"], serde_json::Value::Null);
+
+        let (status, content) = handle_request(&request("GET", "/stats?from_apt=abc", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+        assert_eq!(content, "from_apt must be an integer");
+    }
+
+    #[test]
+    fn policy_rejects_violations_and_checks_dry_runs() {
+        let app = App {
+            policy: Policy::from_json(r#"{"max_per_species": {"Dog": 1}, "weight": {"Dog": {"max": 80}}}"#).unwrap(),
+            ..app()
+        };
+        let heavy = r#"[{"animal": "Dog", "name": "Bruno", "weight": 95, "breed": "Mastiff"}]"#;
+
+        let (status, content) = handle_request(&request("POST", "/pets/123", heavy), &app);
+        assert_eq!(status, UNPROCESSABLE_ENTITY);
+        let violations: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(violations[0]["rule"], "weight");
+        assert_eq!(violations[0]["name"], "Bruno");
+        let (status, _) = handle_request(&request("GET", "/pets/123", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+
+        let (status, _) = handle_request(&request("POST", "/pets/123", PETS), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let two_dogs = r#"[
+            {"animal": "Dog", "name": "Sunny", "weight": 70, "breed": "Labrador"},
+            {"animal": "Dog", "name": "Paris", "weight": 60, "breed": "Poodle"}
+        ]"#;
+        let (status, content) = handle_request(&request("PUT", "/pets/123", two_dogs), &app);
+        assert_eq!(status, UNPROCESSABLE_ENTITY);
+        assert!(content.contains("max_per_species"));
+
+        // The dry run reports the same violations and saves nothing
+        let (status, content) = handle_request(&request("POST", "/policy/check", two_dogs), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let result: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(result["allowed"], false);
+        assert_eq!(result["violations"][0]["rule"], "max_per_species");
+        let (_, content) = handle_request(&request("POST", "/policy/check", PETS), &app);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap()["allowed"], true);
+        let (_, content) = handle_request(&request("GET", "/pets/123", ""), &app);
+        assert!(content.contains("Nova"));
+    }
+
+    #[test]
+    fn records_track_expiry() {
+        let app = app();
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let today = Local::now().date_naive();
+        let date = |days: i64| (today + chrono::Duration::days(days)).to_string();
+
+        let vaccination = |given: i64, expires: i64| format!(
+            r#"{{"vaccine": "Rabies", "given": "{}", "expires": "{}", "vet": "Dr. Reyes"}}"#, date(given), date(expires)
+        );
+        let (status, content) = handle_request(&request("POST", "/pets/123/Dog/1/vaccinations", &vaccination(-400, -35)), &app);
+        assert_eq!(status, OK_RESPONSE);
+        assert!(content.contains(r#""vaccine":"Rabies""#));
+        let license = format!(r#"{{"number": "DL-1001", "issued": "{}", "expires": "{}"}}"#, date(-300), date(10));
+        let (status, _) = handle_request(&request("POST", "/pets/123/Dog/1/licenses", &license), &app);
+        assert_eq!(status, OK_RESPONSE);
+
+        let (_, content) = handle_request(&request("GET", "/records/expiring?days=30", ""), &app);
+        let records: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(records.as_array().unwrap().len(), 2);
+        assert_eq!(records[0]["record"], "vaccination");
+        assert_eq!(records[0]["expired"], true);
+        assert_eq!(records[1]["detail"], "DL-1001");
+        assert_eq!(records[1]["expired"], false);
+
+        // A booster replaces the expired vaccination, the license is outside a 5 day window
+        handle_request(&request("POST", "/pets/123/Dog/1/vaccinations", &vaccination(-30, 335)), &app);
+        let (_, content) = handle_request(&request("GET", "/records/expiring?days=5", ""), &app);
+        assert_eq!(content, "[]");
+
+        let (_, content) = handle_request(&request("GET", "/pets/123/Dog/1/records", ""), &app);
+        let records: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(records["vaccinations"].as_array().unwrap().len(), 2);
+        assert_eq!(records["licenses"][0]["number"], "DL-1001");
+
+        let (status, _) = handle_request(&request("POST", "/pets/123/Dog/1/licenses", &license), &app);
+        assert_eq!(status, CONFLICT);
+        let (status, _) = handle_request(&request("POST", "/pets/124/Dog/1/licenses", &license), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (status, content) = handle_request(&request("POST", "/pets/123/Dog/1/vaccinations", &vaccination(0, -1)), &app);
+        assert_eq!(status, BAD_REQUEST);
+        assert_eq!(content, "Vaccination can't expire before it was given");
+        let (status, _) = handle_request(&request("GET", "/records/expiring?days=-1", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+    }
+
+    fn upload(path: &str, field: &str, data: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            "--b0undary\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"photo\"\r\nContent-Type: image/png\r\n\r\n", field
+        ).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--b0undary--\r\n");
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b0undary\r\nContent-Length: {}\r\n\r\n", path, body.len()
+        ).into_bytes();
+        request.extend(body);
+        request
+    }
+
+    #[test]
+    fn photos_are_stored_with_thumbnails() {
+        let dir = photo_dir();
+        let app = App { photos: PhotoStore::new(&dir, 64 * 1024), ..app() };
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let mut png = Vec::new();
+        image::RgbImage::from_pixel(400, 300, image::Rgb([200, 120, 40]))
+            .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+
+        let (status, content) = handle_binary_request(&upload("/pets/123/Dog/1/photo", "photo", &png), &app);
+        assert_eq!(status, OK_RESPONSE);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&content).unwrap(),
+            serde_json::json!({"content_type": "image/png", "bytes": png.len(), "width": 400, "height": 300})
+        );
+
+        let (status, content) = handle_binary_request(request("GET", "/pets/123/Dog/1/photo", "").as_bytes(), &app);
+        assert_eq!(status, format!("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n", png.len()));
+        assert_eq!(content, png);
+        let (status, content) = handle_binary_request(request("GET", "/pets/123/Dog/1/photo/thumbnail", "").as_bytes(), &app);
+        assert!(status.contains("Content-Type: image/jpeg"));
+        let thumbnail = image::load_from_memory(&content).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (200, 150));
+
+        // Other requests still go through as text
+        let (status, _) = handle_binary_request(request("GET", "/pets/123", "").as_bytes(), &app);
+        assert_eq!(status, OK_RESPONSE);
+
+        let (status, content) = handle_binary_request(&upload("/pets/123/Dog/1/photo", "photo", b"not an image"), &app);
+        assert_eq!((status.as_str(), content.as_slice()), (UNSUPPORTED_MEDIA_TYPE, &b"Photo must be a JPEG, PNG, GIF or WebP image"[..]));
+        let (status, _) = handle_binary_request(&upload("/pets/123/Dog/1/photo", "photo", &vec![0; 65 * 1024]), &app);
+        assert_eq!(status, PAYLOAD_TOO_LARGE);
+        let (status, content) = handle_binary_request(&upload("/pets/123/Dog/1/photo", "image", &png), &app);
+        assert_eq!((status.as_str(), content.as_slice()), (BAD_REQUEST, &b"Multipart body requires a photo field"[..]));
+        let (status, _) = handle_binary_request(&upload("/pets/123/Cat/1/photo", "photo", &png), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (status, content) = handle_binary_request(request("GET", "/pets/123/Cat/2/photo", "").as_bytes(), &app);
+        assert_eq!((status.as_str(), content.as_slice()), (NOT_FOUND, &b"Photo not found"[..]));
+
+        // Photos are kept while the pet can be restored and go when it is purged
+        let dog = PetRef { apt: Apt::numbered(123), animal: "Dog".to_string(), id: 1 };
+        handle_request(&request("DELETE", "/pets/123", ""), &app);
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        assert!(app.photos.open(&dog, false).unwrap().is_some());
+        assert_eq!(purge_deleted(&app, Days::new(0)).unwrap(), 3);
+        assert!(app.photos.open(&dog, false).unwrap().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_request_body_to_content_length() {
+        let body = "x".repeat(5000);
+        let raw = format!("POST /pets/1 HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        let request = read_request(&mut io::Cursor::new(raw.as_bytes()), 1024).unwrap().unwrap();
+        assert_eq!(request, raw.as_bytes());
+
+        let raw = format!("POST /pets/1 HTTP/1.1\r\ncontent-length: {}\r\n\r\n", 1024 + MULTIPART_OVERHEAD + 1);
+        assert_eq!(read_request(&mut io::Cursor::new(raw.as_bytes()), 1024).unwrap(), None);
+    }
+
+    #[test]
+    fn tenants_own_pets() {
+        let app = app();
+        let moved_in = (Local::now().date_naive() - Days::new(90)).to_string();
+        let tenant = format!(r#"{{"name": "Ana Ruiz", "email": "ana@example.com", "tenancies": [{{"unit": "123", "move_in": "{}"}}]}}"#, moved_in);
+        let (status, content) = handle_request(&request("POST", "/tenants", &tenant), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let tenant: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(tenant["id"], 1);
+        assert_eq!(tenant["tenancies"][0]["move_out"], serde_json::Value::Null);
+
+        let owned = r#"[{"animal": "Dog", "name": "Sunny", "weight": 70, "breed": "Labrador", "owner": 1}]"#;
+        let (status, content) = handle_request(&request("POST", "/pets/124", owned), &app);
+        assert_eq!(status, BAD_REQUEST);
+        assert_eq!(content, "Owner 1 is not a current tenant of apartment 124");
+        let (status, content) = handle_request(&request("POST", "/pets/123", owned), &app);
+        assert_eq!(status, OK_RESPONSE);
+        assert!(content.contains(r#""owner":1"#));
+
+        let (_, content) = handle_request(&request("GET", "/pets/123?embed=owner", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets[0]["owner"], serde_json::json!({"id": 1, "name": "Ana Ruiz", "email": "ana@example.com", "phone": null}));
+        let (status, _) = handle_request(&request("GET", "/pets/123?embed=vet", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+
+        let (_, content) = handle_request(&request("GET", "/tenants?apt=123", ""), &app);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap()[0]["name"], "Ana Ruiz");
+        let (_, content) = handle_request(&request("GET", "/tenants?apt=124", ""), &app);
+        assert_eq!(content, "[]");
+
+        // Once moved out they can't be named as an owner anymore
+        let moved_out = format!(
+            r#"{{"name": "Ana Ruiz", "tenancies": [{{"unit": "123", "move_in": "{}", "move_out": "{}"}}]}}"#,
+            moved_in, Local::now().date_naive() - Days::new(1)
+        );
+        let (status, content) = handle_request(&request("PUT", "/tenants/1", &moved_out), &app);
+        assert_eq!(status, OK_RESPONSE);
+        assert!(content.contains(r#""email":null"#));
+        let (status, _) = handle_request(&request("PUT", "/pets/123", owned), &app);
+        assert_eq!(status, BAD_REQUEST);
+
+        let (status, _) = handle_request(&request("DELETE", "/tenants/1", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let (_, content) = handle_request(&request("GET", "/pets/123?embed=owner", ""), &app);
+        assert!(!content.contains("owner"));
+        let (status, _) = handle_request(&request("GET", "/tenants/1", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+
+        let (status, content) = handle_request(&request("POST", "/tenants", r#"{"name": "Bo", "email": "bo"}"#), &app);
+        assert_eq!((status.as_str(), content.as_str()), (BAD_REQUEST, "Email must contain @"));
+    }
+
+    #[test]
+    fn buildings_have_named_units() {
+        let app = app();
+        let building = r#"{"code": "north", "name": "North Tower", "property": "Riverside"}"#;
+        let (status, _) = handle_request(&request("POST", "/buildings", building), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let (status, content) = handle_request(&request("POST", "/buildings", building), &app);
+        assert_eq!((status.as_str(), content.as_str()), (CONFLICT, "Building already exists"));
+        let (_, content) = handle_request(&request("GET", "/buildings", ""), &app);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap()[1]["code"], "north");
+
+        let (status, _) = handle_request(&request("POST", "/buildings/north/units/12B/pets", PETS), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let (status, _) = handle_request(&request("POST", "/buildings/north/units/101/pets", "[]"), &app);
+        assert_eq!(status, OK_RESPONSE);
+        // The same number in the default building is another apartment
+        let (status, _) = handle_request(&request("GET", "/pets/101", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (_, content) = handle_request(&request("GET", "/buildings/north/units/12B/pets", ""), &app);
+        assert!(content.contains("Sunny"));
+        let (status, _) = handle_request(&request("GET", "/buildings/north/units/12B/pets/Dog/1/records", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+
+        let (_, content) = handle_request(&request("GET", "/buildings/north/units?limit=1", ""), &app);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap()[0]["unit"], "101");
+        let (_, content) = handle_request(&request("GET", "/search/pets?building=north&name=nova", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!((&pets[0]["building"], &pets[0]["unit"]), (&serde_json::json!("north"), &serde_json::json!("12B")));
+
+        let (status, content) = handle_request(&request("POST", "/buildings/south/units/1/pets", "[]"), &app);
+        assert_eq!((status.as_str(), content.as_str()), (NOT_FOUND, "Building not found"));
+        let (status, _) = handle_request(&request("POST", "/buildings", r#"{"code": "a b", "name": "A", "property": "P"}"#), &app);
+        assert_eq!(status, BAD_REQUEST);
+        let (status, _) = handle_request(&request("DELETE", "/buildings/north/units/12B/pets", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+    }
+
+    #[test]
+    fn fees_are_computed_from_stored_pets() {
+        let app = App {
+            fees: FeeSchedule::from_json(r#"{"species": {"Dog": {"monthly": 2500, "one_time": 30000}, "Cat": {"monthly": 1500}}}"#).unwrap(),
+            ..app()
+        };
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        handle_request(&request("POST", "/buildings", r#"{"code": "north", "name": "North Tower", "property": "Riverside"}"#), &app);
+        handle_request(&request("POST", "/buildings/north/units/12B/pets", r#"[{"animal": "Cat", "name": "Nova", "weight": 13, "hair": "LongHaired"}]"#), &app);
+        for pet in ["/pets/123/Dog/1", "/pets/123/Cat/2", "/pets/123/Bird/3", "/buildings/north/units/12B/pets/Cat/4"] {
+            assert_eq!(approve(&app, pet).0, OK_RESPONSE);
+        }
+
+        let (status, content) = handle_request(&request("GET", "/pets/123/fees", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let fees: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(fees["pets"][0], serde_json::json!({"animal": "Dog", "id": 1, "name": "Sunny", "monthly": 2500, "one_time": 30000}));
+        assert_eq!(fees["total"], serde_json::json!({"monthly": 4000, "one_time": 30000}));
+
+        let (status, content) = handle_request(&request("GET", "/fees?format=csv", ""), &app);
+        assert_eq!(status, CSV_RESPONSE);
+        assert_eq!(content, "building,unit,pets,monthly,one_time\ndefault,123,3,4000,30000\nnorth,12B,1,1500,0\n");
+        let (_, content) = handle_request(&request("GET", "/fees?building=north", ""), &app);
+        assert_eq!(content, r#"[{"building":"north","unit":"12B","pets":1,"monthly":1500,"one_time":0}]"#);
+
+        let (status, _) = handle_request(&request("GET", "/pets/124/fees", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (status, _) = handle_request(&request("GET", "/fees?format=xml", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+    }
+
+    #[test]
+    fn assistance_animals_are_verified_by_managers() {
+        let app = App { policy: Policy::from_json(r#"{"max_pets": 1}"#).unwrap(), ..app() };
+        let pets = r#"[
+            {"animal": "Dog", "name": "Sunny", "weight": 70, "breed": "Labrador"},
+            {"animal": "Dog", "name": "Scout", "weight": 60, "breed": "Labrador", "assistance": {
+                "kind": "service", "verification": "verified", "documentation": {"provider": "Dr. Reyes", "issued": "2024-02-01"}
+            }}
+        ]"#;
+        let (status, content) = handle_request(&request("POST", "/pets/123", pets), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let created: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(created[1]["assistance"], serde_json::json!({"kind": "service", "verification": "pending"}));
+
+        // Sent back without the documentation it is kept, and only a manager can verify it
+        let shown = pets.replace(r#", "documentation": {"provider": "Dr. Reyes", "issued": "2024-02-01"}"#, "");
+        let (status, _) = handle_request(&request("PUT", "/pets/123", &shown), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let (_, content) = handle_request(&manager_request("PUT", "/pets/123", &shown), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets[1]["assistance"]["documentation"]["provider"], "Dr. Reyes");
+        let (_, content) = handle_request(&request("PUT", "/pets/123", &shown.replace("verified", "pending")), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets[1]["assistance"], serde_json::json!({"kind": "service", "verification": "verified"}));
+
+        let (status, content) = handle_request(&request("POST", "/pets/124", r#"[{"animal": "Cat", "name": "Nova", "weight": 13, "hair": "LongHaired", "assistance": {"kind": "emotional_support"}}]"#), &app);
+        assert_eq!((status.as_str(), content.as_str()), (BAD_REQUEST, "Nova needs documentation to be an assistance animal"));
+        let (status, _) = handle_request(&request("GET", "/pets/124", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+
+        let (_, content) = handle_request(&request("GET", "/stats", ""), &app);
+        let stats: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(stats["assistance"], serde_json::json!({"emotional_support": 0, "service": 1}));
+    }
+
+    #[test]
+    fn managers_review_new_pets() {
+        let app = app();
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let (status, _) = handle_request(&request("POST", "/pets/123/Dog/1/review", r#"{"status": "approved", "reviewer": "Jo"}"#), &app);
+        assert_eq!(status, FORBIDDEN);
+
+        let (status, content) = handle_request(&manager_request("GET", "/reviews", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let pending: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pending.as_array().unwrap().len(), 3);
+        assert_eq!(pending[0]["review"]["status"], "pending");
+
+        let (status, content) = approve(&app, "/pets/123/Dog/1");
+        assert_eq!(status, OK_RESPONSE);
+        let pet: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!((&pet["review"]["status"], &pet["review"]["reviewed_by"]), (&serde_json::json!("approved"), &serde_json::json!("Jo")));
+        let (status, content) = handle_request(&manager_request("POST", "/pets/123/Cat/2/review", r#"{"status": "rejected", "reviewer": "Jo"}"#), &app);
+        assert_eq!((status.as_str(), content.as_str()), (BAD_REQUEST, "A reason is required when a pet is rejected"));
+        let (status, _) = handle_request(&manager_request("POST", "/pets/123/Cat/2/review", r#"{"status": "rejected", "reviewer": "Jo", "reason": "No vaccination records"}"#), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let (status, content) = approve(&app, "/pets/123/Cat/2");
+        assert_eq!((status.as_str(), content.as_str()), (CONFLICT, "A rejected pet can't be approved"));
+        let (status, _) = approve(&app, "/pets/123/Cat/9");
+        assert_eq!(status, NOT_FOUND);
+
+        let (_, content) = handle_request(&request("GET", "/pets/123?status=approved", ""), &app);
+        let approved: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(approved.as_array().unwrap().len(), 1);
+        assert_eq!(approved[0]["name"], "Sunny");
+        let (status, _) = handle_request(&request("GET", "/pets/123?status=gone", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+
+        // Pets sent again unchanged keep their review, changed ones go back to pending
+        let (_, content) = handle_request(&request("PUT", "/pets/123", &PETS.replace("\"weight\": 13", "\"weight\": 14")), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(pets[0]["review"]["status"], "approved");
+        assert_eq!(pets[1]["review"]["status"], "pending");
+    }
+
+    #[test]
+    fn history_records_every_change() {
+        let app = app();
+        let front_desk = |request: String| request.replacen("\r\n\r\n", "\r\nX-Actor: Front Desk\r\n\r\n", 1);
+        let history = |app: &App| -> serde_json::Value {
+            let (status, content) = handle_request(&request("GET", "/pets/123/history", ""), app);
+            assert_eq!(status, OK_RESPONSE);
+            serde_json::from_str(&content).unwrap()
+        };
+        handle_request(&front_desk(request("POST", "/pets/123", PETS)), &app);
+        handle_request(&front_desk(request("PUT", "/pets/123", &PETS.replace("\"weight\": 13", "\"weight\": 14"))), &app);
+        // Replaced pets get new ids. The header is only a claim, a manager's changes are logged under the token.
+        handle_request(&front_desk(manager_request("POST", "/pets/123/Dog/4/review", r#"{"status": "approved", "reviewer": "Jo"}"#)), &app);
+
+        let entries = history(&app);
+        let summary: Vec<_> = entries.as_array().unwrap().iter()
+            .map(|e| (e["action"].as_str().unwrap(), e["actor"].as_str().unwrap(), e["manager"].as_bool().unwrap(), e["claimed_actor"].as_str(), e["pet_id"].as_i64()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("create", "anonymous", false, Some("Front Desk"), None),
+            ("update", "anonymous", false, Some("Front Desk"), None),
+            ("update", "manager", true, None, Some(4)),
+        ]);
+        assert_eq!(entries[0]["before"], serde_json::Value::Null);
+        assert_eq!(
+            (&entries[1]["before"][1]["weight"], &entries[1]["after"][1]["weight"]),
+            (&serde_json::json!({"value": 13.0, "unit": "lb"}), &serde_json::json!({"value": 14.0, "unit": "lb"}))
+        );
+        assert_eq!(entries[2]["after"][0]["review"]["status"], "approved");
+
+        // The registry as it was after each change
+        let as_of = |app: &App, at: &serde_json::Value| handle_request(&request("GET", &format!("/pets/123?as_of={}", at.as_str().unwrap()), ""), app);
+        let (status, content) = as_of(&app, &entries[0]["at"]);
+        assert_eq!(status, OK_RESPONSE);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!((&pets[1]["weight"], &pets[0]["review"]["status"]), (&serde_json::json!({"value": 13.0, "unit": "lb"}), &serde_json::json!("pending")));
+        let (status, _) = handle_request(&request("GET", "/pets/123?as_of=2000-01-01", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (status, _) = handle_request(&request("GET", "/pets/123?as_of=yesterday", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+
+        handle_request(&front_desk(request("DELETE", "/pets/123", "")), &app);
+        let entries = history(&app);
+        assert_eq!((&entries[3]["action"], &entries[3]["after"]), (&serde_json::json!("delete"), &serde_json::Value::Null));
+        let (_, content) = as_of(&app, &entries[2]["at"]);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!((&pets[0]["review"]["status"], &pets[1]["weight"]), (&serde_json::json!("approved"), &serde_json::json!({"value": 14.0, "unit": "lb"})));
+        let (status, _) = as_of(&app, &entries[3]["at"]);
+        assert_eq!(status, NOT_FOUND);
+
+        let (status, _) = handle_request(&request("GET", "/pets/124/history", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+    }
+
+    #[test]
+    fn history_shows_weights_in_the_unit_asked_for() {
+        let app = app();
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let (status, content) = handle_request(&request("GET", "/pets/123/history?weight_unit=kg", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let entries: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(entries[0]["after"][0]["weight"], serde_json::json!({"value": 31.75, "unit": "kg"}));
+        assert_eq!(entries[0]["after"][2].get("weight"), None);
+
+        let (status, content) = handle_request(&request("GET", "/pets/123/history?weight_unit=stone", ""), &app);
+        assert_eq!((status.as_str(), content.as_str()), (BAD_REQUEST, "Weight unit must be either lb or kg"));
+    }
+
+    #[test]
+    fn deleted_pets_can_be_restored() {
+        let app = App { policy: serde_json::from_str(r#"{"max_pets": 3}"#).unwrap(), ..app() };
+        let get = |app: &App, path: &str| -> (String, serde_json::Value) {
+            let (status, content) = handle_request(&request("GET", path, ""), app);
+            (status, serde_json::from_str(&content).unwrap_or_default())
+        };
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        let (status, content) = handle_request(&request("DELETE", "/pets/123/Cat/2", ""), &app);
+        assert_eq!((status.as_str(), content.as_str()), (OK_RESPONSE, "Pet deleted"));
+        let (status, _) = handle_request(&request("DELETE", "/pets/123/Cat/2", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (_, pets) = get(&app, "/pets/123");
+        assert_eq!(pets.as_array().unwrap().len(), 2);
+        let (_, deleted) = get(&app, "/pets/123/deleted");
+        assert_eq!((&deleted[0]["name"], deleted[0]["deleted_at"].is_string()), (&serde_json::json!("Nova"), true));
+
+        // The lease is checked against the pets there now
+        handle_request(&request("PUT", "/pets/123", &PETS.replace("Nova", "Luna")), &app);
+        let (status, content) = handle_request(&request("POST", "/pets/123/Cat/2/restore", ""), &app);
+        assert_eq!(status, UNPROCESSABLE_ENTITY);
+        assert!(content.contains("max_pets"));
+        handle_request(&request("DELETE", "/pets/123/Cat/5", ""), &app);
+        let (status, content) = handle_request(&request("POST", "/pets/123/Cat/2/restore", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap()["name"], "Nova");
+        let (_, pets) = get(&app, "/pets/123");
+        let names: Vec<_> = pets.as_array().unwrap().iter().map(|p| p["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["Nova", "Sunny", "Polly"]);
+
+        // The whole apartment comes back with the pets it had when it was deleted
+        handle_request(&request("DELETE", "/pets/123", ""), &app);
+        let (status, _) = get(&app, "/pets/123");
+        assert_eq!(status, NOT_FOUND);
+        let (status, content) = handle_request(&request("POST", "/pets/123/restore", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap().as_array().unwrap().len(), 3);
+        let (status, _) = handle_request(&request("POST", "/pets/123/restore", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (_, history) = get(&app, "/pets/123/history");
+        let actions: Vec<_> = history.as_array().unwrap().iter().map(|e| (e["action"].as_str().unwrap(), e["pet_id"].as_i64())).collect();
+        assert_eq!(actions[actions.len() - 3..], [("create", Some(2)), ("delete", None), ("create", None)]);
+
+        // Purged pets are gone for good
+        assert_eq!(purge_deleted(&app, Days::new(1)).unwrap(), 0);
+        assert_eq!(purge_deleted(&app, Days::new(0)).unwrap(), 3);
+        let (_, deleted) = get(&app, "/pets/123/deleted");
+        assert_eq!(deleted, serde_json::json!([]));
+    }
+
+    #[test]
+    fn pets_transfer_between_apartments() {
+        let app = App { policy: serde_json::from_str(r#"{"max_pets": 3}"#).unwrap(), ..app() };
+        let transfer = |app: &App, from: &str, body: &str| handle_request(&request("POST", &format!("/pets/{}/transfer", from), body), app);
+        let names = |app: &App, apt: &str| -> Vec<String> {
+            let (_, content) = handle_request(&request("GET", &format!("/pets/{}", apt), ""), app);
+            let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+            pets.as_array().unwrap().iter().map(|p| format!("{}/{}", p["name"].as_str().unwrap(), p["id"])).collect()
+        };
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+        handle_request(&request("POST", "/pets/124", r#"[{"animal": "Dog", "name": "Rex", "weight": 40, "breed": "Beagle"}]"#), &app);
+        handle_request(&request("POST", "/pets/123/Cat/2/licenses", r#"{"number": "CL-7", "issued": "2024-01-01", "expires": "2099-01-01"}"#), &app);
+
+        // An unregistered destination is registered, the pet keeps its id and records
+        let (status, content) = transfer(&app, "123", r#"{"unit": "125", "pets": [{"animal": "Cat", "id": 2}]}"#);
+        assert_eq!(status, OK_RESPONSE);
+        assert!(content.contains("Nova"));
+        assert_eq!(names(&app, "123"), vec!["Sunny/1", "Polly/3"]);
+        assert_eq!(names(&app, "125"), vec!["Nova/2"]);
+        let (_, content) = handle_request(&request("GET", "/pets/125/Cat/2/records", ""), &app);
+        assert!(content.contains("CL-7"));
+
+        let (status, _) = transfer(&app, "123", r#"{"unit": "124", "pets": [{"animal": "Dog", "id": 1}, {"animal": "Bird", "id": 3}]}"#);
+        assert_eq!(status, OK_RESPONSE);
+        assert_eq!(names(&app, "124"), vec!["Sunny/1", "Polly/3", "Rex/4"]);
+        assert_eq!(names(&app, "123"), Vec::<String>::new());
+
+        // The destination's policy applies and nothing moves when it fails
+        let (status, content) = transfer(&app, "125", r#"{"unit": "124", "pets": [{"animal": "Cat", "id": 2}]}"#);
+        assert_eq!(status, UNPROCESSABLE_ENTITY);
+        assert!(content.contains("max_pets"));
+        assert_eq!(names(&app, "125"), vec!["Nova/2"]);
+
+        for (body, expected) in [
+            (r#"{"unit": "123", "pets": [{"animal": "Cat", "id": 9}]}"#, NOT_FOUND),
+            (r#"{"unit": "125", "pets": [{"animal": "Cat", "id": 2}]}"#, BAD_REQUEST),
+            (r#"{"unit": "123", "pets": []}"#, BAD_REQUEST),
+            (r#"{"unit": "123", "pets": [{"animal": "Cat", "id": 2}, {"animal": "Cat", "id": 2}]}"#, BAD_REQUEST),
+            (r#"{"building": "nowhere", "unit": "1", "pets": [{"animal": "Cat", "id": 2}]}"#, NOT_FOUND),
+        ] {
+            assert_eq!(transfer(&app, "125", body).0, expected, "{}", body);
+        }
+
+        // Both sides of the move are in the history
+        let history = |apt: &str| -> serde_json::Value {
+            let (_, content) = handle_request(&request("GET", &format!("/pets/{}/history", apt), ""), &app);
+            serde_json::from_str(&content).unwrap()
+        };
+        let to = history("125");
+        assert_eq!((&to[0]["action"], to[0]["after"].as_array().unwrap().len()), (&serde_json::json!("create"), 1));
+        let to = history("124");
+        assert_eq!((&to[1]["action"], to[1]["before"].as_array().unwrap().len(), to[1]["after"].as_array().unwrap().len()), (&serde_json::json!("update"), 1, 3));
+        let from = history("123");
+        assert_eq!((from[2]["before"].as_array().unwrap().len(), from[2]["after"].as_array().unwrap().len()), (2, 0));
+    }
+
+    #[test]
+    fn incidents_are_reported_and_resolved() {
+        let app = app();
+        let report = |path: &str, body: &str| -> (String, serde_json::Value) {
+            let (status, content) = handle_request(&request("POST", path, body), &app);
+            (status, serde_json::from_str(&content).unwrap_or_default())
+        };
+        let list = |path: &str| -> Vec<(i64, String)> {
+            let (status, content) = handle_request(&request("GET", path, ""), &app);
+            assert_eq!(status, OK_RESPONSE);
+            let incidents: serde_json::Value = serde_json::from_str(&content).unwrap();
+            incidents.as_array().unwrap().iter().map(|i| (i["id"].as_i64().unwrap(), i["status"].as_str().unwrap().to_string())).collect()
+        };
+        handle_request(&request("POST", "/pets/123", PETS), &app);
+
+        let bite = r#"{"type": "bite", "severity": "high", "date": "2024-05-01", "description": "Bit a delivery driver", "reporter": "Front Desk"}"#;
+        let (status, incident) = report("/pets/123/Dog/1/incidents", bite);
+        assert_eq!(status, OK_RESPONSE);
+        assert_eq!((&incident["id"], &incident["pet_id"], &incident["status"]), (&serde_json::json!(1), &serde_json::json!(1), &serde_json::json!("open")));
+        report("/pets/123/Dog/1/incidents", &bite.replace("bite", "noise").replace("high", "low"));
+        let noise = r#"{"type": "noise", "severity": "medium", "date": "2024-04-01", "description": "Barking at night", "reporter": "Unit 124",
+            "resolution": "Spoke to the tenant", "resolved_on": "2024-04-02"}"#;
+        let (status, _) = report("/pets/123/incidents", noise);
+        assert_eq!(status, OK_RESPONSE);
+
+        // The pet GET counts what is still open
+        let (_, content) = handle_request(&request("GET", "/pets/123", ""), &app);
+        let pets: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!((&pets[0]["open_incidents"], &pets[1]["open_incidents"]), (&serde_json::json!(2), &serde_json::Value::Null));
+
+        assert_eq!(list("/pets/123/incidents"), vec![(3, "resolved".to_string()), (1, "open".to_string()), (2, "open".to_string())]);
+        assert_eq!(list("/pets/123/incidents?status=resolved"), vec![(3, "resolved".to_string())]);
+        assert_eq!(list("/pets/123/Dog/1/incidents").len(), 2);
+
+        let (status, incident) = report("/incidents/1/resolve", r#"{"resolution": "Muzzle required in common areas"}"#);
+        assert_eq!((status.as_str(), &incident["resolved_on"]), (OK_RESPONSE, &serde_json::json!(Local::now().date_naive())));
+        let (status, _) = report("/incidents/1/resolve", r#"{"resolution": "Again"}"#);
+        assert_eq!(status, CONFLICT);
+        let (status, _) = report("/incidents/2/resolve", r#"{"resolution": "Too early", "resolved_on": "2024-01-01"}"#);
+        assert_eq!(status, BAD_REQUEST);
+        let (status, _) = report("/incidents/9/resolve", r#"{"resolution": "Nothing"}"#);
+        assert_eq!(status, NOT_FOUND);
+        let (_, content) = handle_request(&request("GET", "/pets/123", ""), &app);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap()[0]["open_incidents"], 1);
+
+        for (path, body, expected) in [
+            ("/pets/123/Dog/9/incidents", bite, NOT_FOUND),
+            ("/pets/124/incidents", bite, NOT_FOUND),
+            ("/pets/123/incidents", &bite.replace("bite", "fire"), BAD_REQUEST),
+            ("/pets/123/incidents", &bite.replace("Front Desk", " "), BAD_REQUEST),
+            ("/pets/123/incidents", &bite.replace("}", r#", "resolution": "Fixed"}"#), BAD_REQUEST),
+        ] {
+            assert_eq!(report(path, body).0, expected, "{} {}", path, body);
+        }
+        let (status, _) = handle_request(&request("GET", "/pets/123/incidents?status=closed", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+    }
+
+    #[test]
+    fn pets_are_found_by_microchip() {
+        let app = app();
+        let rex = r#"[{"animal": "Dog", "name": "Rex", "weight": 40, "breed": "Beagle", "microchip": "985 112 003 456 789"}]"#;
+        let (status, content) = handle_request(&request("POST", "/pets/123", rex), &app);
+        assert_eq!(status, OK_RESPONSE);
+        assert!(content.contains(r#""microchip":"985112003456789""#));
+
+        let (status, content) = handle_request(&request("GET", "/lookup/microchip/985-112-003-456-789", ""), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let found: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!((&found["unit"], &found["name"], &found["id"]), (&serde_json::json!("123"), &serde_json::json!("Rex"), &serde_json::json!(1)));
+
+        // A chip is only on one pet, in a POST or across the registry
+        let (status, content) = handle_request(&request("POST", "/pets/124", rex), &app);
+        assert_eq!((status.as_str(), content.as_str()), (CONFLICT, "Microchip already registered"));
+        let twins = r#"[{"animal": "Cat", "name": "Nova", "weight": 12, "hair": "LongHaired", "microchip": "avid12345"},
+            {"animal": "Cat", "name": "Fenrir", "weight": 9, "hair": "ShortHaired", "microchip": "AVID12345"}]"#;
+        let (status, _) = handle_request(&request("POST", "/pets/125", twins), &app);
+        assert_eq!(status, CONFLICT);
+        let (status, _) = handle_request(&request("GET", "/pets/125", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+
+        // Replacing the apartment's pets can keep the chip
+        let (status, _) = handle_request(&request("PUT", "/pets/123", rex), &app);
+        assert_eq!(status, OK_RESPONSE);
+        let (_, content) = handle_request(&request("GET", "/lookup/microchip/985112003456789", ""), &app);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap()["id"], 2);
+
+        // A malformed chip is a bad request whichever way it comes in, and changes nothing
+        let (status, content) = handle_request(&request("POST", "/pets/124", &rex.replace("985 112 003 456 789", "12345")), &app);
+        assert_eq!((status.as_str(), content.as_str()), (BAD_REQUEST, "Microchip field must be a 15 digit ISO number or a 9 or 10 character legacy id"));
+        let (status, _) = handle_request(&request("PUT", "/pets/123", &rex.replace("985 112 003 456 789", "98511200345678X")), &app);
+        assert_eq!(status, BAD_REQUEST);
+        let (_, content) = handle_request(&request("GET", "/lookup/microchip/985112003456789", ""), &app);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap()["id"], 2);
+        let (status, _) = handle_request(&request("GET", "/lookup/microchip/AVID12345", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+        let (status, _) = handle_request(&request("GET", "/lookup/microchip/12345", ""), &app);
+        assert_eq!(status, BAD_REQUEST);
+    }
+
+    #[test]
+    fn unknown_route_is_not_found() {
+        let app = app();
+        let (status, _) = handle_request(&request("GET", "/owners/123", ""), &app);
+        assert_eq!(status, NOT_FOUND);
+    }
+}
@@ -1,3 +1,17 @@
+#[macro_use]
+extern crate serde_derive;
+
+pub mod catalog;
+pub mod fees;
+pub mod handlers;
+pub mod migrations;
+pub mod models;
+pub mod photos;
+pub mod policy;
+pub mod species;
+pub mod stats;
+pub mod store;
+
 use std::{thread, sync::{mpsc, Arc, Mutex}};
 
 pub struct ThreadPool {
@@ -83,4 +97,4 @@ type Job = Box<dyn FnOnce() + Send +'static>;
 enum Message {
     NewJob(Job),
     Terminate
-}
\ No newline at end of file
+}
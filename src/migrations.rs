@@ -0,0 +1,444 @@
+use postgres::Client;
+use postgres::Error as PostgresError;
+use rusqlite::Connection;
+use rusqlite::Error as SqliteError;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.up.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+macro_rules! migration {
+    ($backend:literal, $version:literal, $prefix:literal, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $backend, "/", $prefix, "_", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $backend, "/", $prefix, "_", $name, ".down.sql")),
+        }
+    };
+}
+
+// Both lists must stay sorted by version and in step with each other; applied migrations
+// are never edited, add a new one instead
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    migration!("postgres", 1, "0001", "create_pets_tables"),
+    migration!("postgres", 2, "0002", "pets_apt_foreign_keys"),
+    migration!("postgres", 3, "0003", "pet_search_indexes"),
+    migration!("postgres", 4, "0004", "generic_pets_table"),
+    migration!("postgres", 5, "0005", "pet_records"),
+    migration!("postgres", 6, "0006", "tenants"),
+    migration!("postgres", 7, "0007", "buildings"),
+    migration!("postgres", 8, "0008", "assistance_animals"),
+    migration!("postgres", 9, "0009", "pet_reviews"),
+    migration!("postgres", 10, "0010", "audit_log"),
+    migration!("postgres", 11, "0011", "soft_delete"),
+    migration!("postgres", 12, "0012", "incidents"),
+    migration!("postgres", 13, "0013", "microchips"),
+    migration!("postgres", 14, "0014", "audit_claimed_actor"),
+    migration!("postgres", 15, "0015", "legacy_weights"),
+];
+
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    migration!("sqlite", 1, "0001", "create_pets_tables"),
+    migration!("sqlite", 2, "0002", "pets_apt_foreign_keys"),
+    migration!("sqlite", 3, "0003", "pet_search_indexes"),
+    migration!("sqlite", 4, "0004", "generic_pets_table"),
+    migration!("sqlite", 5, "0005", "pet_records"),
+    migration!("sqlite", 6, "0006", "tenants"),
+    migration!("sqlite", 7, "0007", "buildings"),
+    migration!("sqlite", 8, "0008", "assistance_animals"),
+    migration!("sqlite", 9, "0009", "pet_reviews"),
+    migration!("sqlite", 10, "0010", "audit_log"),
+    migration!("sqlite", 11, "0011", "soft_delete"),
+    migration!("sqlite", 12, "0012", "incidents"),
+    migration!("sqlite", 13, "0013", "microchips"),
+    migration!("sqlite", 14, "0014", "audit_claimed_actor"),
+    migration!("sqlite", 15, "0015", "legacy_weights"),
+];
+
+// Arbitrary key shared by every instance so only one runs migrations at a time
+const LOCK_KEY: i64 = 0x6170_745f_7065_7473;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Postgres(PostgresError),
+    Sqlite(SqliteError),
+    ChecksumMismatch { version: i64, name: String },
+    Unknown { version: i64, name: String },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::Postgres(e) => write!(f, "{}", e),
+            MigrationError::Sqlite(e) => write!(f, "{}", e),
+            MigrationError::ChecksumMismatch { version, name } =>
+                write!(f, "Migration {:04}_{} was modified after being applied", version, name),
+            MigrationError::Unknown { version, name } =>
+                write!(f, "Migration {:04}_{} is applied but not known to this build", version, name),
+        }
+    }
+}
+
+impl From<PostgresError> for MigrationError {
+    fn from(e: PostgresError) -> Self {
+        MigrationError::Postgres(e)
+    }
+}
+
+impl From<SqliteError> for MigrationError {
+    fn from(e: SqliteError) -> Self {
+        MigrationError::Sqlite(e)
+    }
+}
+
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: &'static str,
+    pub applied_at: Option<String>,
+}
+
+pub struct AppliedMigration {
+    version: i64,
+    name: String,
+    checksum: String,
+    applied_at: String,
+}
+
+// What the runner needs from a database to track and apply migrations
+pub trait Migrate {
+    fn migrations(&self) -> &'static [Migration];
+
+    // Held for the whole run and creates schema_migrations if needed
+    fn lock(&mut self) -> Result<(), MigrationError>;
+
+    fn unlock(&mut self) -> Result<(), MigrationError>;
+
+    fn applied(&mut self) -> Result<Vec<AppliedMigration>, MigrationError>;
+
+    // Runs the up or down script and records it in schema_migrations, all or nothing
+    fn run(&mut self, migration: &Migration, up: bool) -> Result<(), MigrationError>;
+}
+
+impl Migrate for Client {
+    fn migrations(&self) -> &'static [Migration] {
+        POSTGRES_MIGRATIONS
+    }
+
+    fn lock(&mut self) -> Result<(), MigrationError> {
+        self.execute("SELECT pg_advisory_lock($1)", &[&LOCK_KEY])?;
+        self.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name VARCHAR NOT NULL,
+                checksum VARCHAR NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );"
+        )?;
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> Result<(), MigrationError> {
+        self.execute("SELECT pg_advisory_unlock($1)", &[&LOCK_KEY])?;
+        Ok(())
+    }
+
+    fn applied(&mut self) -> Result<Vec<AppliedMigration>, MigrationError> {
+        Ok(self.query(
+            "SELECT version, name, checksum, applied_at::TEXT AS applied_at FROM schema_migrations ORDER BY version",
+            &[]
+        )?.iter().map(|row| AppliedMigration {
+            version: row.get("version"),
+            name: row.get("name"),
+            checksum: row.get("checksum"),
+            applied_at: row.get("applied_at"),
+        }).collect())
+    }
+
+    fn run(&mut self, migration: &Migration, up: bool) -> Result<(), MigrationError> {
+        let mut transaction = self.transaction()?;
+        if up {
+            transaction.batch_execute(migration.up)?;
+            transaction.execute(
+                "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+                &[&migration.version, &migration.name, &migration.checksum()]
+            )?;
+        } else {
+            transaction.batch_execute(migration.down)?;
+            transaction.execute("DELETE FROM schema_migrations WHERE version = $1", &[&migration.version])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+// SQLite has no advisory locks, instead the whole run happens inside one write transaction
+// and each migration gets a savepoint so a failure only undoes that migration
+impl Migrate for Connection {
+    fn migrations(&self) -> &'static [Migration] {
+        SQLITE_MIGRATIONS
+    }
+
+    fn lock(&mut self) -> Result<(), MigrationError> {
+        self.execute_batch(
+            "BEGIN IMMEDIATE;
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name VARCHAR NOT NULL,
+                checksum VARCHAR NOT NULL,
+                applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );"
+        )?;
+        Ok(())
+    }
+
+    fn unlock(&mut self) -> Result<(), MigrationError> {
+        self.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    fn applied(&mut self) -> Result<Vec<AppliedMigration>, MigrationError> {
+        let mut statement = self.prepare(
+            "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version"
+        )?;
+        let rows = statement.query_map([], |row| Ok(AppliedMigration {
+            version: row.get("version")?,
+            name: row.get("name")?,
+            checksum: row.get("checksum")?,
+            applied_at: row.get("applied_at")?,
+        }))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn run(&mut self, migration: &Migration, up: bool) -> Result<(), MigrationError> {
+        self.execute_batch("SAVEPOINT migration")?;
+        let res = if up {
+            self.execute_batch(migration.up).and_then(|_| self.execute(
+                "INSERT INTO schema_migrations (version, name, checksum) VALUES (?1, ?2, ?3)",
+                (migration.version, migration.name, migration.checksum())
+            ))
+        } else {
+            self.execute_batch(migration.down).and_then(|_| self.execute(
+                "DELETE FROM schema_migrations WHERE version = ?1",
+                [migration.version]
+            ))
+        };
+        match res {
+            Ok(_) => self.execute_batch("RELEASE migration")?,
+            Err(_) => self.execute_batch("ROLLBACK TO migration; RELEASE migration")?
+        }
+        res?;
+        Ok(())
+    }
+}
+
+// Applies every pending migration in order, returns the versions applied
+pub fn migrate(db: &mut dyn Migrate) -> Result<Vec<i64>, MigrationError> {
+    with_lock(db, |db| {
+        let applied = verify(db)?;
+        let mut done = Vec::new();
+        for migration in db.migrations().iter().filter(|m| !applied.iter().any(|a| a.version == m.version)) {
+            db.run(migration, true)?;
+            done.push(migration.version);
+        }
+        Ok(done)
+    })
+}
+
+// Reverts the most recently applied migrations, returns the versions reverted
+pub fn rollback(db: &mut dyn Migrate, steps: usize) -> Result<Vec<i64>, MigrationError> {
+    with_lock(db, |db| {
+        let applied = verify(db)?;
+        let mut done = Vec::new();
+        for applied in applied.iter().rev().take(steps) {
+            let migration = find(db, applied.version).ok_or_else(|| unknown(applied))?;
+            db.run(migration, false)?;
+            done.push(migration.version);
+        }
+        Ok(done)
+    })
+}
+
+pub fn status(db: &mut dyn Migrate) -> Result<Vec<MigrationStatus>, MigrationError> {
+    with_lock(db, |db| {
+        let applied = verify(db)?;
+        Ok(db.migrations().iter().map(|m| MigrationStatus {
+            version: m.version,
+            name: m.name,
+            applied_at: applied.iter().find(|a| a.version == m.version).map(|a| a.applied_at.clone()),
+        }).collect())
+    })
+}
+
+fn with_lock<T>(
+    db: &mut dyn Migrate,
+    f: impl FnOnce(&mut dyn Migrate) -> Result<T, MigrationError>
+) -> Result<T, MigrationError> {
+    db.lock()?;
+    let res = f(db);
+    db.unlock()?;
+    res
+}
+
+// Loads applied migrations and checks they still match the ones compiled in
+fn verify(db: &mut dyn Migrate) -> Result<Vec<AppliedMigration>, MigrationError> {
+    let applied = db.applied()?;
+    for a in &applied {
+        match find(db, a.version) {
+            Some(m) if m.checksum() != a.checksum =>
+                return Err(MigrationError::ChecksumMismatch { version: a.version, name: a.name.clone() }),
+            Some(_) => {},
+            None => return Err(unknown(a))
+        }
+    }
+    Ok(applied)
+}
+
+fn find(db: &dyn Migrate, version: i64) -> Option<&'static Migration> {
+    db.migrations().iter().find(|m| m.version == version)
+}
+
+fn unknown(a: &AppliedMigration) -> MigrationError {
+    MigrationError::Unknown { version: a.version, name: a.name.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SQLite runner with migrations of the test's own
+    struct TestDb {
+        conn: Connection,
+        migrations: &'static [Migration],
+    }
+
+    impl Migrate for TestDb {
+        fn migrations(&self) -> &'static [Migration] {
+            self.migrations
+        }
+
+        fn lock(&mut self) -> Result<(), MigrationError> {
+            self.conn.lock()
+        }
+
+        fn unlock(&mut self) -> Result<(), MigrationError> {
+            self.conn.unlock()
+        }
+
+        fn applied(&mut self) -> Result<Vec<AppliedMigration>, MigrationError> {
+            self.conn.applied()
+        }
+
+        fn run(&mut self, migration: &Migration, up: bool) -> Result<(), MigrationError> {
+            self.conn.run(migration, up)
+        }
+    }
+
+    fn migrated() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&mut conn).unwrap(), SQLITE_MIGRATIONS.iter().map(|m| m.version).collect::<Vec<_>>());
+        conn
+    }
+
+    fn applied(conn: &mut Connection) -> Vec<i64> {
+        conn.applied().unwrap().iter().map(|a| a.version).collect()
+    }
+
+    fn has_table(conn: &Connection, table: &str) -> bool {
+        conn.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1").unwrap().exists([table]).unwrap()
+    }
+
+    #[test]
+    fn migrates_once() {
+        let mut conn = migrated();
+        assert_eq!(migrate(&mut conn).unwrap(), Vec::<i64>::new());
+        assert!(status(&mut conn).unwrap().iter().all(|m| m.applied_at.is_some()));
+    }
+
+    #[test]
+    fn rejects_modified_migrations() {
+        let mut conn = migrated();
+        conn.execute("UPDATE schema_migrations SET checksum = 'edited' WHERE version = 2", []).unwrap();
+        assert!(matches!(migrate(&mut conn), Err(MigrationError::ChecksumMismatch { version: 2, .. })));
+        assert!(matches!(rollback(&mut conn, 1), Err(MigrationError::ChecksumMismatch { version: 2, .. })));
+        // Nothing was reverted
+        assert_eq!(applied(&mut conn).len(), SQLITE_MIGRATIONS.len());
+    }
+
+    #[test]
+    fn rejects_unknown_migrations() {
+        let mut conn = migrated();
+        conn.execute("INSERT INTO schema_migrations (version, name, checksum) VALUES (99, 'from_the_future', '')", []).unwrap();
+        match migrate(&mut conn) {
+            Err(e @ MigrationError::Unknown { version: 99, .. }) =>
+                assert_eq!(e.to_string(), "Migration 0099_from_the_future is applied but not known to this build"),
+            _ => panic!("expected an unknown migration")
+        }
+    }
+
+    #[test]
+    fn rolls_back_the_latest_steps() {
+        let mut conn = migrated();
+        let latest = SQLITE_MIGRATIONS.len() as i64;
+        assert_eq!(rollback(&mut conn, 2).unwrap(), vec![latest, latest - 1]);
+        assert_eq!(applied(&mut conn), (1..=latest - 2).collect::<Vec<_>>());
+        assert_eq!(rollback(&mut conn, 0).unwrap(), Vec::<i64>::new());
+
+        // Back up with only what was reverted, then all the way down
+        assert_eq!(migrate(&mut conn).unwrap(), vec![latest - 1, latest]);
+        assert_eq!(rollback(&mut conn, 100).unwrap(), (1..=latest).rev().collect::<Vec<_>>());
+        assert_eq!(applied(&mut conn), Vec::<i64>::new());
+        assert!(!has_table(&conn, "pets"));
+    }
+
+    #[test]
+    fn failed_migration_is_undone() {
+        const MIGRATIONS: &[Migration] = &[
+            Migration { version: 1, name: "first", up: "CREATE TABLE first (id INTEGER);", down: "DROP TABLE first;" },
+            Migration {
+                version: 2,
+                name: "broken",
+                up: "CREATE TABLE second (id INTEGER); INSERT INTO missing VALUES (1);",
+                down: "DROP TABLE second;",
+            },
+        ];
+        let mut db = TestDb { conn: Connection::open_in_memory().unwrap(), migrations: MIGRATIONS };
+        assert!(matches!(migrate(&mut db), Err(MigrationError::Sqlite(_))));
+
+        // The migrations before it stay applied, the failing one leaves nothing behind
+        assert_eq!(applied(&mut db.conn), vec![1]);
+        assert!(has_table(&db.conn, "first"));
+        assert!(!has_table(&db.conn, "second"));
+        assert!(db.conn.is_autocommit());
+    }
+
+    #[test]
+    fn drops_legacy_weights_that_are_not_positive() {
+        // The schema as it was before the generic pets table
+        let mut db = TestDb { conn: Connection::open_in_memory().unwrap(), migrations: &SQLITE_MIGRATIONS[..3] };
+        migrate(&mut db).unwrap();
+        db.conn.execute_batch(
+            "INSERT INTO apts (apt) VALUES (123);
+            INSERT INTO dogs (name, weight, breed, apt) VALUES ('Rex', -3, 'Beagle', 123), ('Ace', 40, 'Boxer', 123);
+            INSERT INTO cats (name, weight, hair, apt) VALUES ('Tom', 0, FALSE, 123);"
+        ).unwrap();
+
+        let mut conn = db.conn;
+        migrate(&mut conn).unwrap();
+        let weights: Vec<(String, Option<i64>)> = conn.prepare("SELECT name, json_extract(attributes, '$.weight') FROM pets ORDER BY name").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        assert_eq!(weights, vec![("Ace".to_string(), Some(40)), ("Rex".to_string(), None), ("Tom".to_string(), None)]);
+    }
+}
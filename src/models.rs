@@ -0,0 +1,1073 @@
+use crate::catalog::Catalog;
+use crate::species::SpeciesRegistry;
+use chrono::{DateTime, NaiveDate, SubsecRound, Utc};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+// A pet of any registered species. Everything besides the name is kept in attributes, which
+// have been checked against the species' fields by the time a Pet exists.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pet {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    pub animal: String,
+    pub name: String,
+    #[serde(default)]
+    pub attributes: Map<String, Value>,
+    // Id of the responsible tenant
+    #[serde(default, alias = "owner_id", skip_serializing_if = "Option::is_none")]
+    pub owner: Option<i32>,
+    // Unique among pets that haven't been deleted, see parse_microchip
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub microchip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assistance: Option<Assistance>,
+    #[serde(default)]
+    pub review: Review,
+    // Set while a deleted pet waits to be purged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Pet {
+    pub fn new(animal: &str, name: &str, attributes: Value) -> Pet {
+        Pet {
+            id: None,
+            animal: animal.to_string(),
+            name: name.to_string(),
+            attributes: match attributes {
+                Value::Object(attributes) => attributes,
+                _ => Map::new()
+            },
+            owner: None,
+            microchip: None,
+            assistance: None,
+            review: Review::default(),
+            deleted_at: None,
+        }
+    }
+
+    // Assistance animals don't count against policy limits or fees unless their designation was rejected
+    pub fn is_assistance(&self) -> bool {
+        self.assistance.as_ref().is_some_and(|a| a.verification != Verification::Rejected)
+    }
+
+    // Rejected and removed pets stay on record but no longer live in the apartment
+    pub fn is_active(&self) -> bool {
+        matches!(self.review.status, Status::Pending | Status::Approved)
+    }
+
+    pub fn text(&self, field: &str) -> Option<&str> {
+        self.attributes.get(field).and_then(Value::as_str)
+    }
+
+    pub fn int(&self, field: &str) -> Option<i64> {
+        self.attributes.get(field).and_then(Value::as_i64)
+    }
+
+    pub fn number(&self, field: &str) -> Option<f64> {
+        self.attributes.get(field).and_then(Value::as_f64)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightUnit {
+    Lb,
+    Kg,
+}
+
+impl WeightUnit {
+    pub const ALL: [WeightUnit; 2] = [WeightUnit::Lb, WeightUnit::Kg];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WeightUnit::Lb => "lb",
+            WeightUnit::Kg => "kg",
+        }
+    }
+
+    pub fn parse(unit: &str) -> Option<WeightUnit> {
+        WeightUnit::ALL.into_iter().find(|u| u.as_str() == unit)
+    }
+
+    fn pounds(&self) -> f64 {
+        match self {
+            WeightUnit::Lb => 1.0,
+            WeightUnit::Kg => 2.20462262185,
+        }
+    }
+}
+
+// A weight as it is given and shown. Weights are stored as pounds to two decimals, a bare
+// number is taken to be pounds like weights always were.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Weight {
+    pub value: f64,
+    pub unit: WeightUnit,
+}
+
+impl Weight {
+    pub fn pounds(lb: f64) -> Weight {
+        Weight { value: lb, unit: WeightUnit::Lb }
+    }
+
+    pub fn to_pounds(&self) -> f64 {
+        round_weight(self.value * self.unit.pounds())
+    }
+
+    pub fn to(&self, unit: WeightUnit) -> Weight {
+        Weight { value: round_weight(self.value * self.unit.pounds() / unit.pounds()), unit }
+    }
+}
+
+fn round_weight(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// Where a pet is in its registration review. New pets wait for a manager, an approved pet can
+// later be removed.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    #[default]
+    Pending,
+    Approved,
+    Rejected,
+    Removed,
+}
+
+impl Status {
+    pub const ALL: [Status; 4] = [Status::Pending, Status::Approved, Status::Rejected, Status::Removed];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Pending => "pending",
+            Status::Approved => "approved",
+            Status::Rejected => "rejected",
+            Status::Removed => "removed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Status> {
+        Status::ALL.into_iter().find(|s| s.as_str() == status)
+    }
+
+    pub fn can_become(&self, next: Status) -> bool {
+        matches!((self, next), (Status::Pending, Status::Approved | Status::Rejected) | (Status::Approved, Status::Removed))
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Review {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submitted_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reviewed_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reviewed_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+// A manager's decision on a pet, the body of a review request
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReviewDecision {
+    pub status: Status,
+    pub reviewer: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl ReviewDecision {
+    pub fn validate(&self) -> Result<(), String> {
+        let no_reason = self.reason.as_ref().is_none_or(|r| r.trim().is_empty());
+        match (self.status, self.reviewer.trim().is_empty()) {
+            (Status::Pending, _) => Err("Status must be approved, rejected or removed".to_string()),
+            (_, true) => Err("Reviewer can't be empty".to_string()),
+            (Status::Rejected | Status::Removed, _) if no_reason => Err(format!("A reason is required when a pet is {}", self.status)),
+            _ => Ok(())
+        }
+    }
+}
+
+// A service or emotional support animal, which fair-housing rules exempt from pet limits and fees
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Assistance {
+    pub kind: AssistanceKind,
+    #[serde(default)]
+    pub verification: Verification,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<Documentation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum AssistanceKind {
+    Service,
+    EmotionalSupport,
+}
+
+impl AssistanceKind {
+    pub const ALL: [AssistanceKind; 2] = [AssistanceKind::Service, AssistanceKind::EmotionalSupport];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssistanceKind::Service => "service",
+            AssistanceKind::EmotionalSupport => "emotional_support",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    #[default]
+    Pending,
+    Verified,
+    Rejected,
+}
+
+// Who vouches for the animal, e.g. the letter from a health professional
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Documentation {
+    pub provider: String,
+    pub issued: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+}
+
+// Pets of one apartment in registration order
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Pets(pub Vec<Pet>);
+
+impl Pets {
+    pub fn of<'a>(&'a self, animal: &'a str) -> impl Iterator<Item = &'a Pet> {
+        self.0.iter().filter(move |p| p.animal == animal)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Assistance documentation is only shown to managers
+    pub fn redacted(&self) -> Pets {
+        let mut pets = self.clone();
+        for assistance in pets.0.iter_mut().filter_map(|p| p.assistance.as_mut()) {
+            assistance.documentation = None;
+        }
+        pets
+    }
+
+    // Same shape the POST body uses, with numbers rendered as strings like weights always were
+    pub fn to_json(&self) -> Value {
+        let mut pets: Vec<Value> = Vec::new();
+        for p in &self.0 {
+            let mut pet = Map::new();
+            pet.insert("animal".to_string(), Value::String(p.animal.clone()));
+            insert_id(&mut pet, p.id);
+            pet.insert("name".to_string(), Value::String(p.name.clone()));
+            if let Some(owner) = p.owner {
+                pet.insert("owner".to_string(), Value::from(owner));
+            }
+            if let Some(microchip) = &p.microchip {
+                pet.insert("microchip".to_string(), Value::from(microchip.clone()));
+            }
+            if let Some(assistance) = &p.assistance {
+                pet.insert("assistance".to_string(), serde_json::to_value(assistance).unwrap());
+            }
+            pet.insert("review".to_string(), serde_json::to_value(&p.review).unwrap());
+            if let Some(deleted_at) = p.deleted_at {
+                pet.insert("deleted_at".to_string(), serde_json::to_value(deleted_at).unwrap());
+            }
+            for (field, value) in &p.attributes {
+                let value = match value {
+                    Value::Number(n) => Value::String(n.to_string()),
+                    value => value.clone()
+                };
+                pet.insert(field.clone(), value);
+            }
+            pets.push(Value::Object(pet));
+        }
+        Value::Array(pets)
+    }
+}
+
+// Pets only have an id once stored
+fn insert_id(pet: &mut Map<String, Value>, id: Option<i32>) {
+    if let Some(id) = id {
+        pet.insert("id".to_string(), Value::from(id));
+    }
+}
+
+// Apartments registered before buildings existed are units of this building
+pub const DEFAULT_BUILDING: &str = "default";
+
+// A unit of a building. /pets/{apt} addresses the unit named by the number in the default
+// building, /buildings/{building}/units/{unit}/pets any unit.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Apt {
+    pub building: String,
+    pub unit: String,
+}
+
+impl Apt {
+    pub fn new(building: &str, unit: &str) -> Apt {
+        Apt { building: building.to_string(), unit: unit.to_string() }
+    }
+
+    pub fn numbered(apt: i32) -> Apt {
+        Apt::new(DEFAULT_BUILDING, &apt.to_string())
+    }
+
+    // Units named by a plain number, the only ones apartment ranges apply to
+    pub fn number(&self) -> Option<i32> {
+        match !self.unit.is_empty() && self.unit.len() <= 9 && self.unit.bytes().all(|b| b.is_ascii_digit()) {
+            true => self.unit.parse().ok(),
+            false => None
+        }
+    }
+}
+
+// By building, then shorter units first so numbered units sort by number. The databases use
+// the same ORDER BY.
+impl Ord for Apt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.building, self.unit.len(), &self.unit).cmp(&(&other.building, other.unit.len(), &other.unit))
+    }
+}
+
+impl PartialOrd for Apt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Apt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.building == DEFAULT_BUILDING {
+            true => write!(f, "{}", self.unit),
+            false => write!(f, "{}/{}", self.building, self.unit)
+        }
+    }
+}
+
+// A 15 digit ISO 11784 number, or a 9 or 10 character legacy id in capitals. Spaces and
+// dashes people copy from tags and vet records are dropped.
+pub fn parse_microchip(microchip: &str) -> Option<String> {
+    let microchip: String = microchip.chars().filter(|c| *c != ' ' && *c != '-').collect();
+    match microchip.len() {
+        15 if microchip.chars().all(|c| c.is_ascii_digit()) => Some(microchip),
+        9 | 10 if microchip.chars().all(|c| c.is_ascii_alphanumeric()) => Some(microchip.to_ascii_uppercase()),
+        _ => None
+    }
+}
+
+// Building codes and unit names are part of urls, so they are kept to letters, digits and dashes
+pub fn is_valid_name(name: &str) -> bool {
+    (1..=32).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+// A building of a property. The property is created along with its first building.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Building {
+    pub code: String,
+    pub name: String,
+    pub property: String,
+}
+
+impl Building {
+    pub fn validate(&self) -> Result<(), String> {
+        match (is_valid_name(&self.code), self.name.trim().is_empty(), self.property.trim().is_empty()) {
+            (false, _, _) => Err("Building code must be 1 to 32 letters, digits or dashes".to_string()),
+            (_, true, _) => Err("Building name can't be empty".to_string()),
+            (_, _, true) => Err("Property can't be empty".to_string()),
+            _ => Ok(())
+        }
+    }
+}
+
+// One pet addressed the way the url does, /pets/{apt}/{animal}/{id}
+#[derive(Debug, Clone, PartialEq)]
+pub struct PetRef {
+    pub apt: Apt,
+    pub animal: String,
+    pub id: i32,
+}
+
+// Pets moving to another apartment, the body of a transfer request. The building may be left
+// out for the default one.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Transfer {
+    #[serde(default = "default_building")]
+    pub building: String,
+    pub unit: String,
+    pub pets: Vec<PetId>,
+}
+
+// A pet of the apartment a request is about
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(deny_unknown_fields)]
+pub struct PetId {
+    pub animal: String,
+    pub id: i32,
+}
+
+impl Transfer {
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_valid_name(&self.building) || !is_valid_name(&self.unit) {
+            return Err("Building and unit must be 1 to 32 letters, digits or dashes".to_string());
+        }
+        let distinct: BTreeSet<&PetId> = self.pets.iter().collect();
+        match (self.pets.is_empty(), distinct.len() < self.pets.len()) {
+            (true, _) => Err("Pets to transfer can't be empty".to_string()),
+            (_, true) => Err("Each pet can only be transferred once".to_string()),
+            _ => Ok(())
+        }
+    }
+
+    pub fn to(&self) -> Apt {
+        Apt::new(&self.building, &self.unit)
+    }
+
+    pub fn includes(&self, pet: &Pet) -> bool {
+        self.pets.iter().any(|p| p.animal == pet.animal && Some(p.id) == pet.id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Vaccination {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    pub vaccine: String,
+    pub given: NaiveDate,
+    pub expires: NaiveDate,
+    pub vet: String,
+}
+
+impl Vaccination {
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.vaccine.trim().is_empty(), self.expires < self.given) {
+            (true, _) => Err("Vaccine can't be empty".to_string()),
+            (_, true) => Err("Vaccination can't expire before it was given".to_string()),
+            _ => Ok(())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct License {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    pub number: String,
+    pub issued: NaiveDate,
+    pub expires: NaiveDate,
+}
+
+impl License {
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.number.trim().is_empty(), self.expires < self.issued) {
+            (true, _) => Err("License number can't be empty".to_string()),
+            (_, true) => Err("License can't expire before it was issued".to_string()),
+            _ => Ok(())
+        }
+    }
+}
+
+// Every record of one pet, oldest first
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct PetRecords {
+    pub vaccinations: Vec<Vaccination>,
+    pub licenses: Vec<License>,
+}
+
+// What an incident was about, its type
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IncidentType {
+    Noise,
+    Bite,
+    Damage,
+    Other,
+}
+
+impl IncidentType {
+    pub const ALL: [IncidentType; 4] = [IncidentType::Noise, IncidentType::Bite, IncidentType::Damage, IncidentType::Other];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IncidentType::Noise => "noise",
+            IncidentType::Bite => "bite",
+            IncidentType::Damage => "damage",
+            IncidentType::Other => "other",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<IncidentType> {
+        IncidentType::ALL.into_iter().find(|k| k.as_str() == kind)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    pub const ALL: [Severity; 3] = [Severity::Low, Severity::Medium, Severity::High];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        }
+    }
+
+    pub fn parse(severity: &str) -> Option<Severity> {
+        Severity::ALL.into_iter().find(|s| s.as_str() == severity)
+    }
+}
+
+// A noise complaint, bite, damage or anything else reported in a unit, about one of its pets or
+// the apartment as a whole. It is open until it has a resolution. Where it happened and the pet
+// are taken from the url rather than the body.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Incident {
+    #[serde(skip)]
+    pub id: Option<i32>,
+    #[serde(skip)]
+    pub apt: Apt,
+    #[serde(skip)]
+    pub pet: Option<PetId>,
+    #[serde(rename = "type")]
+    pub kind: IncidentType,
+    pub severity: Severity,
+    pub date: NaiveDate,
+    pub description: String,
+    pub reporter: String,
+    #[serde(default)]
+    pub resolution: Option<String>,
+    #[serde(default)]
+    pub resolved_on: Option<NaiveDate>,
+}
+
+impl Incident {
+    // An incident from before it was tracked here may be reported already resolved
+    pub fn validate(&self) -> Result<(), String> {
+        if self.description.trim().is_empty() {
+            return Err("Description can't be empty".to_string());
+        }
+        if self.reporter.trim().is_empty() {
+            return Err("Reporter can't be empty".to_string());
+        }
+        match (&self.resolution, self.resolved_on) {
+            (Some(resolution), Some(resolved_on)) => Resolution { resolution: resolution.clone(), resolved_on: Some(resolved_on) }.validate(self),
+            (None, None) => Ok(()),
+            _ => Err("Resolution and resolved_on go together".to_string())
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.resolution.is_none()
+    }
+
+    pub fn to_json(incidents: &[Incident]) -> Value {
+        Value::Array(incidents.iter().map(|incident| {
+            let mut json = serde_json::json!({
+                "building": incident.apt.building,
+                "unit": incident.apt.unit,
+                "type": incident.kind,
+                "severity": incident.severity,
+                "date": incident.date,
+                "description": incident.description,
+                "reporter": incident.reporter,
+                "status": if incident.is_open() { "open" } else { "resolved" },
+            });
+            if let Some(id) = incident.id {
+                json["id"] = Value::from(id);
+            }
+            if let Some(pet) = &incident.pet {
+                json["animal"] = Value::from(pet.animal.clone());
+                json["pet_id"] = Value::from(pet.id);
+            }
+            if let (Some(resolution), Some(resolved_on)) = (&incident.resolution, incident.resolved_on) {
+                json["resolution"] = Value::from(resolution.clone());
+                json["resolved_on"] = serde_json::to_value(resolved_on).unwrap();
+            }
+            json
+        }).collect())
+    }
+}
+
+// The body of a resolve request, resolved today unless the date is given
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Resolution {
+    pub resolution: String,
+    #[serde(default)]
+    pub resolved_on: Option<NaiveDate>,
+}
+
+impl Resolution {
+    pub fn validate(&self, incident: &Incident) -> Result<(), String> {
+        match (self.resolution.trim().is_empty(), self.resolved_on.is_some_and(|on| on < incident.date)) {
+            (true, _) => Err("Resolution can't be empty".to_string()),
+            (_, true) => Err("An incident can't be resolved before it happened".to_string()),
+            _ => Ok(())
+        }
+    }
+}
+
+// The current vaccination or license of a pet that has run out or is about to. Detail is the
+// vaccine or the license number.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ExpiringRecord {
+    #[serde(flatten)]
+    pub apt: Apt,
+    pub animal: String,
+    pub id: i32,
+    pub name: String,
+    pub record: String,
+    pub detail: String,
+    pub expires: NaiveDate,
+}
+
+// A tenant and every apartment they have lived in, oldest move-in first
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    pub name: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub phone: Option<String>,
+    #[serde(default)]
+    pub tenancies: Vec<Tenancy>,
+}
+
+// A tenancy without move_out is ongoing. The building may be left out for the default one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Tenancy {
+    #[serde(default = "default_building")]
+    pub building: String,
+    pub unit: String,
+    pub move_in: NaiveDate,
+    #[serde(default)]
+    pub move_out: Option<NaiveDate>,
+}
+
+fn default_building() -> String {
+    DEFAULT_BUILDING.to_string()
+}
+
+impl Tenancy {
+    pub fn apt(&self) -> Apt {
+        Apt::new(&self.building, &self.unit)
+    }
+}
+
+impl Tenant {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Tenant name can't be empty".to_string());
+        }
+        if self.email.as_ref().is_some_and(|email| !email.contains('@')) {
+            return Err("Email must contain @".to_string());
+        }
+        if self.tenancies.iter().any(|t| !is_valid_name(&t.building) || !is_valid_name(&t.unit)) {
+            return Err("Tenancy building and unit must be 1 to 32 letters, digits or dashes".to_string());
+        }
+        match self.tenancies.iter().any(|t| t.move_out.is_some_and(|out| out < t.move_in)) {
+            true => Err("Move-out can't be before move-in".to_string()),
+            false => Ok(())
+        }
+    }
+
+    pub fn lives_in(&self, apt: &Apt, on: NaiveDate) -> bool {
+        self.tenancies.iter().any(|t| t.building == apt.building && t.unit == apt.unit && t.move_in <= on && t.move_out.is_none_or(|out| out >= on))
+    }
+}
+
+// Pet counts are keyed by animal, species the apartment has none of are left out
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ApartmentSummary {
+    #[serde(flatten)]
+    pub apt: Apt,
+    pub pets: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+    pub descending: bool,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page { limit: 20, offset: 0, descending: false }
+    }
+}
+
+// Pets of one apartment, as returned by searches across the building
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ApartmentPets {
+    pub apt: Apt,
+    pub pets: Pets,
+}
+
+impl ApartmentPets {
+    // Flattens a list of apartments into one array of pets, each tagged with its building and unit
+    pub fn to_json(apartments: &[ApartmentPets]) -> Value {
+        let mut pets = Vec::new();
+        for apartment in apartments {
+            if let Value::Array(apt_pets) = apartment.pets.to_json() {
+                for mut pet in apt_pets {
+                    if let Value::Object(pet) = &mut pet {
+                        pet.insert("building".to_string(), Value::from(apartment.apt.building.clone()));
+                        pet.insert("unit".to_string(), Value::from(apartment.apt.unit.clone()));
+                    }
+                    pets.push(pet);
+                }
+            }
+        }
+        Value::Array(pets)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Action {
+    pub const ALL: [Action; 3] = [Action::Create, Action::Update, Action::Delete];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Update => "update",
+            Action::Delete => "delete",
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|a| a.as_str() == action)
+    }
+}
+
+// Who a change is recorded under in the audit log. The name is who the request was
+// authenticated as, claimed is a name the requester gave that nothing vouches for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Actor {
+    pub name: String,
+    pub manager: bool,
+    pub claimed: Option<String>,
+}
+
+// One change in the audit log. A change to the whole apartment holds all of its pets before and
+// after, a change to one pet holds just that pet. There is nothing before a create or after a delete.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub id: Option<i64>,
+    pub at: DateTime<Utc>,
+    pub actor: String,
+    pub manager: bool,
+    pub claimed_actor: Option<String>,
+    pub action: Action,
+    pub apt: Apt,
+    // Animal and id of the pet that changed, None for changes to the whole apartment
+    pub pet: Option<(String, i32)>,
+    pub before: Option<Pets>,
+    pub after: Option<Pets>,
+}
+
+impl AuditEntry {
+    // A change made now, to the whole apartment or to one of its pets. Timestamps are kept to the
+    // microsecond the databases store.
+    pub fn new(actor: &Actor, action: Action, apt: &Apt, pet: Option<&PetRef>, before: Option<Pets>, after: Option<Pets>) -> AuditEntry {
+        AuditEntry {
+            id: None,
+            at: Utc::now().trunc_subsecs(6),
+            actor: actor.name.clone(),
+            manager: actor.manager,
+            claimed_actor: actor.claimed.clone(),
+            action,
+            apt: apt.clone(),
+            pet: pet.map(|pet| (pet.animal.clone(), pet.id)),
+            before,
+            after,
+        }
+    }
+
+    // Pets are shown the way GET shows them, redacted unless the requester is a manager
+    pub fn to_json(entries: &[AuditEntry], manager: bool) -> Value {
+        let pets = |pets: &Option<Pets>| match pets {
+            Some(pets) if manager => pets.to_json(),
+            Some(pets) => pets.redacted().to_json(),
+            None => Value::Null
+        };
+        Value::Array(entries.iter().map(|entry| {
+            let mut json = serde_json::json!({
+                "at": entry.at,
+                "actor": entry.actor,
+                "manager": entry.manager,
+                "claimed_actor": entry.claimed_actor,
+                "action": entry.action,
+                "building": entry.apt.building,
+                "unit": entry.apt.unit,
+                "before": pets(&entry.before),
+                "after": pets(&entry.after),
+            });
+            if let Some(id) = entry.id {
+                json["id"] = Value::from(id);
+            }
+            if let Some((animal, id)) = &entry.pet {
+                json["animal"] = Value::from(animal.clone());
+                json["pet_id"] = Value::from(*id);
+            }
+            json
+        }).collect())
+    }
+
+    // The apartment's pets after the given entries, oldest first, were applied. None when the
+    // apartment wasn't registered at that point.
+    pub fn replay(entries: &[AuditEntry]) -> Option<Pets> {
+        let mut pets: Option<Pets> = None;
+        for entry in entries {
+            match (&entry.pet, pets.as_mut()) {
+                (None, _) => pets = entry.after.clone(),
+                (Some((animal, id)), Some(pets)) => {
+                    let position = pets.0.iter().position(|p| p.animal == *animal && p.id == Some(*id));
+                    let after = entry.after.as_ref().and_then(|after| after.0.first());
+                    match (position, after) {
+                        (Some(i), Some(after)) => pets.0[i] = after.clone(),
+                        (Some(i), None) => { pets.0.remove(i); },
+                        (None, Some(after)) => pets.0.push(after.clone()),
+                        (None, None) => {}
+                    }
+                },
+                (Some(_), None) => {}
+            }
+        }
+        pets
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum NameMatch {
+    Prefix,
+    #[default]
+    Substring,
+}
+
+// Filters for searching pets across apartments. Attribute filters only match pets that have
+// the attribute, so breed=Labrador rules out cats and a weight range rules out birds.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PetSearch {
+    pub animal: Option<String>,
+    pub name: Option<String>,
+    pub name_match: NameMatch,
+    // Text attributes, compared case-insensitively
+    pub equals: Vec<(String, String)>,
+    // Inclusive bounds on number attributes, weights in pounds
+    pub min: Vec<(String, f64)>,
+    pub max: Vec<(String, f64)>,
+    pub building: Option<String>,
+    // Inclusive bounds on numbered units, other units never fall inside a range
+    pub from_apt: Option<i32>,
+    pub to_apt: Option<i32>,
+    pub status: Option<Status>,
+}
+
+impl PetSearch {
+    pub fn includes_apt(&self, apt: &Apt) -> bool {
+        let in_range = match (self.from_apt, self.to_apt, apt.number()) {
+            (None, None, _) => true,
+            (_, _, None) => false,
+            (from, to, Some(n)) => from.is_none_or(|from| n >= from) && to.is_none_or(|to| n <= to)
+        };
+        in_range && self.building.as_ref().is_none_or(|b| *b == apt.building)
+    }
+
+    pub fn matches(&self, pet: &Pet) -> bool {
+        self.animal.as_ref().is_none_or(|a| *a == pet.animal)
+            && self.status.is_none_or(|s| s == pet.review.status)
+            && self.name_matches(&pet.name)
+            && self.equals.iter().all(|(field, value)| pet.text(field).is_some_and(|v| v.to_lowercase() == value.to_lowercase()))
+            && self.min.iter().all(|(field, min)| pet.number(field).is_some_and(|v| v >= *min))
+            && self.max.iter().all(|(field, max)| pet.number(field).is_some_and(|v| v <= *max))
+    }
+
+    fn name_matches(&self, name: &str) -> bool {
+        match &self.name {
+            Some(search) => match self.name_match {
+                NameMatch::Prefix => name.to_lowercase().starts_with(&search.to_lowercase()),
+                NameMatch::Substring => name.to_lowercase().contains(&search.to_lowercase())
+            },
+            None => true
+        }
+    }
+
+    // LIKE pattern for the name filter, lowercased with wildcards in the input escaped
+    pub fn name_pattern(&self) -> Option<String> {
+        self.name.as_ref().map(|name| {
+            let escaped = name.to_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            match self.name_match {
+                NameMatch::Prefix => format!("{}%", escaped),
+                NameMatch::Substring => format!("%{}%", escaped)
+            }
+        })
+    }
+}
+
+// Request bodies are validated against the species registry, with breeds and bird species
+// stored by their catalog names
+pub fn get_pets_vecs(a: Value, species: &SpeciesRegistry, catalog: &Catalog) -> Result<Pets, String> {
+    catalog.normalize(species.parse_pets(a)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_animal() {
+        let pets = get_pets_vecs(serde_json::json!([
+            {"animal": "Dog", "name": "Sunny", "weight": 70, "breed": "Labrador"},
+            {"animal": "Cat", "name": "Fenrir", "weight": 7, "hair": "ShortHaired"},
+            {"animal": "Bird", "name": "Polly", "species": "Parrot"}
+        ]), &SpeciesRegistry::default(), &Catalog::default()).unwrap();
+        assert_eq!(pets, Pets(vec![
+            Pet::new("Dog", "Sunny", serde_json::json!({"weight": 70, "breed": "Labrador Retriever"})),
+            Pet::new("Cat", "Fenrir", serde_json::json!({"weight": 7, "hair": "ShortHaired"})),
+            Pet::new("Bird", "Polly", serde_json::json!({"species": "Parrot"})),
+        ]));
+        assert_eq!(pets.to_json()[0]["weight"], "70");
+    }
+
+    #[test]
+    fn orders_units_by_number() {
+        let mut apts = [Apt::new("north", "2"), Apt::numbered(110), Apt::new("north", "12B"), Apt::numbered(9), Apt::new("north", "10")];
+        apts.sort();
+        assert_eq!(apts.iter().map(Apt::to_string).collect::<Vec<_>>(), vec!["9", "110", "north/2", "north/10", "north/12B"]);
+        assert_eq!(Apt::new("north", "007").number(), Some(7));
+        assert_eq!(Apt::new("north", "PH-2").number(), None);
+        assert!(!is_valid_name("12/B") && is_valid_name("PH-2"));
+    }
+
+    #[test]
+    fn rejects_malformed_pets() {
+        let parse = |body: Value| get_pets_vecs(body, &SpeciesRegistry::default(), &Catalog::default());
+        assert_eq!(parse(serde_json::json!({})), Err("Json must be array".to_string()));
+        assert_eq!(parse(serde_json::json!([{"name": "Rex"}])), Err("Each pet requires animal field".to_string()));
+        assert_eq!(parse(serde_json::json!([{"animal": "Fish"}])), Err("Invalid pet type".to_string()));
+        assert_eq!(parse(serde_json::json!([{"animal": "Dog", "name": "Rex"}])), Err("Dogs require weight field".to_string()));
+        assert_eq!(
+            parse(serde_json::json!([{"animal": "Dog", "name": "Rex", "weight": "heavy", "breed": "Boxer"}])),
+            Err("Weight field must be a number of pounds or a value with a unit of lb or kg".to_string())
+        );
+        assert_eq!(
+            parse(serde_json::json!([{"animal": "Dog", "name": "Rex", "weight": {"value": 12, "unit": "stone"}, "breed": "Boxer"}])),
+            Err("Weight field must be a number of pounds or a value with a unit of lb or kg".to_string())
+        );
+        assert_eq!(
+            parse(serde_json::json!([{"animal": "Dog", "name": "Rex", "weight": -5, "breed": "Boxer"}])),
+            Err("Weight field must be more than 0".to_string())
+        );
+        assert_eq!(
+            parse(serde_json::json!([{"animal": "Cat", "name": "Nova", "weight": {"value": 30, "unit": "kg"}, "hair": "LongHaired"}])),
+            Err("Weight field must be at most 40 lb".to_string())
+        );
+        assert_eq!(
+            parse(serde_json::json!([{"animal": "Cat", "name": "Nova", "weight": 13, "hair": "Bald"}])),
+            Err("Hair field must be either LongHaired or ShortHaired".to_string())
+        );
+    }
+
+    #[test]
+    fn weights_are_stored_in_pounds() {
+        let pets = get_pets_vecs(serde_json::json!([
+            {"animal": "Dog", "name": "Sunny", "weight": {"value": 31.75, "unit": "kg"}, "breed": "Labrador"},
+            {"animal": "Cat", "name": "Fenrir", "weight": 7.5, "hair": "ShortHaired"},
+            {"animal": "Cat", "name": "Nova", "weight": {"value": 13, "unit": "lb"}, "hair": "LongHaired"}
+        ]), &SpeciesRegistry::default(), &Catalog::default()).unwrap();
+        assert_eq!(pets.0[0].attributes["weight"], serde_json::json!(70));
+        assert_eq!(pets.0[1].attributes["weight"], serde_json::json!(7.5));
+        assert_eq!(pets.0[2].attributes["weight"], serde_json::json!(13));
+
+        assert_eq!(Weight::pounds(70.0).to(WeightUnit::Kg), Weight { value: 31.75, unit: WeightUnit::Kg });
+        assert_eq!(Weight { value: 3.4, unit: WeightUnit::Kg }.to_pounds(), 7.5);
+        assert_eq!(Weight { value: 3.4, unit: WeightUnit::Kg }.to(WeightUnit::Kg).value, 3.4);
+    }
+
+    #[test]
+    fn parses_microchips() {
+        assert_eq!(parse_microchip("985112003456789"), Some("985112003456789".to_string()));
+        assert_eq!(parse_microchip("985 112 003 456 789"), Some("985112003456789".to_string()));
+        assert_eq!(parse_microchip("0a12-3c4d5e"), Some("0A123C4D5E".to_string()));
+        assert_eq!(parse_microchip("AVID12345"), Some("AVID12345".to_string()));
+        assert_eq!(parse_microchip("98511200345678A"), None);
+        assert_eq!(parse_microchip("9851120034567890"), None);
+        assert_eq!(parse_microchip("12345678"), None);
+        assert_eq!(parse_microchip("0A12_3C4D5E"), None);
+    }
+
+    #[test]
+    fn replays_history() {
+        let pet = |id: i32, name: &str| Pet { id: Some(id), ..Pet::new("Cat", name, serde_json::json!({"weight": 7, "hair": "ShortHaired"})) };
+        let entry = |action: Action, pet_id: Option<i32>, before: Option<Vec<Pet>>, after: Option<Vec<Pet>>| AuditEntry {
+            id: None,
+            at: Utc::now(),
+            actor: "anonymous".to_string(),
+            manager: false,
+            claimed_actor: Some("front desk".to_string()),
+            action,
+            apt: Apt::numbered(1),
+            pet: pet_id.map(|id| ("Cat".to_string(), id)),
+            before: before.map(Pets),
+            after: after.map(Pets),
+        };
+        let reviewed = Pet { review: Review { status: Status::Approved, ..Review::default() }, ..pet(1, "Nova") };
+        let mut entries = vec![
+            entry(Action::Create, None, None, Some(vec![pet(1, "Nova"), pet(2, "Fenrir")])),
+            entry(Action::Update, Some(1), Some(vec![pet(1, "Nova")]), Some(vec![reviewed.clone()])),
+        ];
+        assert_eq!(AuditEntry::replay(&entries[..1]), Some(Pets(vec![pet(1, "Nova"), pet(2, "Fenrir")])));
+        assert_eq!(AuditEntry::replay(&entries), Some(Pets(vec![reviewed, pet(2, "Fenrir")])));
+
+        entries.push(entry(Action::Delete, None, Some(vec![]), None));
+        assert_eq!(AuditEntry::replay(&entries), None);
+        assert_eq!(AuditEntry::replay(&[]), None);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::models::{Apt, PetRef};
+use image::{DynamicImage, ImageFormat};
+use std::fmt;
+use std::io::{self, Cursor};
+use std::path::PathBuf;
+
+pub const DEFAULT_MAX_BYTES: usize = 5 * 1024 * 1024;
+// Longest side of a thumbnail in pixels
+const THUMBNAIL_SIZE: u32 = 200;
+
+// Pet photos kept in a local directory, one {animal}-{id}.photo per pet with a JPEG thumbnail
+// next to it. Pet ids are unique per animal so the apartment isn't part of the name.
+#[derive(Debug, Clone)]
+pub struct PhotoStore {
+    dir: PathBuf,
+    max_bytes: usize,
+}
+
+// What was stored, the content type comes from the bytes rather than what the client claimed
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Photo {
+    pub content_type: &'static str,
+    pub bytes: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug)]
+pub enum PhotoError {
+    TooLarge(usize),
+    Unsupported,
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for PhotoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhotoError::TooLarge(max) => write!(f, "Photo must be at most {} bytes", max),
+            PhotoError::Unsupported => write!(f, "Photo must be a JPEG, PNG, GIF or WebP image"),
+            PhotoError::Invalid(e) => write!(f, "Photo could not be read: {}", e),
+            PhotoError::Io(e) => write!(f, "Could not store photo: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for PhotoError {
+    fn from(e: io::Error) -> Self {
+        PhotoError::Io(e)
+    }
+}
+
+impl PhotoStore {
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: usize) -> PhotoStore {
+        PhotoStore { dir: dir.into(), max_bytes }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    // Replaces the pet's photo. The thumbnail is written first and both files are renamed into
+    // place, so a reader never sees half a photo.
+    pub fn save(&self, pet: &PetRef, data: &[u8]) -> Result<Photo, PhotoError> {
+        if data.len() > self.max_bytes {
+            return Err(PhotoError::TooLarge(self.max_bytes));
+        }
+        let (format, content_type) = sniff(data).ok_or(PhotoError::Unsupported)?;
+        let image = image::load_from_memory_with_format(data, format).map_err(|e| PhotoError::Invalid(e.to_string()))?;
+
+        let mut thumbnail = Vec::new();
+        DynamicImage::ImageRgb8(image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8())
+            .write_to(&mut Cursor::new(&mut thumbnail), ImageFormat::Jpeg)
+            .map_err(|e| PhotoError::Invalid(e.to_string()))?;
+
+        std::fs::create_dir_all(&self.dir)?;
+        self.write(&self.path(pet, true), &thumbnail)?;
+        self.write(&self.path(pet, false), data)?;
+        Ok(Photo { content_type, bytes: data.len(), width: image.width(), height: image.height() })
+    }
+
+    // The photo or its thumbnail with its content type, None when the pet has no photo
+    pub fn open(&self, pet: &PetRef, thumbnail: bool) -> Result<Option<(&'static str, Vec<u8>)>, PhotoError> {
+        match std::fs::read(self.path(pet, thumbnail)) {
+            Ok(data) => match sniff(&data) {
+                Some((_, content_type)) => Ok(Some((content_type, data))),
+                None => Err(PhotoError::Unsupported)
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    pub fn remove(&self, animal: &str, id: i32) -> io::Result<()> {
+        let pet = PetRef { apt: Apt::default(), animal: animal.to_string(), id };
+        for path in [self.path(&pet, false), self.path(&pet, true)] {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, pet: &PetRef, thumbnail: bool) -> PathBuf {
+        let extension = match thumbnail {
+            true => "thumb.jpg",
+            false => "photo"
+        };
+        self.dir.join(format!("{}-{}.{}", file_name(&pet.animal), pet.id, extension))
+    }
+
+    fn write(&self, path: &PathBuf, data: &[u8]) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+// Species come from a config file, anything but letters and digits is escaped so a name can't
+// reach outside the directory
+fn file_name(animal: &str) -> String {
+    animal.chars().map(|c| match c.is_ascii_alphanumeric() {
+        true => c.to_string(),
+        false => c.to_string().bytes().map(|b| format!("%{:02X}", b)).collect()
+    }).collect()
+}
+
+// Tells the format from the magic bytes, only formats browsers can show are accepted
+fn sniff(data: &[u8]) -> Option<(ImageFormat, &'static str)> {
+    match image::guess_format(data).ok()? {
+        ImageFormat::Jpeg => Some((ImageFormat::Jpeg, "image/jpeg")),
+        ImageFormat::Png => Some((ImageFormat::Png, "image/png")),
+        ImageFormat::Gif => Some((ImageFormat::Gif, "image/gif")),
+        ImageFormat::WebP => Some((ImageFormat::WebP, "image/webp")),
+        _ => None
+    }
+}
+
+// Contents of the named field of a multipart/form-data body
+pub fn multipart_field<'a>(content_type: &str, body: &'a [u8], name: &str) -> Result<&'a [u8], String> {
+    let boundary = content_type.split(';').map(str::trim)
+        .find_map(|param| param.strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"'));
+    let delimiter = match (content_type.trim().to_lowercase().starts_with("multipart/form-data"), boundary) {
+        (true, Some(boundary)) if !boundary.is_empty() => format!("--{}", boundary),
+        _ => return Err("Photo must be uploaded as multipart/form-data".to_string())
+    };
+    let delimiter = delimiter.as_bytes();
+    let malformed = || "Malformed multipart body".to_string();
+
+    let mut start = find(body, delimiter, 0).ok_or_else(malformed)? + delimiter.len();
+    // A delimiter followed by "--" closes the body
+    while !body[start..].starts_with(b"--") {
+        let end = find(body, delimiter, start).ok_or_else(malformed)?;
+        let part = &body[start..end];
+        let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+        let part = part.strip_suffix(b"\r\n").unwrap_or(part);
+        let split = find(part, b"\r\n\r\n", 0).ok_or_else(malformed)?;
+
+        let field = format!("name=\"{}\"", name);
+        let headers = String::from_utf8_lossy(&part[..split]);
+        let is_field = headers.lines()
+            .filter(|header| header.to_lowercase().starts_with("content-disposition:"))
+            .any(|header| header.split(';').any(|param| param.trim() == field));
+        if is_field {
+            return Ok(&part[split + 4..]);
+        }
+        start = end + delimiter.len();
+    }
+    Err(format!("Multipart body requires a {} field", name))
+}
+
+pub fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack.get(from..)?.windows(needle.len()).position(|w| w == needle).map(|i| i + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_multipart_field() {
+        let body = b"--XyZ\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhello\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\n\x89PNG\r\n\x00\r\n\
+            --XyZ--\r\n";
+        let content_type = "multipart/form-data; boundary=\"XyZ\"";
+        assert_eq!(multipart_field(content_type, body, "photo"), Ok(&b"\x89PNG\r\n\x00"[..]));
+        assert_eq!(multipart_field(content_type, body, "note"), Ok(&b"hello"[..]));
+        assert_eq!(multipart_field(content_type, body, "file"), Err("Multipart body requires a file field".to_string()));
+        assert!(multipart_field("application/json", body, "photo").is_err());
+        assert_eq!(multipart_field(content_type, b"--XyZ\r\nno headers", "photo"), Err("Malformed multipart body".to_string()));
+    }
+
+    #[test]
+    fn escapes_species_in_file_names() {
+        assert_eq!(file_name("Dog"), "Dog");
+        assert_eq!(file_name("../Guinea pig"), "%2E%2E%2FGuinea%20pig");
+    }
+}
@@ -0,0 +1,211 @@
+use crate::models::{Pet, Pets};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+
+// Lease rules every apartment's pets are checked against, an empty policy allows anything.
+// Per-species rules are keyed by animal, e.g. "Dog". Weight and restricted breeds or species
+// apply to any species with a weight, breed or species field, weights in pounds. Assistance
+// animals are only held to the name formats, rejected and removed pets to nothing.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub max_pets: Option<usize>,
+    pub max_per_species: HashMap<String, usize>,
+    pub weight: HashMap<String, WeightLimit>,
+    pub restricted_breeds: Vec<String>,
+    pub restricted_species: Vec<String>,
+    // Names must match at least one of these patterns
+    pub name_formats: Vec<String>,
+    #[serde(skip)]
+    name_patterns: Vec<Regex>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct WeightLimit {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Violation {
+    pub rule: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub animal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum PolicyError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    NameFormat(regex::Error),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::Io(e) => write!(f, "Could not read policy file: {}", e),
+            PolicyError::Parse(e) => write!(f, "Invalid policy: {}", e),
+            PolicyError::NameFormat(e) => write!(f, "Invalid name format: {}", e),
+        }
+    }
+}
+
+impl Policy {
+    pub fn load(path: &str) -> Result<Policy, PolicyError> {
+        Policy::from_json(&std::fs::read_to_string(path).map_err(PolicyError::Io)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Policy, PolicyError> {
+        let mut policy: Policy = serde_json::from_str(json).map_err(PolicyError::Parse)?;
+        // Anchored so a format has to match the whole name
+        policy.name_patterns = policy.name_formats.iter()
+            .map(|format| Regex::new(&format!("^(?:{})$", format)))
+            .collect::<Result<_, _>>()
+            .map_err(PolicyError::NameFormat)?;
+        Ok(policy)
+    }
+
+    // Every rule the pets break, empty when they are allowed
+    pub fn evaluate(&self, pets: &Pets) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        let counted = |p: &&Pet| p.is_active() && !p.is_assistance();
+        let total = pets.0.iter().filter(counted).count();
+        if let Some(max) = self.max_pets.filter(|&max| total > max) {
+            violations.push(Violation {
+                rule: "max_pets",
+                animal: None,
+                name: None,
+                message: format!("At most {} pets are allowed per apartment, got {}", max, total),
+            });
+        }
+
+        let mut animals: Vec<&str> = self.max_per_species.keys().map(String::as_str).collect();
+        animals.sort();
+        for animal in animals {
+            let (max, count) = (self.max_per_species[animal], pets.of(animal).filter(counted).count());
+            if count > max {
+                violations.push(Violation {
+                    rule: "max_per_species",
+                    animal: Some(animal.to_string()),
+                    name: None,
+                    message: format!("At most {} {}s are allowed per apartment, got {}", max, animal, count),
+                });
+            }
+        }
+
+        for pet in pets.0.iter().filter(|p| p.is_active()) {
+            self.check_name(&mut violations, pet);
+            if pet.is_assistance() {
+                continue;
+            }
+            self.check_weight(&mut violations, pet);
+            if let Some(breed) = pet.text("breed").filter(|b| is_listed(&self.restricted_breeds, b)) {
+                violations.push(violation(pet, "restricted_breeds", format!("{} is a restricted breed", breed)));
+            }
+            if let Some(species) = pet.text("species").filter(|s| is_listed(&self.restricted_species, s)) {
+                violations.push(violation(pet, "restricted_species", format!("{} is a restricted species", species)));
+            }
+        }
+
+        violations
+    }
+
+    fn check_name(&self, violations: &mut Vec<Violation>, pet: &Pet) {
+        if !self.name_patterns.is_empty() && !self.name_patterns.iter().any(|p| p.is_match(&pet.name)) {
+            violations.push(violation(pet, "name_formats", format!("{} does not match an allowed name format", pet.name)));
+        }
+    }
+
+    fn check_weight(&self, violations: &mut Vec<Violation>, pet: &Pet) {
+        let (limit, weight) = match (self.weight.get(&pet.animal), pet.number("weight")) {
+            (Some(limit), Some(weight)) => (limit, weight),
+            _ => return
+        };
+        if let Some(max) = limit.max.filter(|&max| weight > max) {
+            violations.push(violation(pet, "weight", format!("{}s may weigh at most {} lb, {} weighs {} lb", pet.animal, max, pet.name, weight)));
+        }
+        if let Some(min) = limit.min.filter(|&min| weight < min) {
+            violations.push(violation(pet, "weight", format!("{}s must weigh at least {} lb, {} weighs {} lb", pet.animal, min, pet.name, weight)));
+        }
+    }
+}
+
+fn violation(pet: &Pet, rule: &'static str, message: String) -> Violation {
+    Violation { rule, animal: Some(pet.animal.clone()), name: Some(pet.name.clone()), message }
+}
+
+fn is_listed(list: &[String], value: &str) -> bool {
+    list.iter().any(|item| item.eq_ignore_ascii_case(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Assistance, AssistanceKind, Verification};
+    use serde_json::json;
+
+    const POLICY: &str = r#"{
+        "max_pets": 3,
+        "max_per_species": {"Dog": 2},
+        "weight": {"Dog": {"max": 80}},
+        "restricted_breeds": ["Pit Bull"],
+        "restricted_species": ["Macaw"],
+        "name_formats": ["[A-Z][a-z]+"]
+    }"#;
+
+    fn dog(name: &str, weight: i64, breed: &str) -> Pet {
+        Pet::new("Dog", name, json!({"weight": weight, "breed": breed}))
+    }
+
+    #[test]
+    fn reports_each_failed_rule() {
+        let policy = Policy::from_json(POLICY).unwrap();
+        let pets = Pets(vec![
+            dog("Sunny", 70, "Labrador"),
+            dog("Bruno", 95, "pit bull"),
+            dog("rex", 20, "Beagle"),
+            Pet::new("Bird", "Polly", json!({"species": "Macaw"})),
+        ]);
+
+        let rules: Vec<_> = policy.evaluate(&pets).iter().map(|v| (v.rule, v.name.clone())).collect();
+        assert_eq!(rules, vec![
+            ("max_pets", None),
+            ("max_per_species", None),
+            ("weight", Some("Bruno".to_string())),
+            ("restricted_breeds", Some("Bruno".to_string())),
+            ("name_formats", Some("rex".to_string())),
+            ("restricted_species", Some("Polly".to_string())),
+        ]);
+
+        assert!(policy.evaluate(&Pets(vec![dog("Sunny", 70, "Labrador")])).is_empty());
+    }
+
+    #[test]
+    fn assistance_animals_are_exempt() {
+        let policy = Policy::from_json(POLICY).unwrap();
+        let assistance = |verification| Some(Assistance { kind: AssistanceKind::Service, verification, documentation: None });
+        let mut pets = Pets(vec![
+            dog("Sunny", 70, "Labrador"),
+            dog("Paris", 60, "Poodle"),
+            Pet { assistance: assistance(Verification::Pending), ..dog("Bruno", 95, "Pit Bull") },
+        ]);
+        assert!(policy.evaluate(&pets).is_empty());
+
+        pets.0[2].assistance = assistance(Verification::Rejected);
+        let rules: Vec<_> = policy.evaluate(&pets).iter().map(|v| v.rule).collect();
+        assert_eq!(rules, vec!["max_per_species", "weight", "restricted_breeds"]);
+        assert!(Policy::default().evaluate(&pets).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        assert!(matches!(Policy::from_json(r#"{"max_dogs": 2}"#), Err(PolicyError::Parse(_))));
+        assert!(matches!(Policy::from_json(r#"{"name_formats": ["[a-"]}"#), Err(PolicyError::NameFormat(_))));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::models::{parse_microchip, Assistance, Pet, Pets, Review, Weight, WeightUnit};
+use serde_json::{Map, Value};
+use std::fmt;
+
+// The animals the API has always accepted, more can be added through a species file
+const BUILTIN_SPECIES: &str = r#"[
+    {"name": "Dog", "fields": [
+        {"name": "weight", "type": "weight", "min": 1, "max": 250},
+        {"name": "breed", "type": "string"}
+    ]},
+    {"name": "Cat", "fields": [
+        {"name": "weight", "type": "weight", "min": 1, "max": 40},
+        {"name": "hair", "type": "enum", "values": ["LongHaired", "ShortHaired"]}
+    ]},
+    {"name": "Bird", "fields": [
+        {"name": "species", "type": "string"}
+    ]}
+]"#;
+
+// Every animal type a pet can be, with the fields each one takes besides its name
+#[derive(Debug, Clone)]
+pub struct SpeciesRegistry {
+    species: Vec<Species>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Species {
+    pub name: String,
+    #[serde(default)]
+    pub fields: Vec<Field>,
+}
+
+// Bounds only apply to the matching type: min/max to integers and weights, in pounds for the
+// latter, max_length to strings and values to enums
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: FieldType,
+    #[serde(default = "required")]
+    pub required: bool,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+fn required() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    String,
+    Integer,
+    Boolean,
+    Enum,
+    Weight,
+}
+
+#[derive(Debug)]
+pub enum SpeciesError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for SpeciesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpeciesError::Io(e) => write!(f, "Could not read species file: {}", e),
+            SpeciesError::Parse(e) => write!(f, "Invalid species file: {}", e),
+            SpeciesError::Invalid(e) => write!(f, "Invalid species file: {}", e),
+        }
+    }
+}
+
+impl Default for SpeciesRegistry {
+    fn default() -> Self {
+        SpeciesRegistry { species: serde_json::from_str(BUILTIN_SPECIES).unwrap() }
+    }
+}
+
+impl SpeciesRegistry {
+    pub fn load(path: &str) -> Result<SpeciesRegistry, SpeciesError> {
+        SpeciesRegistry::from_json(&std::fs::read_to_string(path).map_err(SpeciesError::Io)?)
+    }
+
+    // Adds the species to the built-in ones, a species with a built-in name replaces it
+    pub fn from_json(json: &str) -> Result<SpeciesRegistry, SpeciesError> {
+        let mut registry = SpeciesRegistry::default();
+        for species in serde_json::from_str::<Vec<Species>>(json).map_err(SpeciesError::Parse)? {
+            species.check()?;
+            match registry.species.iter_mut().find(|s| s.name == species.name) {
+                Some(existing) => *existing = species,
+                None => registry.species.push(species)
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, animal: &str) -> Option<&Species> {
+        self.species.iter().find(|s| s.name == animal)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.species.iter().map(|s| s.name.as_str())
+    }
+
+    // First declaration of a field across species, used to tell what a search filter means
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.species.iter().flat_map(|s| &s.fields).find(|f| f.name == name)
+    }
+
+    // Weight fields shown as a value with a unit, in the one the caller asked for
+    pub fn in_unit(&self, pets: &Pets, unit: WeightUnit) -> Pets {
+        let mut pets = pets.clone();
+        for pet in pets.0.iter_mut() {
+            let fields = self.get(&pet.animal).map(|s| s.fields.as_slice()).unwrap_or_default();
+            for field in fields.iter().filter(|f| f.kind == FieldType::Weight) {
+                if let Some(lb) = pet.number(&field.name) {
+                    pet.attributes.insert(field.name.clone(), serde_json::to_value(Weight::pounds(lb).to(unit)).unwrap());
+                }
+            }
+        }
+        pets
+    }
+
+    // Validates a request body against the registry, every pet must be of a registered species
+    pub fn parse_pets(&self, body: Value) -> Result<Pets, String> {
+        match body {
+            Value::Array(body) => {
+                let mut pets = Vec::new();
+                for pet in body {
+                    match pet {
+                        Value::Object(pet) => {
+                            match pet.get("animal") {
+                                Some(animal) => {
+                                    match animal.as_str().and_then(|a| self.get(a)) {
+                                        Some(species) => pets.push(species.parse(&pet)?),
+                                        None => return Err("Invalid pet type".to_string())
+                                    }
+                                },
+                                None => return Err("Each pet requires animal field".to_string())
+                            }
+                        },
+                        _ => return Err("Object is not pet".to_string())
+                    }
+                }
+                Ok(Pets(pets))
+            },
+            _ => Err("Json must be array".to_string())
+        }
+    }
+}
+
+impl Species {
+    fn check(&self) -> Result<(), SpeciesError> {
+        if self.name.is_empty() {
+            return Err(SpeciesError::Invalid("Species name can't be empty".to_string()));
+        }
+        for field in &self.fields {
+            if matches!(field.name.as_str(), "id" | "animal" | "name" | "apt" | "owner" | "microchip" | "assistance" | "review" | "deleted_at" | "open_incidents") {
+                return Err(SpeciesError::Invalid(format!("{} can't be used as a field name", field.name)));
+            }
+            if field.kind == FieldType::Enum && field.values.is_empty() {
+                return Err(SpeciesError::Invalid(format!("Enum field {} needs values", field.name)));
+            }
+        }
+        Ok(())
+    }
+
+    // Fields that aren't declared are dropped, optional fields may be left out. Any pet may name
+    // its owner, have a microchip and be designated an assistance animal.
+    fn parse(&self, pet: &Map<String, Value>) -> Result<Pet, String> {
+        let name = match pet.get("name") {
+            Some(n) => match n.as_str() {
+                Some(n) => n.to_string(),
+                None => return Err("Name field must be string".to_string())
+            },
+            None => return Err(format!("{}s require name field", self.name))
+        };
+
+        let owner = match pet.get("owner") {
+            Some(owner) => match owner.as_i64().and_then(|o| i32::try_from(o).ok()) {
+                Some(owner) => Some(owner),
+                None => return Err("Owner field must be a tenant id".to_string())
+            },
+            None => None
+        };
+
+        let microchip = match pet.get("microchip") {
+            Some(microchip) => match microchip.as_str().and_then(parse_microchip) {
+                Some(microchip) => Some(microchip),
+                None => return Err("Microchip field must be a 15 digit ISO number or a 9 or 10 character legacy id".to_string())
+            },
+            None => None
+        };
+
+        let assistance = match pet.get("assistance") {
+            Some(assistance) => match serde_json::from_value::<Assistance>(assistance.clone()) {
+                Ok(assistance) => Some(assistance),
+                Err(e) => return Err(format!("Invalid assistance: {}", e))
+            },
+            None => None
+        };
+
+        let mut attributes = Map::new();
+        for field in &self.fields {
+            match pet.get(&field.name) {
+                Some(value) => {
+                    attributes.insert(field.name.clone(), field.validate(value)?);
+                },
+                None if field.required => return Err(format!("{}s require {} field", self.name, field.name)),
+                None => {}
+            }
+        }
+
+        Ok(Pet { id: None, animal: self.name.clone(), name, attributes, owner, microchip, assistance, review: Review::default(), deleted_at: None })
+    }
+}
+
+impl Field {
+    fn validate(&self, value: &Value) -> Result<Value, String> {
+        let label = capitalize(&self.name);
+        match self.kind {
+            FieldType::String => match value.as_str() {
+                Some(s) if self.max_length.is_some_and(|max| s.chars().count() > max) =>
+                    Err(format!("{} field must be at most {} characters", label, self.max_length.unwrap())),
+                Some(_) => Ok(value.clone()),
+                None => Err(format!("{} field must be string", label))
+            },
+            FieldType::Integer => match value.as_i64() {
+                Some(i) if self.min.is_some_and(|min| (i as f64) < min) =>
+                    Err(format!("{} field must be at least {}", label, self.min.unwrap())),
+                Some(i) if self.max.is_some_and(|max| (i as f64) > max) =>
+                    Err(format!("{} field must be at most {}", label, self.max.unwrap())),
+                Some(_) => Ok(value.clone()),
+                None => Err(format!("{} field must be integer", label))
+            },
+            FieldType::Boolean => match value.as_bool() {
+                Some(_) => Ok(value.clone()),
+                None => Err(format!("{} field must be boolean", label))
+            },
+            FieldType::Enum => match value.as_str() {
+                Some(s) if self.values.iter().any(|v| v == s) => Ok(value.clone()),
+                Some(_) => Err(format!("{} field must be {}", label, self.allowed_values())),
+                None => Err(format!("{} field must be string", label))
+            },
+            FieldType::Weight => {
+                let weight = match value {
+                    Value::Number(n) => n.as_f64().map(Weight::pounds),
+                    Value::Object(_) => serde_json::from_value::<Weight>(value.clone()).ok(),
+                    _ => None
+                };
+                match weight.map(|w| w.to_pounds()) {
+                    Some(lb) if !lb.is_finite() || lb <= 0.0 => Err(format!("{} field must be more than 0", label)),
+                    Some(lb) if self.min.is_some_and(|min| lb < min) =>
+                        Err(format!("{} field must be at least {} lb", label, self.min.unwrap())),
+                    Some(lb) if self.max.is_some_and(|max| lb > max) =>
+                        Err(format!("{} field must be at most {} lb", label, self.max.unwrap())),
+                    Some(lb) => Ok(pounds_value(lb)),
+                    None => Err(format!("{} field must be a number of pounds or a value with a unit of lb or kg", label))
+                }
+            }
+        }
+    }
+
+    // "either A or B", or "one of A, B, C"
+    pub fn allowed_values(&self) -> String {
+        match self.values.as_slice() {
+            [a, b] => format!("either {} or {}", a, b),
+            values => format!("one of {}", values.join(", "))
+        }
+    }
+}
+
+// Whole pounds are kept as integers so weights stored before decimals compare the same
+fn pounds_value(lb: f64) -> Value {
+    match lb.fract() == 0.0 {
+        true => Value::from(lb as i64),
+        false => Value::from(lb)
+    }
+}
+
+pub fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RABBIT: &str = r#"[{"name": "Rabbit", "fields": [
+        {"name": "weight", "type": "integer", "min": 1, "max": 20},
+        {"name": "color", "type": "string", "max_length": 10, "required": false}
+    ]}]"#;
+
+    #[test]
+    fn configured_species_are_validated() {
+        let registry = SpeciesRegistry::from_json(RABBIT).unwrap();
+        assert_eq!(registry.names().collect::<Vec<_>>(), vec!["Dog", "Cat", "Bird", "Rabbit"]);
+
+        let pets = registry.parse_pets(serde_json::json!([
+            {"animal": "Rabbit", "name": "Thumper", "weight": 4, "ears": "floppy"}
+        ])).unwrap();
+        assert_eq!(pets.0, vec![Pet::new("Rabbit", "Thumper", serde_json::json!({"weight": 4}))]);
+
+        let parse = |pet: Value| registry.parse_pets(Value::Array(vec![pet]));
+        assert_eq!(parse(serde_json::json!({"animal": "Rabbit", "name": "Thumper"})), Err("Rabbits require weight field".to_string()));
+        assert_eq!(parse(serde_json::json!({"animal": "Rabbit", "name": "Thumper", "weight": 30})), Err("Weight field must be at most 20".to_string()));
+        assert_eq!(
+            parse(serde_json::json!({"animal": "Rabbit", "name": "Thumper", "weight": 3, "color": "Cinnamon brown"})),
+            Err("Color field must be at most 10 characters".to_string())
+        );
+        assert_eq!(parse(serde_json::json!({"animal": "Fish", "name": "Nemo"})), Err("Invalid pet type".to_string()));
+        assert_eq!(SpeciesRegistry::default().parse_pets(serde_json::json!([{"animal": "Rabbit"}])), Err("Invalid pet type".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_species() {
+        assert!(matches!(SpeciesRegistry::from_json(r#"[{"name": "Fish", "fields": [{"name": "fins", "type": "float"}]}]"#), Err(SpeciesError::Parse(_))));
+        assert!(matches!(SpeciesRegistry::from_json(r#"[{"name": "Fish", "fields": [{"name": "water", "type": "enum"}]}]"#), Err(SpeciesError::Invalid(_))));
+        assert!(matches!(SpeciesRegistry::from_json(r#"[{"name": "Fish", "fields": [{"name": "id", "type": "integer"}]}]"#), Err(SpeciesError::Invalid(_))));
+    }
+}
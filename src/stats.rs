@@ -0,0 +1,160 @@
+use crate::catalog::Catalog;
+use crate::models::{ApartmentPets, AssistanceKind, Pet};
+use crate::species::{FieldType, SpeciesRegistry};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PetStats {
+    pub apartments: usize,
+    pub animals: BTreeMap<String, usize>,
+    // Of the animals, how many are assistance animals by kind
+    pub assistance: BTreeMap<&'static str, usize>,
+    pub top_breeds: Vec<NameCount>,
+    pub top_bird_species: Vec<NameCount>,
+    pub cat_hair: BTreeMap<String, usize>,
+    pub weight: BTreeMap<String, Option<WeightStats>>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct NameCount {
+    pub name: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct WeightStats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub p25: f64,
+    pub p50: f64,
+    pub p75: f64,
+    pub p90: f64,
+}
+
+// Aggregates are computed here rather than in SQL so every backend reports the same numbers.
+// Every registered species is counted and gets weight stats, in pounds, if it has a weight field.
+// Assistance animals are counted with their species and again by kind, unless rejected. Only
+// pending and approved pets are counted.
+pub fn compute(apartments: &[ApartmentPets], species: &SpeciesRegistry, catalog: &Catalog, top: usize) -> PetStats {
+    let pets = || apartments.iter().flat_map(|a| &a.pets.0).filter(|p| p.is_active());
+
+    let mut animals: BTreeMap<String, usize> = species.names().map(|name| (name.to_string(), 0)).collect();
+    for pet in pets() {
+        *animals.entry(pet.animal.clone()).or_default() += 1;
+    }
+
+    let mut assistance: BTreeMap<&'static str, usize> = AssistanceKind::ALL.iter().map(|kind| (kind.as_str(), 0)).collect();
+    for kind in pets().filter(|p| p.is_assistance()).filter_map(|p| p.assistance.as_ref().map(|a| a.kind)) {
+        *assistance.entry(kind.as_str()).or_default() += 1;
+    }
+
+    let mut cat_hair: BTreeMap<String, usize> = species.get("Cat")
+        .and_then(|cat| cat.fields.iter().find(|f| f.name == "hair"))
+        .map(|hair| hair.values.iter().map(|v| (v.clone(), 0)).collect())
+        .unwrap_or_default();
+    for hair in pets().filter(|p| p.animal == "Cat").filter_map(|p| p.text("hair")) {
+        *cat_hair.entry(hair.to_string()).or_default() += 1;
+    }
+
+    let weighed = species.names().filter(|&name| species.get(name).unwrap().fields.iter()
+        .any(|f| f.name == "weight" && matches!(f.kind, FieldType::Weight | FieldType::Integer)));
+
+    PetStats {
+        apartments: apartments.iter().filter(|a| a.pets.0.iter().any(Pet::is_active)).count(),
+        animals,
+        assistance,
+        top_breeds: top_counts(pets().filter_map(|p| canonical(catalog, p, "breed")), top),
+        top_bird_species: top_counts(pets().filter(|p| p.animal == "Bird").filter_map(|p| canonical(catalog, p, "species")), top),
+        cat_hair,
+        weight: weighed.map(|animal| (
+            animal.to_string(),
+            weight_stats(pets().filter(|p| p.animal == animal).filter_map(|p| p.number("weight")).collect())
+        )).collect(),
+    }
+}
+
+// Pets registered before the catalog may still have an alias stored
+fn canonical<'a>(catalog: &'a Catalog, pet: &'a Pet, field: &str) -> Option<&'a str> {
+    pet.text(field).map(|value| catalog.canonical(Some(&pet.animal), field, value).unwrap_or(value))
+}
+
+// Most common first, ties broken alphabetically
+fn top_counts<'a>(names: impl Iterator<Item = &'a str>, top: usize) -> Vec<NameCount> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for name in names {
+        *counts.entry(name).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts.into_iter().take(top).map(|(name, count)| NameCount { name: name.to_string(), count }).collect()
+}
+
+fn weight_stats(mut weights: Vec<f64>) -> Option<WeightStats> {
+    weights.sort_by(f64::total_cmp);
+    Some(WeightStats {
+        min: *weights.first()?,
+        max: *weights.last()?,
+        avg: weights.iter().sum::<f64>() / weights.len() as f64,
+        p25: percentile(&weights, 0.25),
+        p50: percentile(&weights, 0.50),
+        p75: percentile(&weights, 0.75),
+        p90: percentile(&weights, 0.90),
+    })
+}
+
+// Linear interpolation between the closest ranks, same as Postgres percentile_cont
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p * (sorted.len() - 1) as f64;
+    let (lower, upper) = (sorted[rank.floor() as usize], sorted[rank.ceil() as usize]);
+    lower + (upper - lower) * rank.fract()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Apt, Assistance, Pet, Pets, Verification};
+    use serde_json::json;
+
+    fn service() -> Assistance {
+        Assistance { kind: AssistanceKind::Service, verification: Verification::Verified, documentation: None }
+    }
+
+    fn dog(weight: i64, breed: &str) -> Pet {
+        Pet::new("Dog", "Dog", json!({"weight": weight, "breed": breed}))
+    }
+
+    #[test]
+    fn computes_building_stats() {
+        let apartments = vec![
+            ApartmentPets { apt: Apt::numbered(101), pets: Pets(vec![
+                dog(10, "Poodle"), dog(20, "Labrador"), dog(30, "lab"),
+                Pet::new("Cat", "Nova", json!({"weight": 12, "hair": "LongHaired"})),
+                Pet::new("Bird", "Polly", json!({"species": "Parrot"})),
+            ])},
+            ApartmentPets { apt: Apt::numbered(102), pets: Pets(vec![Pet { assistance: Some(service()), ..dog(40, "Beagle") }]) },
+        ];
+
+        let stats = compute(&apartments, &SpeciesRegistry::default(), &Catalog::default(), 2);
+        assert_eq!(stats.apartments, 2);
+        assert_eq!(stats.animals, BTreeMap::from([("Dog".to_string(), 4), ("Cat".to_string(), 1), ("Bird".to_string(), 1)]));
+        assert_eq!(stats.assistance, BTreeMap::from([("emotional_support", 0), ("service", 1)]));
+        assert_eq!(stats.top_breeds, vec![
+            NameCount { name: "Labrador Retriever".to_string(), count: 2 },
+            NameCount { name: "Beagle".to_string(), count: 1 },
+        ]);
+        assert_eq!(stats.cat_hair, BTreeMap::from([("LongHaired".to_string(), 1), ("ShortHaired".to_string(), 0)]));
+        assert_eq!(stats.weight["Dog"], Some(WeightStats { min: 10.0, max: 40.0, avg: 25.0, p25: 17.5, p50: 25.0, p75: 32.5, p90: 37.0 }));
+        assert_eq!(stats.weight["Cat"].as_ref().unwrap().p90, 12.0);
+        assert!(!stats.weight.contains_key("Bird"));
+    }
+
+    #[test]
+    fn empty_building_has_no_weights() {
+        let stats = compute(&[], &SpeciesRegistry::default(), &Catalog::default(), 5);
+        assert_eq!(stats.apartments, 0);
+        assert_eq!(stats.animals["Bird"], 0);
+        assert!(stats.top_breeds.is_empty());
+        assert_eq!(stats.weight["Dog"], None);
+    }
+}
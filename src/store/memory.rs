@@ -0,0 +1,562 @@
+use super::{
+    already_registered, building_not_found, building_taken, incident_not_found, incident_resolved, license_taken, microchip_not_found,
+    microchip_taken, not_registered, nothing_to_restore, owner_not_found, pet_not_found, pets_changed, tenant_not_found, PetStore, StoreError
+};
+use crate::models::{
+    Action, Actor, ApartmentPets, ApartmentSummary, Apt, AuditEntry, Building, ExpiringRecord, Incident, License, Page, Pet, PetId, PetRecords, PetRef, PetSearch, Pets, Review,
+    Tenant, Vaccination, DEFAULT_BUILDING
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// Keeps everything in process, for tests and running without a database
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+#[derive(Default)]
+struct Data {
+    apts: BTreeMap<Apt, Pets>,
+    // Kept until purged, in the order they were deleted. Deleted pets have deleted_at set.
+    deleted_apts: Vec<(Apt, DateTime<Utc>)>,
+    deleted_pets: Vec<(Apt, Pet)>,
+    last_id: i32,
+    // Keyed by the pet's animal and id
+    vaccinations: Vec<(String, i32, Vaccination)>,
+    licenses: Vec<(String, i32, License)>,
+    last_record_id: i32,
+    // By id, incidents outlive the pets they are about
+    incidents: BTreeMap<i32, Incident>,
+    tenants: BTreeMap<i32, Tenant>,
+    last_tenant_id: i32,
+    buildings: BTreeMap<String, Building>,
+    // In the order entries were recorded, ids are positions plus one
+    audit_log: Vec<AuditEntry>,
+}
+
+impl Data {
+    // Owners have to exist, like the foreign key on pets.owner_id
+    fn check_owners(&self, pets: &Pets) -> Result<(), StoreError> {
+        match pets.0.iter().filter_map(|p| p.owner).all(|owner| self.tenants.contains_key(&owner)) {
+            true => Ok(()),
+            false => Err(owner_not_found(not_registered()))
+        }
+    }
+
+    // Microchips are unique among pets that haven't been deleted, like pets_microchip_idx. The
+    // pets of an apartment being replaced don't count.
+    fn check_microchips<'a>(&'a self, pets: impl Iterator<Item = &'a Pet>, replacing: Option<&Apt>) -> Result<(), StoreError> {
+        let mut taken: Vec<&str> = self.apts.iter()
+            .filter(|(apt, _)| Some(*apt) != replacing)
+            .flat_map(|(_, pets)| &pets.0)
+            .filter_map(|p| p.microchip.as_deref())
+            .collect();
+        for microchip in pets.filter_map(|p| p.microchip.as_deref()) {
+            if taken.contains(&microchip) {
+                return Err(microchip_taken(already_registered()));
+            }
+            taken.push(microchip);
+        }
+        Ok(())
+    }
+
+    // Units and tenancies have to be in a building that exists, like their foreign keys
+    fn check_building(&self, building: &str) -> Result<(), StoreError> {
+        match self.buildings.contains_key(building) {
+            true => Ok(()),
+            false => Err(building_not_found())
+        }
+    }
+
+    fn check_tenancies(&self, tenant: &Tenant) -> Result<(), StoreError> {
+        tenant.tenancies.iter().try_for_each(|t| self.check_building(&t.building))
+    }
+
+    fn with_ids(&mut self, pets: &Pets) -> Pets {
+        let mut pets = pets.clone();
+        for pet in &mut pets.0 {
+            self.last_id += 1;
+            pet.id = Some(self.last_id);
+        }
+        pets
+    }
+
+    fn pet(&self, pet: &PetRef) -> Option<&Pet> {
+        self.apts.get(&pet.apt)?.0.iter().find(|p| p.animal == pet.animal && p.id == Some(pet.id))
+    }
+
+    fn live(&self, animal: &str, id: i32) -> bool {
+        self.apts.values().flat_map(|p| &p.0).any(|p| p.animal == animal && p.id == Some(id))
+    }
+
+    // Deleted pets keep their records until they are purged
+    fn exists(&self, animal: &str, id: i32) -> bool {
+        self.live(animal, id) || self.deleted_pets.iter().any(|(_, p)| p.animal == animal && p.id == Some(id))
+    }
+
+    fn delete_pets(&mut self, apt: &Apt, pets: Pets, at: DateTime<Utc>) {
+        for pet in pets.0 {
+            self.deleted_pets.push((apt.clone(), Pet { deleted_at: Some(at), ..pet }));
+        }
+    }
+
+    // Records go with their pet, like ON DELETE CASCADE, and incidents lose it like ON DELETE SET NULL
+    fn remove_orphaned_records(&mut self) {
+        let vaccinations = std::mem::take(&mut self.vaccinations);
+        self.vaccinations = vaccinations.into_iter().filter(|(animal, id, _)| self.exists(animal, *id)).collect();
+        let licenses = std::mem::take(&mut self.licenses);
+        self.licenses = licenses.into_iter().filter(|(animal, id, _)| self.exists(animal, *id)).collect();
+        let mut incidents = std::mem::take(&mut self.incidents);
+        for incident in incidents.values_mut() {
+            if incident.pet.as_ref().is_some_and(|p| !self.exists(&p.animal, p.id)) {
+                incident.pet = None;
+            }
+        }
+        self.incidents = incidents;
+    }
+
+    // Tenancies are kept oldest first, like the ORDER BY the databases use
+    fn stored_tenant(id: i32, tenant: &Tenant) -> Tenant {
+        let mut tenant = Tenant { id: Some(id), ..tenant.clone() };
+        tenant.tenancies.sort_by_key(|t| (t.move_in, t.apt()));
+        tenant
+    }
+
+    fn record(&mut self, entry: AuditEntry) {
+        let id = self.audit_log.len() as i64 + 1;
+        self.audit_log.push(AuditEntry { id: Some(id), ..entry });
+    }
+
+    fn expiring(&self, pet: (&str, i32), record: &str, detail: &str, expires: NaiveDate) -> ExpiringRecord {
+        let (apt, pet) = self.apts.iter()
+            .find_map(|(apt, pets)| pets.0.iter().find(|p| p.animal == pet.0 && p.id == Some(pet.1)).map(|p| (apt, p)))
+            .unwrap();
+        ExpiringRecord {
+            apt: apt.clone(),
+            animal: pet.animal.clone(),
+            id: pet.id.unwrap(),
+            name: pet.name.clone(),
+            record: record.to_string(),
+            detail: detail.to_string(),
+            expires,
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+// Starts out with the default building, like a migrated database
+impl Default for MemoryStore {
+    fn default() -> Self {
+        let mut data = Data::default();
+        data.buildings.insert(DEFAULT_BUILDING.to_string(), Building {
+            code: DEFAULT_BUILDING.to_string(),
+            name: "Default".to_string(),
+            property: "Default".to_string(),
+        });
+        MemoryStore { data: Mutex::new(data) }
+    }
+}
+
+impl PetStore for MemoryStore {
+    fn register_apartment(&self, apt: &Apt, pets: &Pets, actor: &Actor) -> Result<Pets, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        data.check_building(&apt.building)?;
+        if data.apts.contains_key(apt) {
+            return Err(already_registered());
+        }
+        data.check_owners(pets)?;
+        data.check_microchips(pets.0.iter(), None)?;
+        let pets = data.with_ids(pets);
+        data.apts.insert(apt.clone(), pets.clone());
+        data.record(AuditEntry::new(actor, Action::Create, apt, None, None, Some(pets.clone())));
+        Ok(pets)
+    }
+
+    fn list_pets(&self, apt: &Apt) -> Result<Pets, StoreError> {
+        self.data.lock().unwrap().apts.get(apt).cloned().ok_or_else(not_registered)
+    }
+
+    fn update_pets(&self, apt: &Apt, pets: &Pets, replacing: &Pets, actor: &Actor) -> Result<Pets, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        match data.apts.get(apt) {
+            Some(stored) if stored != replacing => return Err(pets_changed()),
+            Some(_) => {},
+            None => return Err(not_registered())
+        }
+        data.check_owners(pets)?;
+        data.check_microchips(pets.0.iter(), Some(apt))?;
+        let pets = data.with_ids(pets);
+        if let Some(replaced) = data.apts.insert(apt.clone(), pets.clone()) {
+            data.record(AuditEntry::new(actor, Action::Update, apt, None, Some(replaced.clone()), Some(pets.clone())));
+            data.delete_pets(apt, replaced, Utc::now());
+        }
+        Ok(pets)
+    }
+
+    fn delete_apartment(&self, apt: &Apt, actor: &Actor) -> Result<(), StoreError> {
+        let mut data = self.data.lock().unwrap();
+        match data.apts.remove(apt) {
+            Some(pets) => {
+                data.record(AuditEntry::new(actor, Action::Delete, apt, None, Some(pets.clone()), None));
+                let now = Utc::now();
+                data.deleted_apts.push((apt.clone(), now));
+                data.delete_pets(apt, pets, now);
+                Ok(())
+            },
+            None => Err(not_registered())
+        }
+    }
+
+    fn delete_pet(&self, pet: &PetRef, actor: &Actor) -> Result<Pet, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        let pets = data.apts.get_mut(&pet.apt).ok_or_else(pet_not_found)?;
+        let index = pets.0.iter().position(|p| p.animal == pet.animal && p.id == Some(pet.id)).ok_or_else(pet_not_found)?;
+        let deleted = Pet { deleted_at: Some(Utc::now()), ..pets.0.remove(index) };
+        data.deleted_pets.push((pet.apt.clone(), deleted.clone()));
+        data.record(AuditEntry::new(actor, Action::Delete, &pet.apt, Some(pet), Some(Pets(vec![deleted.clone()])), None));
+        Ok(deleted)
+    }
+
+    fn list_deleted(&self, apt: &Apt) -> Result<Pets, StoreError> {
+        let data = self.data.lock().unwrap();
+        let mut pets: Vec<Pet> = data.deleted_pets.iter().filter(|(a, _)| a == apt).map(|(_, p)| p.clone()).collect();
+        pets.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(a.id.cmp(&b.id)).then(a.animal.cmp(&b.animal)));
+        Ok(Pets(pets))
+    }
+
+    fn restore_apartment(&self, apt: &Apt, actor: &Actor) -> Result<Pets, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        let index = data.deleted_apts.iter().rposition(|(a, _)| a == apt).ok_or_else(nothing_to_restore)?;
+        if data.apts.contains_key(apt) {
+            return Err(already_registered());
+        }
+        let deleted_at = data.deleted_apts[index].1;
+        data.check_microchips(data.deleted_pets.iter().filter(|(a, p)| a == apt && p.deleted_at == Some(deleted_at)).map(|(_, p)| p), None)?;
+        data.deleted_apts.remove(index);
+        let (restored, kept) = std::mem::take(&mut data.deleted_pets).into_iter()
+            .partition(|(a, p)| a == apt && p.deleted_at == Some(deleted_at));
+        data.deleted_pets = kept;
+        let mut pets: Vec<Pet> = restored.into_iter().map(|(_, p)| Pet { deleted_at: None, ..p }).collect();
+        pets.sort_by(|a, b| (a.id, &a.animal).cmp(&(b.id, &b.animal)));
+        data.apts.insert(apt.clone(), Pets(pets.clone()));
+        data.record(AuditEntry::new(actor, Action::Create, apt, None, None, Some(Pets(pets.clone()))));
+        Ok(Pets(pets))
+    }
+
+    fn restore_pet(&self, pet: &PetRef, allowed: &dyn Fn(&Pets) -> Result<(), String>, actor: &Actor) -> Result<Pet, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        if !data.apts.contains_key(&pet.apt) {
+            return Err(not_registered());
+        }
+        let index = data.deleted_pets.iter()
+            .position(|(a, p)| *a == pet.apt && p.animal == pet.animal && p.id == Some(pet.id))
+            .ok_or_else(pet_not_found)?;
+        data.check_microchips(std::iter::once(&data.deleted_pets[index].1), None)?;
+        let restored = Pet { deleted_at: None, ..data.deleted_pets[index].1.clone() };
+        let mut pets = data.apts[&pet.apt].clone();
+        pets.0.push(restored.clone());
+        pets.0.sort_by(|a, b| (a.id, &a.animal).cmp(&(b.id, &b.animal)));
+        allowed(&pets).map_err(StoreError::NotAllowed)?;
+        data.deleted_pets.remove(index);
+        data.apts.insert(pet.apt.clone(), pets);
+        data.record(AuditEntry::new(actor, Action::Create, &pet.apt, Some(pet), None, Some(Pets(vec![restored.clone()]))));
+        Ok(restored)
+    }
+
+    fn purge_deleted(&self, before: DateTime<Utc>) -> Result<Pets, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        let (purged, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut data.deleted_pets).into_iter()
+            .partition(|(_, p)| p.deleted_at.is_some_and(|at| at <= before));
+        data.deleted_pets = kept;
+        data.deleted_apts.retain(|(_, at)| *at > before);
+        data.remove_orphaned_records();
+        Ok(Pets(purged.into_iter().map(|(_, p)| p).collect()))
+    }
+
+    fn transfer_pets(
+        &self,
+        from: &Apt,
+        to: &Apt,
+        pets: &[PetId],
+        allowed: &dyn Fn(&Pets) -> Result<(), String>,
+        actor: &Actor
+    ) -> Result<Pets, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        let source = data.apts.get(from).ok_or_else(not_registered)?;
+        let moved = |p: &Pet| pets.iter().any(|m| m.animal == p.animal && Some(m.id) == p.id);
+        if source.0.iter().filter(|p| moved(p)).count() < pets.len() {
+            return Err(pet_not_found());
+        }
+        if !data.apts.contains_key(to) {
+            data.check_building(&to.building)?;
+        }
+        let mut arriving = data.apts.get(to).cloned().unwrap_or_default();
+        arriving.0.extend(source.0.iter().filter(|p| moved(p)).cloned());
+        arriving.0.sort_by(|a, b| (a.id, &a.animal).cmp(&(b.id, &b.animal)));
+        allowed(&arriving).map_err(StoreError::NotAllowed)?;
+        let source = data.apts.remove(from).unwrap();
+        let (moving, staying): (Vec<Pet>, Vec<Pet>) = source.0.iter().cloned().partition(|p| moved(p));
+        data.apts.insert(from.clone(), Pets(staying.clone()));
+        data.record(AuditEntry::new(actor, Action::Update, from, None, Some(source), Some(Pets(staying))));
+        let before = data.apts.get(to).cloned();
+        let destination = data.apts.entry(to.clone()).or_default();
+        destination.0.extend(moving);
+        destination.0.sort_by(|a, b| (a.id, &a.animal).cmp(&(b.id, &b.animal)));
+        let destination = destination.clone();
+        let action = match before {
+            Some(_) => Action::Update,
+            None => Action::Create
+        };
+        data.record(AuditEntry::new(actor, action, to, None, before, Some(destination.clone())));
+        Ok(destination)
+    }
+
+    fn find_microchip(&self, microchip: &str) -> Result<(Apt, Pet), StoreError> {
+        let data = self.data.lock().unwrap();
+        data.apts.iter()
+            .find_map(|(apt, pets)| pets.0.iter().find(|p| p.microchip.as_deref() == Some(microchip)).map(|p| (apt.clone(), p.clone())))
+            .ok_or_else(microchip_not_found)
+    }
+
+    fn list_apartments(&self, page: &Page, building: Option<&str>) -> Result<Vec<ApartmentSummary>, StoreError> {
+        let data = self.data.lock().unwrap();
+        let apts: Box<dyn Iterator<Item = (&Apt, &Pets)>> = match page.descending {
+            true => Box::new(data.apts.iter().rev()),
+            false => Box::new(data.apts.iter())
+        };
+        let apts = apts.filter(|(apt, _)| building.is_none_or(|b| apt.building == b));
+        Ok(apts.skip(page.offset as usize).take(page.limit as usize).map(|(apt, pets)| {
+            let mut counts = BTreeMap::new();
+            for pet in &pets.0 {
+                *counts.entry(pet.animal.clone()).or_default() += 1;
+            }
+            ApartmentSummary { apt: apt.clone(), pets: counts }
+        }).collect())
+    }
+
+    fn search_pets(&self, search: &PetSearch) -> Result<Vec<ApartmentPets>, StoreError> {
+        let data = self.data.lock().unwrap();
+        Ok(data.apts.iter().filter(|(apt, _)| search.includes_apt(apt)).map(|(apt, pets)| ApartmentPets {
+            apt: apt.clone(),
+            pets: Pets(pets.0.iter().filter(|p| search.matches(p)).cloned().collect()),
+        }).filter(|a| !a.pets.is_empty()).collect())
+    }
+
+    fn add_vaccination(&self, pet: &PetRef, vaccination: &Vaccination) -> Result<Vaccination, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        if data.pet(pet).is_none() {
+            return Err(pet_not_found());
+        }
+        data.last_record_id += 1;
+        let vaccination = Vaccination { id: Some(data.last_record_id), ..vaccination.clone() };
+        data.vaccinations.push((pet.animal.clone(), pet.id, vaccination.clone()));
+        Ok(vaccination)
+    }
+
+    fn add_license(&self, pet: &PetRef, license: &License) -> Result<License, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        if data.pet(pet).is_none() {
+            return Err(pet_not_found());
+        }
+        if data.licenses.iter().any(|(_, _, l)| l.number == license.number) {
+            return Err(license_taken(already_registered()));
+        }
+        data.last_record_id += 1;
+        let license = License { id: Some(data.last_record_id), ..license.clone() };
+        data.licenses.push((pet.animal.clone(), pet.id, license.clone()));
+        Ok(license)
+    }
+
+    fn review_pet(&self, pet: &PetRef, review: &Review, actor: &Actor) -> Result<Pet, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        let stored = data.apts.get_mut(&pet.apt)
+            .and_then(|pets| pets.0.iter_mut().find(|p| p.animal == pet.animal && p.id == Some(pet.id)))
+            .ok_or_else(pet_not_found)?;
+        let before = stored.clone();
+        stored.review = review.clone();
+        let reviewed = stored.clone();
+        data.record(AuditEntry::new(actor, Action::Update, &pet.apt, Some(pet), Some(Pets(vec![before])), Some(Pets(vec![reviewed.clone()]))));
+        Ok(reviewed)
+    }
+
+    fn history(&self, apt: &Apt, until: Option<DateTime<Utc>>) -> Result<Vec<AuditEntry>, StoreError> {
+        let data = self.data.lock().unwrap();
+        Ok(data.audit_log.iter()
+            .filter(|entry| entry.apt == *apt && until.is_none_or(|until| entry.at <= until))
+            .cloned()
+            .collect())
+    }
+
+    fn list_records(&self, pet: &PetRef) -> Result<PetRecords, StoreError> {
+        let data = self.data.lock().unwrap();
+        if data.pet(pet).is_none() {
+            return Err(pet_not_found());
+        }
+        let of_pet = |animal: &String, id: &i32| *animal == pet.animal && *id == pet.id;
+        let mut records = PetRecords {
+            vaccinations: data.vaccinations.iter().filter(|(a, i, _)| of_pet(a, i)).map(|(_, _, v)| v.clone()).collect(),
+            licenses: data.licenses.iter().filter(|(a, i, _)| of_pet(a, i)).map(|(_, _, l)| l.clone()).collect(),
+        };
+        records.vaccinations.sort_by_key(|v| (v.given, v.id));
+        records.licenses.sort_by_key(|l| (l.issued, l.id));
+        Ok(records)
+    }
+
+    fn add_incident(&self, incident: &Incident) -> Result<Incident, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        let pets = data.apts.get(&incident.apt);
+        match (pets, &incident.pet) {
+            (None, Some(_)) => return Err(pet_not_found()),
+            (None, None) => return Err(not_registered()),
+            (Some(pets), Some(pet)) if !pets.0.iter().any(|p| p.animal == pet.animal && p.id == Some(pet.id)) => return Err(pet_not_found()),
+            _ => {}
+        }
+        let id = data.incidents.keys().next_back().map_or(1, |id| id + 1);
+        let incident = Incident { id: Some(id), ..incident.clone() };
+        data.incidents.insert(id, incident.clone());
+        Ok(incident)
+    }
+
+    fn get_incident(&self, id: i32) -> Result<Incident, StoreError> {
+        self.data.lock().unwrap().incidents.get(&id).cloned().ok_or_else(incident_not_found)
+    }
+
+    fn list_incidents(&self, apt: &Apt, pet: Option<&PetId>) -> Result<Vec<Incident>, StoreError> {
+        let data = self.data.lock().unwrap();
+        let mut incidents: Vec<Incident> = data.incidents.values()
+            .filter(|i| match pet {
+                Some(pet) => i.pet.as_ref() == Some(pet),
+                None => i.apt == *apt
+            })
+            .cloned()
+            .collect();
+        incidents.sort_by_key(|i| (i.date, i.id));
+        Ok(incidents)
+    }
+
+    fn resolve_incident(&self, id: i32, resolution: &str, resolved_on: NaiveDate) -> Result<Incident, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        match data.incidents.get_mut(&id) {
+            Some(incident) if incident.is_open() => {
+                incident.resolution = Some(resolution.to_string());
+                incident.resolved_on = Some(resolved_on);
+                Ok(incident.clone())
+            },
+            Some(_) => Err(incident_resolved()),
+            None => Err(incident_not_found())
+        }
+    }
+
+    fn open_incident_counts(&self, apt: &Apt) -> Result<BTreeMap<PetId, i64>, StoreError> {
+        let data = self.data.lock().unwrap();
+        let mut counts = BTreeMap::new();
+        if let Some(pets) = data.apts.get(apt) {
+            for pet in data.incidents.values().filter(|i| i.is_open()).filter_map(|i| i.pet.as_ref()) {
+                if pets.0.iter().any(|p| p.animal == pet.animal && p.id == Some(pet.id)) {
+                    *counts.entry(pet.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok(counts)
+    }
+
+    fn expiring_records(&self, by: NaiveDate) -> Result<Vec<ExpiringRecord>, StoreError> {
+        let data = self.data.lock().unwrap();
+
+        // Latest record per pet and vaccine, and per pet for licenses
+        let mut vaccinations: BTreeMap<(&str, i32, String), &Vaccination> = BTreeMap::new();
+        for (animal, id, v) in data.vaccinations.iter().filter(|(animal, id, _)| data.live(animal, *id)) {
+            let latest = vaccinations.entry((animal, *id, v.vaccine.to_lowercase())).or_insert(v);
+            if (v.expires, v.id) > (latest.expires, latest.id) {
+                *latest = v;
+            }
+        }
+        let mut licenses: BTreeMap<(&str, i32), &License> = BTreeMap::new();
+        for (animal, id, l) in data.licenses.iter().filter(|(animal, id, _)| data.live(animal, *id)) {
+            let latest = licenses.entry((animal, *id)).or_insert(l);
+            if (l.expires, l.id) > (latest.expires, latest.id) {
+                *latest = l;
+            }
+        }
+
+        let mut records: Vec<ExpiringRecord> = vaccinations.into_iter()
+            .filter(|(_, v)| v.expires <= by)
+            .map(|((animal, id, _), v)| data.expiring((animal, id), "vaccination", &v.vaccine, v.expires))
+            .chain(licenses.into_iter()
+                .filter(|(_, l)| l.expires <= by)
+                .map(|((animal, id), l)| data.expiring((animal, id), "license", &l.number, l.expires)))
+            .collect();
+        records.sort_by(|a, b| (a.expires, &a.apt, &a.animal, a.id).cmp(&(b.expires, &b.apt, &b.animal, b.id)));
+        Ok(records)
+    }
+
+    fn create_tenant(&self, tenant: &Tenant) -> Result<Tenant, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        data.check_tenancies(tenant)?;
+        data.last_tenant_id += 1;
+        let tenant = Data::stored_tenant(data.last_tenant_id, tenant);
+        data.tenants.insert(tenant.id.unwrap(), tenant.clone());
+        Ok(tenant)
+    }
+
+    fn get_tenant(&self, id: i32) -> Result<Tenant, StoreError> {
+        self.data.lock().unwrap().tenants.get(&id).cloned().ok_or_else(tenant_not_found)
+    }
+
+    fn update_tenant(&self, id: i32, tenant: &Tenant) -> Result<Tenant, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        if !data.tenants.contains_key(&id) {
+            return Err(tenant_not_found());
+        }
+        data.check_tenancies(tenant)?;
+        match data.tenants.get_mut(&id) {
+            Some(stored) => {
+                *stored = Data::stored_tenant(id, tenant);
+                Ok(stored.clone())
+            },
+            None => Err(tenant_not_found())
+        }
+    }
+
+    // Like ON DELETE SET NULL
+    fn delete_tenant(&self, id: i32) -> Result<(), StoreError> {
+        let mut data = self.data.lock().unwrap();
+        if data.tenants.remove(&id).is_none() {
+            return Err(tenant_not_found());
+        }
+        let Data { apts, deleted_pets, .. } = &mut *data;
+        for pet in apts.values_mut().flat_map(|pets| &mut pets.0).chain(deleted_pets.iter_mut().map(|(_, pet)| pet)) {
+            if pet.owner == Some(id) {
+                pet.owner = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn list_tenants(&self, apt: Option<&Apt>) -> Result<Vec<Tenant>, StoreError> {
+        let data = self.data.lock().unwrap();
+        Ok(data.tenants.values()
+            .filter(|tenant| apt.is_none_or(|apt| tenant.tenancies.iter().any(|t| t.apt() == *apt)))
+            .cloned()
+            .collect())
+    }
+
+    fn create_building(&self, building: &Building) -> Result<Building, StoreError> {
+        let mut data = self.data.lock().unwrap();
+        match data.buildings.entry(building.code.clone()) {
+            Entry::Occupied(_) => Err(building_taken(already_registered())),
+            Entry::Vacant(entry) => Ok(entry.insert(building.clone()).clone())
+        }
+    }
+
+    fn list_buildings(&self) -> Result<Vec<Building>, StoreError> {
+        Ok(self.data.lock().unwrap().buildings.values().cloned().collect())
+    }
+}
@@ -0,0 +1,412 @@
+use crate::models::{
+    Actor, ApartmentPets, ApartmentSummary, Apt, AuditEntry, Building, ExpiringRecord, Incident, License, Page, Pet, PetId, PetRecords, PetRef,
+    PetSearch, Pets, Review, Tenant, Vaccination
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use postgres::error::SqlState;
+use postgres::Error as PostgresError;
+use rusqlite::ErrorCode;
+use rusqlite::Error as SqliteError;
+use std::collections::BTreeMap;
+use std::fmt;
+
+mod memory;
+mod pg;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use pg::PostgresStore;
+pub use sqlite::{open_connection as open_sqlite, SqliteStore};
+
+// Every change to pets is written to the audit log under the actor in the same transaction as
+// the change itself, with the pets as they were read inside it. Nothing is changed unless the
+// entry is written too.
+pub trait PetStore: Send + Sync {
+    // Returns the stored pets with their generated ids, fails with Conflict if the apartment
+    // is already registered or a microchip is taken and NotFound if its building doesn't exist
+    fn register_apartment(&self, apt: &Apt, pets: &Pets, actor: &Actor) -> Result<Pets, StoreError>;
+
+    fn list_pets(&self, apt: &Apt) -> Result<Pets, StoreError>;
+
+    // Replaces every pet registered to the apartment, returns the new pets with their ids. The
+    // replaced pets are deleted and can be restored until they are purged, so their microchips
+    // may be registered again.
+    //
+    // replacing is what the caller read before making the new pets from it. Conflict if the
+    // apartment's pets aren't that anymore once it is locked, so a review that lands in between
+    // isn't overwritten.
+    fn update_pets(&self, apt: &Apt, pets: &Pets, replacing: &Pets, actor: &Actor) -> Result<Pets, StoreError>;
+
+    // Deletes the apartment along with its pets, both are kept until they are purged
+    fn delete_apartment(&self, apt: &Apt, actor: &Actor) -> Result<(), StoreError>;
+
+    // Deletes one pet of the apartment, NotFound if it has no such pet. Returns the deleted pet.
+    fn delete_pet(&self, pet: &PetRef, actor: &Actor) -> Result<Pet, StoreError>;
+
+    // Pets of the unit that are deleted but not yet purged, most recently deleted first, including
+    // those of an apartment that was deleted
+    fn list_deleted(&self, apt: &Apt) -> Result<Pets, StoreError>;
+
+    // Brings back the unit's most recently deleted apartment with the pets deleted along with it,
+    // NotFound if there is none and Conflict if the unit has been registered again since or one
+    // of the microchips has been taken. Returns the restored pets.
+    fn restore_apartment(&self, apt: &Apt, actor: &Actor) -> Result<Pets, StoreError>;
+
+    // Brings a deleted pet of the unit back into its registered apartment, NotFound if the
+    // apartment isn't registered or there is no such deleted pet and Conflict if its microchip
+    // has been taken.
+    //
+    // allowed is given the apartment's pets as they would be after the restore, read while the
+    // apartment is locked. Nothing is restored when it fails, the store returns NotAllowed with
+    // its reason.
+    fn restore_pet(&self, pet: &PetRef, allowed: &dyn Fn(&Pets) -> Result<(), String>, actor: &Actor) -> Result<Pet, StoreError>;
+
+    // Removes apartments and pets deleted at or before the given time for good, along with the
+    // pets' records. Returns the purged pets.
+    fn purge_deleted(&self, before: DateTime<Utc>) -> Result<Pets, StoreError>;
+
+    // Moves pets of one apartment to another in a single transaction, registering the destination
+    // if it isn't yet. NotFound if the source apartment, one of the pets or the destination's
+    // building doesn't exist. Pets keep their ids, records and review. Both apartments get an
+    // entry in the audit log. Returns the pets of the destination.
+    //
+    // allowed is given the destination's pets as they would be after the move, read while both
+    // apartments are locked. Nothing moves when it fails, the store returns NotAllowed with its
+    // reason.
+    fn transfer_pets(
+        &self,
+        from: &Apt,
+        to: &Apt,
+        pets: &[PetId],
+        allowed: &dyn Fn(&Pets) -> Result<(), String>,
+        actor: &Actor
+    ) -> Result<Pets, StoreError>;
+
+    // The pet with the microchip and where it lives, NotFound unless a pet that hasn't been
+    // deleted has it
+    fn find_microchip(&self, microchip: &str) -> Result<(Apt, Pet), StoreError>;
+
+    // Registered apartments ordered by building and unit with their pet counts, only those of
+    // one building when given
+    fn list_apartments(&self, page: &Page, building: Option<&str>) -> Result<Vec<ApartmentSummary>, StoreError>;
+
+    // Matching pets grouped by apartment, apartments without a match are left out
+    fn search_pets(&self, search: &PetSearch) -> Result<Vec<ApartmentPets>, StoreError>;
+
+    // Records are attached to a pet of the apartment, NotFound if it has no such pet. Returns
+    // the record with its id.
+    fn add_vaccination(&self, pet: &PetRef, vaccination: &Vaccination) -> Result<Vaccination, StoreError>;
+
+    // License numbers are unique, a number already in use is a Conflict
+    fn add_license(&self, pet: &PetRef, license: &License) -> Result<License, StoreError>;
+
+    // Records a manager's decision on the pet, NotFound if the apartment has no such pet.
+    // Returns the pet as stored.
+    fn review_pet(&self, pet: &PetRef, review: &Review, actor: &Actor) -> Result<Pet, StoreError>;
+
+    // The audit log of the apartment in the order it was written, up to and including the given
+    // time when there is one. Empty for an apartment that never changed.
+    fn history(&self, apt: &Apt, until: Option<DateTime<Utc>>) -> Result<Vec<AuditEntry>, StoreError>;
+
+    fn list_records(&self, pet: &PetRef) -> Result<PetRecords, StoreError>;
+
+    // Reports an incident in the incident's apartment, about one of its pets when it names one.
+    // NotFound if the apartment isn't registered or has no such pet. Returns the incident with
+    // its id.
+    fn add_incident(&self, incident: &Incident) -> Result<Incident, StoreError>;
+
+    fn get_incident(&self, id: i32) -> Result<Incident, StoreError>;
+
+    // Incidents reported in the unit, or those about the pet wherever it lived when given.
+    // Oldest first.
+    fn list_incidents(&self, apt: &Apt, pet: Option<&PetId>) -> Result<Vec<Incident>, StoreError>;
+
+    // NotFound if there is no such incident, Conflict if it has been resolved already
+    fn resolve_incident(&self, id: i32, resolution: &str, resolved_on: NaiveDate) -> Result<Incident, StoreError>;
+
+    // Open incidents of each pet the apartment has now, pets without any are left out
+    fn open_incident_counts(&self, apt: &Apt) -> Result<BTreeMap<PetId, i64>, StoreError>;
+
+    // The latest vaccination of each vaccine and the latest license of every pet, where that
+    // record expires on or before the given date. Soonest expiry first.
+    fn expiring_records(&self, by: NaiveDate) -> Result<Vec<ExpiringRecord>, StoreError>;
+
+    // Returns the tenant with the generated id
+    fn create_tenant(&self, tenant: &Tenant) -> Result<Tenant, StoreError>;
+
+    fn get_tenant(&self, id: i32) -> Result<Tenant, StoreError>;
+
+    // Replaces the name, contact details and tenancies
+    fn update_tenant(&self, id: i32, tenant: &Tenant) -> Result<Tenant, StoreError>;
+
+    // Pets the tenant owned are left without an owner
+    fn delete_tenant(&self, id: i32) -> Result<(), StoreError>;
+
+    // Tenants by id, only those who have ever lived in the apartment when one is given
+    fn list_tenants(&self, apt: Option<&Apt>) -> Result<Vec<Tenant>, StoreError>;
+
+    // Creates the building's property too if it doesn't exist yet. Building codes are unique,
+    // a code already in use is a Conflict.
+    fn create_building(&self, building: &Building) -> Result<Building, StoreError>;
+
+    // Buildings ordered by code
+    fn list_buildings(&self) -> Result<Vec<Building>, StoreError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StoreError {
+    NotFound(String),
+    Conflict(String),
+    // A check the caller passed in refused the change
+    NotAllowed(String),
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::NotFound(e) | StoreError::Conflict(e) | StoreError::NotAllowed(e) | StoreError::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<PostgresError> for StoreError {
+    fn from(e: PostgresError) -> Self {
+        match e.code() {
+            Some(&SqlState::UNIQUE_VIOLATION) => already_registered(),
+            Some(&SqlState::FOREIGN_KEY_VIOLATION) => not_registered(),
+            _ => StoreError::Backend(e.to_string())
+        }
+    }
+}
+
+impl From<SqliteError> for StoreError {
+    fn from(e: SqliteError) -> Self {
+        match e.sqlite_error() {
+            Some(err) if err.code == ErrorCode::ConstraintViolation =>
+                match err.extended_code {
+                    rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE => already_registered(),
+                    rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY => not_registered(),
+                    _ => StoreError::Backend(e.to_string())
+                },
+            _ => StoreError::Backend(e.to_string())
+        }
+    }
+}
+
+pub(crate) fn not_registered() -> StoreError {
+    StoreError::NotFound("Pets not registered to this apartment".to_string())
+}
+
+pub(crate) fn already_registered() -> StoreError {
+    StoreError::Conflict("Pets already registered to this apartment".to_string())
+}
+
+pub(crate) fn pets_changed() -> StoreError {
+    StoreError::Conflict("Pets changed while they were being updated, try again".to_string())
+}
+
+pub(crate) fn pet_not_found() -> StoreError {
+    StoreError::NotFound("Pet not found".to_string())
+}
+
+pub(crate) fn tenant_not_found() -> StoreError {
+    StoreError::NotFound("Tenant not found".to_string())
+}
+
+pub(crate) fn incident_not_found() -> StoreError {
+    StoreError::NotFound("Incident not found".to_string())
+}
+
+pub(crate) fn incident_resolved() -> StoreError {
+    StoreError::Conflict("Incident already resolved".to_string())
+}
+
+pub(crate) fn nothing_to_restore() -> StoreError {
+    StoreError::NotFound("No deleted apartment to restore".to_string())
+}
+
+pub(crate) fn building_not_found() -> StoreError {
+    StoreError::NotFound("Building not found".to_string())
+}
+
+pub(crate) fn building_taken(e: StoreError) -> StoreError {
+    match e {
+        StoreError::Conflict(_) => StoreError::Conflict("Building already exists".to_string()),
+        e => e
+    }
+}
+
+// Tenancies only reference their building
+pub(crate) fn tenancy_building_not_found(e: StoreError) -> StoreError {
+    match e {
+        StoreError::NotFound(_) => building_not_found(),
+        e => e
+    }
+}
+
+// The only reference a pet insert can break once the apartment exists
+pub(crate) fn owner_not_found(e: StoreError) -> StoreError {
+    match e {
+        StoreError::NotFound(_) => StoreError::NotFound("Owner is not a registered tenant".to_string()),
+        e => e
+    }
+}
+
+// The only unique index on pets besides the key, which generated ids never break
+pub(crate) fn microchip_taken(e: StoreError) -> StoreError {
+    match e {
+        StoreError::Conflict(_) => StoreError::Conflict("Microchip already registered".to_string()),
+        e => e
+    }
+}
+
+pub(crate) fn microchip_not_found() -> StoreError {
+    StoreError::NotFound("Microchip not found".to_string())
+}
+
+pub(crate) fn license_taken(e: StoreError) -> StoreError {
+    match e {
+        StoreError::Conflict(_) => StoreError::Conflict("License number already registered".to_string()),
+        e => e
+    }
+}
+
+// Same query for both backends, only the placeholder differs. A newer record of the same
+// vaccine, or a newer license, supersedes an older one.
+pub(crate) fn expiring_records_query(dialect: Dialect) -> String {
+    format!(
+        "SELECT * FROM (
+            SELECT apts.building, apts.unit, pets.animal, pets.id, pets.name, 'vaccination' AS record, v.vaccine AS detail, v.expires_on
+                FROM vaccinations v JOIN pets ON pets.animal = v.animal AND pets.id = v.pet_id JOIN apts ON apts.apt = pets.apt
+                WHERE v.expires_on <= {0} AND pets.deleted_at IS NULL AND NOT EXISTS (
+                    SELECT 1 FROM vaccinations newer
+                    WHERE newer.animal = v.animal AND newer.pet_id = v.pet_id AND lower(newer.vaccine) = lower(v.vaccine)
+                        AND (newer.expires_on > v.expires_on OR (newer.expires_on = v.expires_on AND newer.id > v.id)))
+            UNION ALL
+            SELECT apts.building, apts.unit, pets.animal, pets.id, pets.name, 'license' AS record, l.number AS detail, l.expires_on
+                FROM licenses l JOIN pets ON pets.animal = l.animal AND pets.id = l.pet_id JOIN apts ON apts.apt = pets.apt
+                WHERE l.expires_on <= {0} AND pets.deleted_at IS NULL AND NOT EXISTS (
+                    SELECT 1 FROM licenses newer
+                    WHERE newer.animal = l.animal AND newer.pet_id = l.pet_id
+                        AND (newer.expires_on > l.expires_on OR (newer.expires_on = l.expires_on AND newer.id > l.id)))
+        ) AS records
+        ORDER BY expires_on, building, length(unit), unit, animal, id",
+        dialect.placeholder(1)
+    )
+}
+
+// Bind values for generated SQL, each backend converts them to its own parameter type
+pub(crate) enum SqlParam {
+    Text(String),
+    Int(i32),
+    Number(f64),
+}
+
+// How each backend reaches into the attributes json
+#[derive(Clone, Copy)]
+pub(crate) enum Dialect {
+    Postgres,
+    Sqlite,
+}
+
+impl Dialect {
+    fn placeholder(&self, i: usize) -> String {
+        match self {
+            Dialect::Postgres => format!("${}", i),
+            Dialect::Sqlite => format!("?{}", i),
+        }
+    }
+
+    // Field names come from the species registry, quoted all the same
+    fn text(&self, field: &str) -> String {
+        let field = field.replace('\'', "''").replace('"', "");
+        match self {
+            Dialect::Postgres => format!("attributes->>'{}'", field),
+            Dialect::Sqlite => format!("json_extract(attributes, '$.\"{}\"')", field),
+        }
+    }
+
+    // NULL unless the attribute is a number, so other types never match a range
+    fn number(&self, field: &str) -> String {
+        let field = field.replace('\'', "''").replace('"', "");
+        match self {
+            Dialect::Postgres => format!(
+                "(CASE WHEN jsonb_typeof(attributes->'{0}') = 'number' THEN (attributes->>'{0}')::NUMERIC END)", field
+            ),
+            Dialect::Sqlite => format!(
+                "(CASE WHEN json_type(attributes, '$.\"{0}\"') IN ('integer', 'real') THEN json_extract(attributes, '$.\"{0}\"') END)",
+                field
+            ),
+        }
+    }
+
+    // NULL unless the unit is named by a plain number, like Apt::number
+    fn unit_number(&self) -> &'static str {
+        match self {
+            Dialect::Postgres => "(CASE WHEN apts.unit ~ '^[0-9]{1,9}$' THEN apts.unit::INTEGER END)",
+            Dialect::Sqlite =>
+                "(CASE WHEN apts.unit <> '' AND apts.unit NOT GLOB '*[^0-9]*' AND length(apts.unit) <= 9 THEN CAST(apts.unit AS INTEGER) END)",
+        }
+    }
+
+    fn number_param(&self, placeholder: String) -> String {
+        match self {
+            Dialect::Postgres => format!("{}::FLOAT8", placeholder),
+            Dialect::Sqlite => placeholder,
+        }
+    }
+}
+
+// WHERE clause over pets joined with apts for a search, with its bind values in placeholder order
+pub(crate) fn search_clause(search: &PetSearch, dialect: Dialect) -> (String, Vec<SqlParam>) {
+    let mut params = Vec::new();
+    let mut param = |value: SqlParam| {
+        params.push(value);
+        dialect.placeholder(params.len())
+    };
+
+    let mut conditions = Vec::new();
+    if let Some(animal) = &search.animal {
+        conditions.push(format!("animal = {}", param(SqlParam::Text(animal.clone()))));
+    }
+    if let Some(status) = search.status {
+        conditions.push(format!("status = {}", param(SqlParam::Text(status.as_str().to_string()))));
+    }
+    if let Some(pattern) = search.name_pattern() {
+        conditions.push(format!("lower(name) LIKE {} ESCAPE '\\'", param(SqlParam::Text(pattern))));
+    }
+    for (field, value) in &search.equals {
+        conditions.push(format!("lower({}) = lower({})", dialect.text(field), param(SqlParam::Text(value.clone()))));
+    }
+    for (field, min) in &search.min {
+        conditions.push(format!("{} >= {}", dialect.number(field), dialect.number_param(param(SqlParam::Number(*min)))));
+    }
+    for (field, max) in &search.max {
+        conditions.push(format!("{} <= {}", dialect.number(field), dialect.number_param(param(SqlParam::Number(*max)))));
+    }
+    if let Some(building) = &search.building {
+        conditions.push(format!("apts.building = {}", param(SqlParam::Text(building.clone()))));
+    }
+    if let Some(from) = search.from_apt {
+        conditions.push(format!("{} >= {}", dialect.unit_number(), param(SqlParam::Int(from))));
+    }
+    if let Some(to) = search.to_apt {
+        conditions.push(format!("{} <= {}", dialect.unit_number(), param(SqlParam::Int(to))));
+    }
+
+    match conditions.is_empty() {
+        true => ("TRUE".to_string(), params),
+        false => (conditions.join(" AND "), params)
+    }
+}
+
+// Picks the backend from the url scheme, postgres databases are migrated before use
+pub fn open(db_url: &str) -> Result<Box<dyn PetStore>, StoreError> {
+    match db_url.split("://").next() {
+        Some("memory") => Ok(Box::new(MemoryStore::new())),
+        Some("postgres") | Some("postgresql") => Ok(Box::new(PostgresStore::connect(db_url)?)),
+        Some("sqlite") => Ok(Box::new(SqliteStore::connect(db_url)?)),
+        _ => Err(StoreError::Backend(format!("Unsupported database url: {}", db_url)))
+    }
+}
@@ -0,0 +1,833 @@
+use super::{
+    building_not_found, building_taken, expiring_records_query, incident_not_found, incident_resolved, license_taken, microchip_not_found,
+    microchip_taken, not_registered, nothing_to_restore, owner_not_found, pet_not_found, pets_changed, search_clause, tenancy_building_not_found, tenant_not_found, Dialect, PetStore,
+    SqlParam, StoreError
+};
+use crate::migrations;
+use crate::models::{
+    Action, Actor, ApartmentPets, ApartmentSummary, Apt, AuditEntry, Building, ExpiringRecord, Incident, IncidentType, License, Page, Pet, PetId,
+    PetRecords, PetRef, PetSearch, Pets, Review, Severity, Status, Tenancy, Tenant, Vaccination
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use postgres::{Client, NoTls, Statement};
+use postgres::types::ToSql;
+use postgres::Error as PostgresError;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+
+// Existence check and every pet of the apartment in a single round trip, no row means no apartment.
+// Rows become Pets as json, with the review columns nested the way Pet has them. Deleted
+// apartments and pets are left out here and everywhere else unless a query is about them.
+const SELECT_PETS: &str = "SELECT
+        (SELECT COALESCE(json_agg(to_jsonb(pets) || jsonb_build_object('review', jsonb_build_object(
+                'status', status, 'submitted_at', submitted_at, 'reviewed_by', reviewed_by, 'reviewed_at', reviewed_at, 'reason', reason
+            )) ORDER BY pets.id, pets.animal), '[]')
+            FROM pets WHERE pets.apt = apts.apt AND pets.deleted_at IS NULL) AS pets
+    FROM apts WHERE apts.building = $1 AND apts.unit = $2 AND apts.deleted_at IS NULL";
+const SELECT_APARTMENTS: &str = "SELECT apts.building, apts.unit, pets.animal, COUNT(pets.id) AS count
+    FROM (SELECT * FROM apts WHERE deleted_at IS NULL AND ($3::VARCHAR IS NULL OR building = $3)
+        ORDER BY building ASC, length(unit) ASC, unit ASC LIMIT $1 OFFSET $2) AS apts
+    LEFT JOIN pets ON pets.apt = apts.apt AND pets.deleted_at IS NULL
+    GROUP BY apts.building, apts.unit, pets.animal ORDER BY apts.building ASC, length(apts.unit) ASC, apts.unit ASC";
+const SELECT_APARTMENTS_DESC: &str = "SELECT apts.building, apts.unit, pets.animal, COUNT(pets.id) AS count
+    FROM (SELECT * FROM apts WHERE deleted_at IS NULL AND ($3::VARCHAR IS NULL OR building = $3)
+        ORDER BY building DESC, length(unit) DESC, unit DESC LIMIT $1 OFFSET $2) AS apts
+    LEFT JOIN pets ON pets.apt = apts.apt AND pets.deleted_at IS NULL
+    GROUP BY apts.building, apts.unit, pets.animal ORDER BY apts.building DESC, length(apts.unit) DESC, apts.unit DESC";
+const SELECT_APT_FOR_UPDATE: &str = "SELECT apt FROM apts WHERE building = $1 AND unit = $2 AND deleted_at IS NULL FOR UPDATE";
+// No row is inserted, and no key returned, unless the building exists
+const INSERT_APT: &str = "INSERT INTO apts (building, unit) SELECT code, $2 FROM buildings WHERE code = $1 RETURNING apt";
+// Pets deleted along with the apartment get the same deleted_at, NOW() is fixed for the transaction
+const DELETE_APT: &str = "UPDATE apts SET deleted_at = NOW() WHERE building = $1 AND unit = $2 AND deleted_at IS NULL RETURNING apt";
+// One statement whatever the batch size, rows are inserted in array order so the sequence
+// hands out ascending ids matching the input
+const INSERT_PETS: &str = "INSERT INTO pets (animal, name, attributes, owner_id, assistance, status, submitted_at, reviewed_by, reviewed_at, reason, microchip, apt)
+    SELECT animal, name, attributes, owner_id, assistance, status, submitted_at, reviewed_by, reviewed_at, reason, microchip, $12
+        FROM UNNEST(
+            $1::VARCHAR[], $2::VARCHAR[], $3::JSONB[], $4::INT[], $5::JSONB[],
+            $6::VARCHAR[], $7::TIMESTAMPTZ[], $8::VARCHAR[], $9::TIMESTAMPTZ[], $10::VARCHAR[], $11::VARCHAR[]
+        ) WITH ORDINALITY AS pets(animal, name, attributes, owner_id, assistance, status, submitted_at, reviewed_by, reviewed_at, reason, microchip, n)
+        ORDER BY n
+    RETURNING id";
+const SELECT_PET_FOR_UPDATE: &str = "SELECT pets.* FROM pets JOIN apts USING (apt)
+    WHERE apts.building = $1 AND apts.unit = $2 AND pets.animal = $3 AND pets.id = $4 AND pets.deleted_at IS NULL
+    FOR UPDATE OF pets";
+const REVIEW_PET: &str = "UPDATE pets SET status = $5, submitted_at = $6, reviewed_by = $7, reviewed_at = $8, reason = $9
+    FROM apts WHERE apts.apt = pets.apt AND apts.building = $1 AND apts.unit = $2 AND pets.animal = $3 AND pets.id = $4
+        AND pets.deleted_at IS NULL
+    RETURNING pets.*";
+const INSERT_AUDIT_ENTRY: &str = "INSERT INTO audit_log (at, actor, manager, claimed_actor, action, building, unit, animal, pet_id, before, after)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)";
+const SELECT_HISTORY: &str = "SELECT * FROM audit_log
+    WHERE building = $1 AND unit = $2 AND ($3::TIMESTAMPTZ IS NULL OR at <= $3)
+    ORDER BY id";
+const DELETE_PETS: &str = "UPDATE pets SET deleted_at = NOW() WHERE apt = $1 AND deleted_at IS NULL";
+const DELETE_PET: &str = "UPDATE pets SET deleted_at = NOW()
+    FROM apts WHERE apts.apt = pets.apt AND apts.building = $1 AND apts.unit = $2 AND pets.animal = $3 AND pets.id = $4
+        AND pets.deleted_at IS NULL
+    RETURNING pets.*";
+const SELECT_DELETED_PETS: &str = "SELECT pets.* FROM pets JOIN apts USING (apt)
+    WHERE apts.building = $1 AND apts.unit = $2 AND pets.deleted_at IS NOT NULL
+    ORDER BY pets.deleted_at DESC, pets.id, pets.animal";
+const SELECT_DELETED_APT: &str = "SELECT apt, deleted_at FROM apts WHERE building = $1 AND unit = $2 AND deleted_at IS NOT NULL
+    ORDER BY deleted_at DESC, apt DESC LIMIT 1 FOR UPDATE";
+const RESTORE_APT: &str = "UPDATE apts SET deleted_at = NULL WHERE apt = $1";
+const RESTORE_APT_PETS: &str = "UPDATE pets SET deleted_at = NULL WHERE apt = $1 AND deleted_at = $2";
+// The pet may have been deleted with an earlier apartment of the unit, it moves to the one registered now
+const RESTORE_PET: &str = "UPDATE pets SET deleted_at = NULL, apt = $5
+    FROM apts WHERE apts.apt = pets.apt AND apts.building = $1 AND apts.unit = $2 AND pets.animal = $3 AND pets.id = $4
+        AND pets.deleted_at IS NOT NULL
+    RETURNING pets.*";
+const SELECT_MICROCHIP: &str = "SELECT apts.building, apts.unit, pets.* FROM pets JOIN apts ON apts.apt = pets.apt
+    WHERE pets.microchip = $1 AND pets.deleted_at IS NULL";
+// A deleted apartment only has deleted pets, none of them deleted after it
+const PURGE_PETS: &str = "DELETE FROM pets WHERE deleted_at <= $1 RETURNING *";
+const PURGE_APTS: &str = "DELETE FROM apts WHERE deleted_at <= $1";
+// Moved pets have to be live pets of the source apartment, the caller compares the count
+const TRANSFER_PETS: &str = "UPDATE pets SET apt = $2
+    FROM UNNEST($3::VARCHAR[], $4::INTEGER[]) AS moved (animal, id)
+    WHERE pets.apt = $1 AND pets.animal = moved.animal AND pets.id = moved.id AND pets.deleted_at IS NULL";
+const SELECT_PET: &str = "SELECT 1 FROM pets JOIN apts USING (apt)
+    WHERE apts.building = $1 AND apts.unit = $2 AND pets.animal = $3 AND pets.id = $4 AND pets.deleted_at IS NULL";
+// Nothing is inserted, and no id returned, unless the pet belongs to the apartment
+const INSERT_VACCINATION: &str = "INSERT INTO vaccinations (animal, pet_id, vaccine, given_on, expires_on, vet)
+    SELECT pets.animal, pets.id, $5, $6, $7, $8 FROM pets JOIN apts USING (apt)
+        WHERE apts.building = $1 AND apts.unit = $2 AND pets.animal = $3 AND pets.id = $4 AND pets.deleted_at IS NULL
+    RETURNING id";
+const INSERT_LICENSE: &str = "INSERT INTO licenses (animal, pet_id, number, issued_on, expires_on)
+    SELECT pets.animal, pets.id, $5, $6, $7 FROM pets JOIN apts USING (apt)
+        WHERE apts.building = $1 AND apts.unit = $2 AND pets.animal = $3 AND pets.id = $4 AND pets.deleted_at IS NULL
+    RETURNING id";
+// Nothing is inserted unless the apartment is registered and has the pet, when there is one
+const INSERT_INCIDENT: &str = "INSERT INTO incidents
+        (building, unit, animal, pet_id, type, severity, occurred_on, description, reporter, resolution, resolved_on)
+    SELECT apts.building, apts.unit, $3, $4, $5, $6, $7, $8, $9, $10, $11 FROM apts
+        WHERE apts.building = $1 AND apts.unit = $2 AND apts.deleted_at IS NULL AND ($3::VARCHAR IS NULL OR EXISTS (
+            SELECT 1 FROM pets WHERE pets.apt = apts.apt AND pets.animal = $3 AND pets.id = $4 AND pets.deleted_at IS NULL
+        ))
+    RETURNING id";
+const SELECT_INCIDENT: &str = "SELECT * FROM incidents WHERE id = $1";
+const SELECT_INCIDENTS: &str = "SELECT * FROM incidents
+    WHERE ($3::VARCHAR IS NULL AND building = $1 AND unit = $2) OR (animal = $3 AND pet_id = $4)
+    ORDER BY occurred_on, id";
+const RESOLVE_INCIDENT: &str = "UPDATE incidents SET resolution = $2, resolved_on = $3 WHERE id = $1 AND resolution IS NULL RETURNING *";
+const SELECT_OPEN_INCIDENT_COUNTS: &str = "SELECT pets.animal, pets.id, COUNT(*) AS count
+    FROM incidents JOIN pets ON pets.animal = incidents.animal AND pets.id = incidents.pet_id JOIN apts ON apts.apt = pets.apt
+    WHERE apts.building = $1 AND apts.unit = $2 AND apts.deleted_at IS NULL AND pets.deleted_at IS NULL AND incidents.resolution IS NULL
+    GROUP BY pets.animal, pets.id";
+const INSERT_TENANT: &str = "INSERT INTO tenants (name, email, phone) VALUES ($1, $2, $3) RETURNING id";
+const UPDATE_TENANT: &str = "UPDATE tenants SET name = $2, email = $3, phone = $4 WHERE id = $1";
+const DELETE_TENANT: &str = "DELETE FROM tenants WHERE id = $1";
+const INSERT_TENANCIES: &str = "INSERT INTO tenancies (tenant_id, building, unit, move_in, move_out)
+    SELECT $1, * FROM UNNEST($2::VARCHAR[], $3::VARCHAR[], $4::DATE[], $5::DATE[])";
+const DELETE_TENANCIES: &str = "DELETE FROM tenancies WHERE tenant_id = $1";
+// Tenants with their tenancies as json, oldest move-in first
+const SELECT_TENANTS: &str = "SELECT tenants.*,
+        (SELECT COALESCE(json_agg(
+                json_build_object('building', building, 'unit', unit, 'move_in', move_in, 'move_out', move_out)
+                ORDER BY move_in, building, length(unit), unit
+            ), '[]')
+            FROM tenancies WHERE tenancies.tenant_id = tenants.id) AS tenancies
+    FROM tenants
+    WHERE ($1::INT IS NULL OR id = $1)
+        AND ($2::VARCHAR IS NULL OR EXISTS (SELECT 1 FROM tenancies WHERE tenant_id = tenants.id AND building = $2 AND unit = $3))
+    ORDER BY id";
+const SELECT_VACCINATIONS: &str = "SELECT * FROM vaccinations WHERE animal = $1 AND pet_id = $2 ORDER BY given_on, id";
+const SELECT_LICENSES: &str = "SELECT * FROM licenses WHERE animal = $1 AND pet_id = $2 ORDER BY issued_on, id";
+const INSERT_PROPERTY: &str = "INSERT INTO properties (name) VALUES ($1) ON CONFLICT (name) DO NOTHING";
+const INSERT_BUILDING: &str = "INSERT INTO buildings (property_id, code, name) SELECT id, $2, $3 FROM properties WHERE name = $1";
+const SELECT_BUILDINGS: &str = "SELECT buildings.code, buildings.name, properties.name AS property
+    FROM buildings JOIN properties ON properties.id = buildings.property_id
+    ORDER BY buildings.code";
+
+pub struct PostgresStore {
+    db_url: String,
+    // Connections are checked out for one call and returned afterwards
+    idle: Mutex<Vec<Connection>>,
+}
+
+struct Connection {
+    client: Client,
+    statements: HashMap<String, Statement>,
+}
+
+impl Connection {
+    // Each query is parsed once per connection, later calls go straight to execution
+    fn prepare(&mut self, query: &str) -> Result<Statement, PostgresError> {
+        if let Some(statement) = self.statements.get(query) {
+            return Ok(statement.clone());
+        }
+        let statement = self.client.prepare(query)?;
+        self.statements.insert(query.to_string(), statement.clone());
+        Ok(statement)
+    }
+}
+
+impl PostgresStore {
+    pub fn connect(db_url: &str) -> Result<PostgresStore, StoreError> {
+        let mut client = Client::connect(db_url, NoTls)?;
+        migrations::migrate(&mut client).map_err(|e| StoreError::Backend(e.to_string()))?;
+        Ok(PostgresStore {
+            db_url: db_url.to_string(),
+            idle: Mutex::new(vec![Connection { client, statements: HashMap::new() }]),
+        })
+    }
+
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, StoreError>
+    ) -> Result<T, StoreError> {
+        let idle = self.idle.lock().unwrap().pop();
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => Connection { client: Client::connect(&self.db_url, NoTls)?, statements: HashMap::new() }
+        };
+        let res = f(&mut conn);
+        if !conn.client.is_closed() {
+            self.idle.lock().unwrap().push(conn);
+        }
+        res
+    }
+}
+
+impl PetStore for PostgresStore {
+    // Duplicates are rejected by the (building, unit) constraint
+    fn register_apartment(&self, apt: &Apt, pets: &Pets, actor: &Actor) -> Result<Pets, StoreError> {
+        self.with_connection(|conn| {
+            let insert_apt = conn.prepare(INSERT_APT)?;
+            let insert_pets = conn.prepare(INSERT_PETS)?;
+            let insert_entry = conn.prepare(INSERT_AUDIT_ENTRY)?;
+            let mut transaction = conn.client.transaction()?;
+            let key: i32 = match transaction.query_opt(&insert_apt, &[&apt.building, &apt.unit])? {
+                Some(row) => row.get("apt"),
+                None => return Err(building_not_found())
+            };
+            let pets = insert(&mut transaction, &insert_pets, key, pets)?;
+            record(&mut transaction, &insert_entry, &AuditEntry::new(actor, Action::Create, apt, None, None, Some(pets.clone())))?;
+            transaction.commit()?;
+            Ok(pets)
+        })
+    }
+
+    fn list_pets(&self, apt: &Apt) -> Result<Pets, StoreError> {
+        self.with_connection(|conn| {
+            let select_pets = conn.prepare(SELECT_PETS)?;
+            match conn.client.query_opt(&select_pets, &[&apt.building, &apt.unit])? {
+                Some(row) => Ok(Pets(from_json(row.get("pets"))?)),
+                None => Err(not_registered())
+            }
+        })
+    }
+
+    // The apartment's row is locked before its pets are read, so they are the ones replaced
+    fn update_pets(&self, apt: &Apt, pets: &Pets, replacing: &Pets, actor: &Actor) -> Result<Pets, StoreError> {
+        self.with_connection(|conn| {
+            let select_apt = conn.prepare(SELECT_APT_FOR_UPDATE)?;
+            let select_pets = conn.prepare(SELECT_PETS)?;
+            let delete_pets = conn.prepare(DELETE_PETS)?;
+            let insert_pets = conn.prepare(INSERT_PETS)?;
+            let insert_entry = conn.prepare(INSERT_AUDIT_ENTRY)?;
+            let mut transaction = conn.client.transaction()?;
+            let key: i32 = match transaction.query_opt(&select_apt, &[&apt.building, &apt.unit])? {
+                Some(row) => row.get("apt"),
+                None => return Err(not_registered())
+            };
+            let replaced = transaction.query_one(&select_pets, &[&apt.building, &apt.unit])?;
+            let replaced = Pets(from_json(replaced.get("pets"))?);
+            if replaced != *replacing {
+                return Err(pets_changed());
+            }
+            transaction.execute(&delete_pets, &[&key])?;
+            let pets = insert(&mut transaction, &insert_pets, key, pets)?;
+            record(&mut transaction, &insert_entry, &AuditEntry::new(actor, Action::Update, apt, None, Some(replaced), Some(pets.clone())))?;
+            transaction.commit()?;
+            Ok(pets)
+        })
+    }
+
+    fn delete_apartment(&self, apt: &Apt, actor: &Actor) -> Result<(), StoreError> {
+        self.with_connection(|conn| {
+            let select_apt = conn.prepare(SELECT_APT_FOR_UPDATE)?;
+            let select_pets = conn.prepare(SELECT_PETS)?;
+            let delete_apt = conn.prepare(DELETE_APT)?;
+            let delete_pets = conn.prepare(DELETE_PETS)?;
+            let insert_entry = conn.prepare(INSERT_AUDIT_ENTRY)?;
+            let mut transaction = conn.client.transaction()?;
+            if transaction.query_opt(&select_apt, &[&apt.building, &apt.unit])?.is_none() {
+                return Err(not_registered());
+            }
+            let deleted = transaction.query_one(&select_pets, &[&apt.building, &apt.unit])?;
+            let deleted = Pets(from_json(deleted.get("pets"))?);
+            let key: i32 = transaction.query_one(&delete_apt, &[&apt.building, &apt.unit])?.get("apt");
+            transaction.execute(&delete_pets, &[&key])?;
+            record(&mut transaction, &insert_entry, &AuditEntry::new(actor, Action::Delete, apt, None, Some(deleted), None))?;
+            transaction.commit()?;
+            Ok(())
+        })
+    }
+
+    fn delete_pet(&self, pet: &PetRef, actor: &Actor) -> Result<Pet, StoreError> {
+        self.with_connection(|conn| {
+            let delete_pet = conn.prepare(DELETE_PET)?;
+            let insert_entry = conn.prepare(INSERT_AUDIT_ENTRY)?;
+            let mut transaction = conn.client.transaction()?;
+            let deleted = match transaction.query_opt(&delete_pet, &[&pet.apt.building, &pet.apt.unit, &pet.animal, &pet.id])? {
+                Some(row) => self::pet(&row)?,
+                None => return Err(pet_not_found())
+            };
+            let entry = AuditEntry::new(actor, Action::Delete, &pet.apt, Some(pet), Some(Pets(vec![deleted.clone()])), None);
+            record(&mut transaction, &insert_entry, &entry)?;
+            transaction.commit()?;
+            Ok(deleted)
+        })
+    }
+
+    fn list_deleted(&self, apt: &Apt) -> Result<Pets, StoreError> {
+        self.with_connection(|conn| {
+            let select_deleted = conn.prepare(SELECT_DELETED_PETS)?;
+            let pets = conn.client.query(&select_deleted, &[&apt.building, &apt.unit])?.iter().map(pet).collect::<Result<_, _>>()?;
+            Ok(Pets(pets))
+        })
+    }
+
+    // Restoring an apartment while the unit is registered again breaks the unique index
+    fn restore_apartment(&self, apt: &Apt, actor: &Actor) -> Result<Pets, StoreError> {
+        self.with_connection(|conn| {
+            let select_deleted = conn.prepare(SELECT_DELETED_APT)?;
+            let restore_apt = conn.prepare(RESTORE_APT)?;
+            let restore_pets = conn.prepare(RESTORE_APT_PETS)?;
+            let select_pets = conn.prepare(SELECT_PETS)?;
+            let insert_entry = conn.prepare(INSERT_AUDIT_ENTRY)?;
+            let mut transaction = conn.client.transaction()?;
+            let (key, deleted_at): (i32, DateTime<Utc>) = match transaction.query_opt(&select_deleted, &[&apt.building, &apt.unit])? {
+                Some(row) => (row.get("apt"), row.get("deleted_at")),
+                None => return Err(nothing_to_restore())
+            };
+            transaction.execute(&restore_apt, &[&key])?;
+            transaction.execute(&restore_pets, &[&key, &deleted_at]).map_err(|e| microchip_taken(e.into()))?;
+            let pets = transaction.query_one(&select_pets, &[&apt.building, &apt.unit])?;
+            let pets = Pets(from_json(pets.get("pets"))?);
+            record(&mut transaction, &insert_entry, &AuditEntry::new(actor, Action::Create, apt, None, None, Some(pets.clone())))?;
+            transaction.commit()?;
+            Ok(pets)
+        })
+    }
+
+    fn restore_pet(&self, pet: &PetRef, allowed: &dyn Fn(&Pets) -> Result<(), String>, actor: &Actor) -> Result<Pet, StoreError> {
+        self.with_connection(|conn| {
+            let select_apt = conn.prepare(SELECT_APT_FOR_UPDATE)?;
+            let restore_pet = conn.prepare(RESTORE_PET)?;
+            let select_pets = conn.prepare(SELECT_PETS)?;
+            let insert_entry = conn.prepare(INSERT_AUDIT_ENTRY)?;
+            let mut transaction = conn.client.transaction()?;
+            let key: i32 = match transaction.query_opt(&select_apt, &[&pet.apt.building, &pet.apt.unit])? {
+                Some(row) => row.get("apt"),
+                None => return Err(not_registered())
+            };
+            let restored = match transaction.query_opt(&restore_pet, &[&pet.apt.building, &pet.apt.unit, &pet.animal, &pet.id, &key])
+                .map_err(|e| microchip_taken(e.into()))? {
+                Some(row) => self::pet(&row)?,
+                None => return Err(pet_not_found())
+            };
+            let pets = transaction.query_one(&select_pets, &[&pet.apt.building, &pet.apt.unit])?;
+            allowed(&Pets(from_json(pets.get("pets"))?)).map_err(StoreError::NotAllowed)?;
+            let entry = AuditEntry::new(actor, Action::Create, &pet.apt, Some(pet), None, Some(Pets(vec![restored.clone()])));
+            record(&mut transaction, &insert_entry, &entry)?;
+            transaction.commit()?;
+            Ok(restored)
+        })
+    }
+
+    // Records go with their pets through ON DELETE CASCADE
+    fn purge_deleted(&self, before: DateTime<Utc>) -> Result<Pets, StoreError> {
+        self.with_connection(|conn| {
+            let purge_pets = conn.prepare(PURGE_PETS)?;
+            let purge_apts = conn.prepare(PURGE_APTS)?;
+            let mut transaction = conn.client.transaction()?;
+            let pets = transaction.query(&purge_pets, &[&before])?.iter().map(pet).collect::<Result<_, _>>()?;
+            transaction.execute(&purge_apts, &[&before])?;
+            transaction.commit()?;
+            Ok(Pets(pets))
+        })
+    }
+
+    // Both apartments are locked in the same order whichever way pets move, so concurrent
+    // transfers between them wait for each other instead of deadlocking
+    fn transfer_pets(
+        &self,
+        from: &Apt,
+        to: &Apt,
+        pets: &[PetId],
+        allowed: &dyn Fn(&Pets) -> Result<(), String>,
+        actor: &Actor
+    ) -> Result<Pets, StoreError> {
+        self.with_connection(|conn| {
+            let select_apt = conn.prepare(SELECT_APT_FOR_UPDATE)?;
+            let insert_apt = conn.prepare(INSERT_APT)?;
+            let transfer_pets = conn.prepare(TRANSFER_PETS)?;
+            let select_pets = conn.prepare(SELECT_PETS)?;
+            let insert_entry = conn.prepare(INSERT_AUDIT_ENTRY)?;
+            let mut transaction = conn.client.transaction()?;
+            let mut keys: BTreeMap<&Apt, i32> = BTreeMap::new();
+            for apt in BTreeSet::from([from, to]) {
+                if let Some(row) = transaction.query_opt(&select_apt, &[&apt.building, &apt.unit])? {
+                    keys.insert(apt, row.get("apt"));
+                }
+            }
+            let from_key = *keys.get(from).ok_or_else(not_registered)?;
+            let source = transaction.query_one(&select_pets, &[&from.building, &from.unit])?;
+            let source = Pets(from_json(source.get("pets"))?);
+            let (to_key, destination): (i32, Option<Pets>) = match keys.get(to) {
+                Some(&key) => {
+                    let pets = transaction.query_one(&select_pets, &[&to.building, &to.unit])?;
+                    (key, Some(Pets(from_json(pets.get("pets"))?)))
+                },
+                None => match transaction.query_opt(&insert_apt, &[&to.building, &to.unit])? {
+                    Some(row) => (row.get("apt"), None),
+                    None => return Err(building_not_found())
+                }
+            };
+            let animals: Vec<&str> = pets.iter().map(|p| p.animal.as_str()).collect();
+            let ids: Vec<i32> = pets.iter().map(|p| p.id).collect();
+            if transaction.execute(&transfer_pets, &[&from_key, &to_key, &animals, &ids])? < pets.len() as u64 {
+                return Err(pet_not_found());
+            }
+            let staying = transaction.query_one(&select_pets, &[&from.building, &from.unit])?;
+            let staying = Pets(from_json(staying.get("pets"))?);
+            let moved = transaction.query_one(&select_pets, &[&to.building, &to.unit])?;
+            let moved = Pets(from_json(moved.get("pets"))?);
+            allowed(&moved).map_err(StoreError::NotAllowed)?;
+            record(&mut transaction, &insert_entry, &AuditEntry::new(actor, Action::Update, from, None, Some(source), Some(staying)))?;
+            let action = match destination {
+                Some(_) => Action::Update,
+                None => Action::Create
+            };
+            record(&mut transaction, &insert_entry, &AuditEntry::new(actor, action, to, None, destination, Some(moved.clone())))?;
+            transaction.commit()?;
+            Ok(moved)
+        })
+    }
+
+    fn find_microchip(&self, microchip: &str) -> Result<(Apt, Pet), StoreError> {
+        self.with_connection(|conn| {
+            let select_microchip = conn.prepare(SELECT_MICROCHIP)?;
+            match conn.client.query_opt(&select_microchip, &[&microchip])? {
+                Some(row) => Ok((Apt { building: row.get("building"), unit: row.get("unit") }, pet(&row)?)),
+                None => Err(microchip_not_found())
+            }
+        })
+    }
+
+    fn list_apartments(&self, page: &Page, building: Option<&str>) -> Result<Vec<ApartmentSummary>, StoreError> {
+        self.with_connection(|conn| {
+            let select_apartments = conn.prepare(match page.descending {
+                true => SELECT_APARTMENTS_DESC,
+                false => SELECT_APARTMENTS
+            })?;
+            // One row per apartment and animal, rows of the same apartment are adjacent
+            let mut apartments: Vec<ApartmentSummary> = Vec::new();
+            for row in conn.client.query(&select_apartments, &[&page.limit, &page.offset, &building])? {
+                let apt = Apt { building: row.get("building"), unit: row.get("unit") };
+                if apartments.last().is_none_or(|a| a.apt != apt) {
+                    apartments.push(ApartmentSummary { apt, pets: BTreeMap::new() });
+                }
+                if let Some(animal) = row.get::<_, Option<String>>("animal") {
+                    apartments.last_mut().unwrap().pets.insert(animal, row.get("count"));
+                }
+            }
+            Ok(apartments)
+        })
+    }
+
+    fn search_pets(&self, search: &PetSearch) -> Result<Vec<ApartmentPets>, StoreError> {
+        let (clause, params) = search_clause(search, Dialect::Postgres);
+        let query = format!(
+            "SELECT pets.*, apts.building, apts.unit FROM pets JOIN apts USING (apt)
+                WHERE pets.deleted_at IS NULL AND {} ORDER BY apts.building, length(apts.unit), apts.unit, pets.id, pets.animal",
+            clause
+        );
+        let params: Vec<Box<dyn ToSql + Sync>> = params.into_iter().map(|p| match p {
+            SqlParam::Text(v) => Box::new(v) as Box<dyn ToSql + Sync>,
+            SqlParam::Int(v) => Box::new(v),
+            SqlParam::Number(v) => Box::new(v),
+        }).collect();
+        let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref()).collect();
+
+        self.with_connection(|conn| {
+            let statement = conn.prepare(&query)?;
+            let mut apartments: BTreeMap<Apt, Pets> = BTreeMap::new();
+            for row in conn.client.query(&statement, &params)? {
+                let apt = Apt { building: row.get("building"), unit: row.get("unit") };
+                apartments.entry(apt).or_default().0.push(pet(&row)?);
+            }
+            Ok(apartments.into_iter().map(|(apt, pets)| ApartmentPets { apt, pets }).collect())
+        })
+    }
+
+    fn add_vaccination(&self, pet: &PetRef, vaccination: &Vaccination) -> Result<Vaccination, StoreError> {
+        self.with_connection(|conn| {
+            let insert = conn.prepare(INSERT_VACCINATION)?;
+            let row = conn.client.query_opt(&insert, &[
+                &pet.apt.building, &pet.apt.unit, &pet.animal, &pet.id,
+                &vaccination.vaccine, &vaccination.given, &vaccination.expires, &vaccination.vet
+            ])?;
+            match row {
+                Some(row) => Ok(Vaccination { id: row.get("id"), ..vaccination.clone() }),
+                None => Err(pet_not_found())
+            }
+        })
+    }
+
+    fn add_license(&self, pet: &PetRef, license: &License) -> Result<License, StoreError> {
+        self.with_connection(|conn| {
+            let insert = conn.prepare(INSERT_LICENSE)?;
+            let row = conn.client.query_opt(&insert, &[
+                &pet.apt.building, &pet.apt.unit, &pet.animal, &pet.id, &license.number, &license.issued, &license.expires
+            ]).map_err(|e| license_taken(e.into()))?;
+            match row {
+                Some(row) => Ok(License { id: row.get("id"), ..license.clone() }),
+                None => Err(pet_not_found())
+            }
+        })
+    }
+
+    fn review_pet(&self, pet: &PetRef, review: &Review, actor: &Actor) -> Result<Pet, StoreError> {
+        self.with_connection(|conn| {
+            let select_pet = conn.prepare(SELECT_PET_FOR_UPDATE)?;
+            let review_pet = conn.prepare(REVIEW_PET)?;
+            let insert_entry = conn.prepare(INSERT_AUDIT_ENTRY)?;
+            let mut transaction = conn.client.transaction()?;
+            let stored = match transaction.query_opt(&select_pet, &[&pet.apt.building, &pet.apt.unit, &pet.animal, &pet.id])? {
+                Some(row) => self::pet(&row)?,
+                None => return Err(pet_not_found())
+            };
+            let reviewed = self::pet(&transaction.query_one(&review_pet, &[
+                &pet.apt.building, &pet.apt.unit, &pet.animal, &pet.id,
+                &review.status.as_str(), &review.submitted_at, &review.reviewed_by, &review.reviewed_at, &review.reason
+            ])?)?;
+            let entry = AuditEntry::new(actor, Action::Update, &pet.apt, Some(pet), Some(Pets(vec![stored])), Some(Pets(vec![reviewed.clone()])));
+            record(&mut transaction, &insert_entry, &entry)?;
+            transaction.commit()?;
+            Ok(reviewed)
+        })
+    }
+
+    fn history(&self, apt: &Apt, until: Option<DateTime<Utc>>) -> Result<Vec<AuditEntry>, StoreError> {
+        self.with_connection(|conn| {
+            let select_history = conn.prepare(SELECT_HISTORY)?;
+            conn.client.query(&select_history, &[&apt.building, &apt.unit, &until])?.iter().map(|row| Ok(AuditEntry {
+                id: Some(row.get("id")),
+                at: row.get("at"),
+                actor: row.get("actor"),
+                manager: row.get("manager"),
+                claimed_actor: row.get("claimed_actor"),
+                action: Action::parse(row.get("action"))
+                    .ok_or_else(|| StoreError::Backend(format!("Unknown audit action: {}", row.get::<_, &str>("action"))))?,
+                apt: Apt { building: row.get("building"), unit: row.get("unit") },
+                pet: row.get::<_, Option<String>>("animal").zip(row.get("pet_id")),
+                before: row.get::<_, Option<serde_json::Value>>("before").map(from_json).transpose()?.map(Pets),
+                after: row.get::<_, Option<serde_json::Value>>("after").map(from_json).transpose()?.map(Pets),
+            })).collect()
+        })
+    }
+
+    fn list_records(&self, pet: &PetRef) -> Result<PetRecords, StoreError> {
+        self.with_connection(|conn| {
+            let select_pet = conn.prepare(SELECT_PET)?;
+            let select_vaccinations = conn.prepare(SELECT_VACCINATIONS)?;
+            let select_licenses = conn.prepare(SELECT_LICENSES)?;
+            if conn.client.query_opt(&select_pet, &[&pet.apt.building, &pet.apt.unit, &pet.animal, &pet.id])?.is_none() {
+                return Err(pet_not_found());
+            }
+            Ok(PetRecords {
+                vaccinations: conn.client.query(&select_vaccinations, &[&pet.animal, &pet.id])?.iter().map(|row| Vaccination {
+                    id: row.get("id"),
+                    vaccine: row.get("vaccine"),
+                    given: row.get("given_on"),
+                    expires: row.get("expires_on"),
+                    vet: row.get("vet"),
+                }).collect(),
+                licenses: conn.client.query(&select_licenses, &[&pet.animal, &pet.id])?.iter().map(|row| License {
+                    id: row.get("id"),
+                    number: row.get("number"),
+                    issued: row.get("issued_on"),
+                    expires: row.get("expires_on"),
+                }).collect(),
+            })
+        })
+    }
+
+    fn add_incident(&self, incident: &Incident) -> Result<Incident, StoreError> {
+        self.with_connection(|conn| {
+            let insert_incident = conn.prepare(INSERT_INCIDENT)?;
+            let (animal, pet_id) = incident.pet.as_ref().map(|p| (p.animal.as_str(), p.id)).unzip();
+            let row = conn.client.query_opt(&insert_incident, &[
+                &incident.apt.building, &incident.apt.unit, &animal, &pet_id, &incident.kind.as_str(), &incident.severity.as_str(),
+                &incident.date, &incident.description, &incident.reporter, &incident.resolution, &incident.resolved_on
+            ])?;
+            match (row, &incident.pet) {
+                (Some(row), _) => Ok(Incident { id: Some(row.get("id")), ..incident.clone() }),
+                (None, Some(_)) => Err(pet_not_found()),
+                (None, None) => Err(not_registered())
+            }
+        })
+    }
+
+    fn get_incident(&self, id: i32) -> Result<Incident, StoreError> {
+        self.with_connection(|conn| {
+            let select_incident = conn.prepare(SELECT_INCIDENT)?;
+            match conn.client.query_opt(&select_incident, &[&id])? {
+                Some(row) => self::incident(&row),
+                None => Err(incident_not_found())
+            }
+        })
+    }
+
+    fn list_incidents(&self, apt: &Apt, pet: Option<&PetId>) -> Result<Vec<Incident>, StoreError> {
+        self.with_connection(|conn| {
+            let select_incidents = conn.prepare(SELECT_INCIDENTS)?;
+            let (animal, pet_id) = pet.map(|p| (p.animal.as_str(), p.id)).unzip();
+            conn.client.query(&select_incidents, &[&apt.building, &apt.unit, &animal, &pet_id])?.iter().map(incident).collect()
+        })
+    }
+
+    // An incident that isn't updated either doesn't exist or is resolved already
+    fn resolve_incident(&self, id: i32, resolution: &str, resolved_on: NaiveDate) -> Result<Incident, StoreError> {
+        self.with_connection(|conn| {
+            let resolve_incident = conn.prepare(RESOLVE_INCIDENT)?;
+            let select_incident = conn.prepare(SELECT_INCIDENT)?;
+            if let Some(row) = conn.client.query_opt(&resolve_incident, &[&id, &resolution, &resolved_on])? {
+                return self::incident(&row);
+            }
+            match conn.client.query_opt(&select_incident, &[&id])? {
+                Some(_) => Err(incident_resolved()),
+                None => Err(incident_not_found())
+            }
+        })
+    }
+
+    fn open_incident_counts(&self, apt: &Apt) -> Result<BTreeMap<PetId, i64>, StoreError> {
+        self.with_connection(|conn| {
+            let select_counts = conn.prepare(SELECT_OPEN_INCIDENT_COUNTS)?;
+            Ok(conn.client.query(&select_counts, &[&apt.building, &apt.unit])?.iter()
+                .map(|row| (PetId { animal: row.get("animal"), id: row.get("id") }, row.get("count")))
+                .collect())
+        })
+    }
+
+    fn expiring_records(&self, by: NaiveDate) -> Result<Vec<ExpiringRecord>, StoreError> {
+        self.with_connection(|conn| {
+            let statement = conn.prepare(&expiring_records_query(Dialect::Postgres))?;
+            Ok(conn.client.query(&statement, &[&by])?.iter().map(|row| ExpiringRecord {
+                apt: Apt { building: row.get("building"), unit: row.get("unit") },
+                animal: row.get("animal"),
+                id: row.get("id"),
+                name: row.get("name"),
+                record: row.get("record"),
+                detail: row.get("detail"),
+                expires: row.get("expires_on"),
+            }).collect())
+        })
+    }
+
+    fn create_tenant(&self, tenant: &Tenant) -> Result<Tenant, StoreError> {
+        self.with_connection(|conn| {
+            let insert_tenant = conn.prepare(INSERT_TENANT)?;
+            let insert_tenancies = conn.prepare(INSERT_TENANCIES)?;
+            let mut transaction = conn.client.transaction()?;
+            let id: i32 = transaction.query_one(&insert_tenant, &[&tenant.name, &tenant.email, &tenant.phone])?.get("id");
+            insert_tenancies_of(&mut transaction, &insert_tenancies, id, tenant).map_err(|e| tenancy_building_not_found(e.into()))?;
+            transaction.commit()?;
+            Ok(id)
+        }).and_then(|id| self.get_tenant(id))
+    }
+
+    fn get_tenant(&self, id: i32) -> Result<Tenant, StoreError> {
+        self.with_connection(|conn| {
+            let select_tenants = conn.prepare(SELECT_TENANTS)?;
+            match conn.client.query_opt(&select_tenants, &[&Some(id), &None::<String>, &None::<String>])? {
+                Some(row) => tenant(&row),
+                None => Err(tenant_not_found())
+            }
+        })
+    }
+
+    fn update_tenant(&self, id: i32, tenant: &Tenant) -> Result<Tenant, StoreError> {
+        self.with_connection(|conn| {
+            let update_tenant = conn.prepare(UPDATE_TENANT)?;
+            let delete_tenancies = conn.prepare(DELETE_TENANCIES)?;
+            let insert_tenancies = conn.prepare(INSERT_TENANCIES)?;
+            let mut transaction = conn.client.transaction()?;
+            if transaction.execute(&update_tenant, &[&id, &tenant.name, &tenant.email, &tenant.phone])? == 0 {
+                return Err(tenant_not_found());
+            }
+            transaction.execute(&delete_tenancies, &[&id])?;
+            insert_tenancies_of(&mut transaction, &insert_tenancies, id, tenant).map_err(|e| tenancy_building_not_found(e.into()))?;
+            transaction.commit()?;
+            Ok(())
+        }).and_then(|_| self.get_tenant(id))
+    }
+
+    // Tenancies go through ON DELETE CASCADE, pet owners through ON DELETE SET NULL
+    fn delete_tenant(&self, id: i32) -> Result<(), StoreError> {
+        self.with_connection(|conn| {
+            let delete_tenant = conn.prepare(DELETE_TENANT)?;
+            match conn.client.execute(&delete_tenant, &[&id])? {
+                0 => Err(tenant_not_found()),
+                _ => Ok(())
+            }
+        })
+    }
+
+    fn list_tenants(&self, apt: Option<&Apt>) -> Result<Vec<Tenant>, StoreError> {
+        self.with_connection(|conn| {
+            let select_tenants = conn.prepare(SELECT_TENANTS)?;
+            let (building, unit) = (apt.map(|a| &a.building), apt.map(|a| &a.unit));
+            conn.client.query(&select_tenants, &[&None::<i32>, &building, &unit])?.iter().map(tenant).collect()
+        })
+    }
+
+    fn create_building(&self, building: &Building) -> Result<Building, StoreError> {
+        self.with_connection(|conn| {
+            let insert_property = conn.prepare(INSERT_PROPERTY)?;
+            let insert_building = conn.prepare(INSERT_BUILDING)?;
+            let mut transaction = conn.client.transaction()?;
+            transaction.execute(&insert_property, &[&building.property])?;
+            transaction.execute(&insert_building, &[&building.property, &building.code, &building.name])
+                .map_err(|e| building_taken(e.into()))?;
+            transaction.commit()?;
+            Ok(building.clone())
+        })
+    }
+
+    fn list_buildings(&self) -> Result<Vec<Building>, StoreError> {
+        self.with_connection(|conn| {
+            let select_buildings = conn.prepare(SELECT_BUILDINGS)?;
+            Ok(conn.client.query(&select_buildings, &[])?.iter().map(|row| Building {
+                code: row.get("code"),
+                name: row.get("name"),
+                property: row.get("property"),
+            }).collect())
+        })
+    }
+}
+
+fn tenant(row: &postgres::Row) -> Result<Tenant, StoreError> {
+    Ok(Tenant {
+        id: row.get("id"),
+        name: row.get("name"),
+        email: row.get("email"),
+        phone: row.get("phone"),
+        tenancies: from_json(row.get("tenancies"))?,
+    })
+}
+
+fn insert_tenancies_of(
+    transaction: &mut postgres::Transaction,
+    insert_tenancies: &Statement,
+    id: i32,
+    tenant: &Tenant
+) -> Result<(), PostgresError> {
+    let tenancies: &[Tenancy] = &tenant.tenancies;
+    transaction.execute(insert_tenancies, &[
+        &id,
+        &tenancies.iter().map(|t| t.building.as_str()).collect::<Vec<_>>(),
+        &tenancies.iter().map(|t| t.unit.as_str()).collect::<Vec<_>>(),
+        &tenancies.iter().map(|t| t.move_in).collect::<Vec<_>>(),
+        &tenancies.iter().map(|t| t.move_out).collect::<Vec<_>>(),
+    ])?;
+    Ok(())
+}
+
+fn pet(row: &postgres::Row) -> Result<Pet, StoreError> {
+    Ok(Pet {
+        id: row.get("id"),
+        animal: row.get("animal"),
+        name: row.get("name"),
+        attributes: from_json(row.get("attributes"))?,
+        owner: row.get("owner_id"),
+        microchip: row.get("microchip"),
+        assistance: row.get::<_, Option<serde_json::Value>>("assistance").map(from_json).transpose()?,
+        review: Review {
+            status: status(row.get("status"))?,
+            submitted_at: row.get("submitted_at"),
+            reviewed_by: row.get("reviewed_by"),
+            reviewed_at: row.get("reviewed_at"),
+            reason: row.get("reason"),
+        },
+        deleted_at: row.get("deleted_at"),
+    })
+}
+
+fn incident(row: &postgres::Row) -> Result<Incident, StoreError> {
+    Ok(Incident {
+        id: row.get("id"),
+        apt: Apt { building: row.get("building"), unit: row.get("unit") },
+        pet: row.get::<_, Option<String>>("animal").zip(row.get("pet_id")).map(|(animal, id)| PetId { animal, id }),
+        kind: IncidentType::parse(row.get("type"))
+            .ok_or_else(|| StoreError::Backend(format!("Unknown incident type: {}", row.get::<_, &str>("type"))))?,
+        severity: Severity::parse(row.get("severity"))
+            .ok_or_else(|| StoreError::Backend(format!("Unknown incident severity: {}", row.get::<_, &str>("severity"))))?,
+        date: row.get("occurred_on"),
+        description: row.get("description"),
+        reporter: row.get("reporter"),
+        resolution: row.get("resolution"),
+        resolved_on: row.get("resolved_on"),
+    })
+}
+
+fn status(status: &str) -> Result<Status, StoreError> {
+    Status::parse(status).ok_or_else(|| StoreError::Backend(format!("Unknown pet status: {}", status)))
+}
+
+fn to_json(pets: &Pets) -> serde_json::Value {
+    serde_json::to_value(&pets.0).unwrap()
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> Result<T, StoreError> {
+    serde_json::from_value(value).map_err(|e| StoreError::Backend(e.to_string()))
+}
+
+fn insert(
+    transaction: &mut postgres::Transaction,
+    insert_pets: &Statement,
+    key: i32,
+    pets: &Pets
+) -> Result<Pets, StoreError> {
+    let mut pets = pets.clone();
+    if !pets.is_empty() {
+        let rows = transaction.query(insert_pets, &[
+            &pets.0.iter().map(|p| p.animal.as_str()).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| serde_json::Value::Object(p.attributes.clone())).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.owner).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.assistance.as_ref().map(|a| serde_json::to_value(a).unwrap())).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.review.status.as_str()).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.review.submitted_at).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.review.reviewed_by.as_deref()).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.review.reviewed_at).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.review.reason.as_deref()).collect::<Vec<_>>(),
+            &pets.0.iter().map(|p| p.microchip.as_deref()).collect::<Vec<_>>(),
+            &key
+        ]).map_err(|e| microchip_taken(owner_not_found(e.into())))?;
+        for (pet, id) in pets.0.iter_mut().zip(returned_ids(rows)) {
+            pet.id = Some(id);
+        }
+    }
+    Ok(pets)
+}
+
+fn record(transaction: &mut postgres::Transaction, insert_entry: &Statement, entry: &AuditEntry) -> Result<(), PostgresError> {
+    let (animal, pet_id) = entry.pet.clone().unzip();
+    transaction.execute(insert_entry, &[
+        &entry.at, &entry.actor, &entry.manager, &entry.claimed_actor, &entry.action.as_str(), &entry.apt.building, &entry.apt.unit, &animal, &pet_id,
+        &entry.before.as_ref().map(to_json), &entry.after.as_ref().map(to_json)
+    ])?;
+    Ok(())
+}
+
+fn returned_ids(rows: Vec<postgres::Row>) -> Vec<i32> {
+    let mut ids: Vec<i32> = rows.iter().map(|row| row.get("id")).collect();
+    ids.sort();
+    ids
+}
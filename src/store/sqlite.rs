@@ -0,0 +1,670 @@
+use super::{
+    building_not_found, building_taken, expiring_records_query, incident_not_found, incident_resolved, license_taken, microchip_not_found,
+    microchip_taken, not_registered, nothing_to_restore, owner_not_found, pet_not_found, pets_changed, search_clause, tenancy_building_not_found, tenant_not_found,
+    Dialect, PetStore, SqlParam, StoreError
+};
+use crate::migrations;
+use crate::models::{
+    Action, Actor, ApartmentPets, ApartmentSummary, Apt, AuditEntry, Building, ExpiringRecord, Incident, IncidentType, License, Page, Pet, PetId,
+    PetRecords, PetRef, PetSearch, Pets, Review, Severity, Status, Tenancy, Tenant, Vaccination
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{params_from_iter, Connection, OptionalExtension, Row, ToSql, Transaction, TransactionBehavior};
+use rusqlite::types::{ToSqlOutput, Type};
+use rusqlite::Error as SqliteError;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// A single connection shared by every worker, SQLite only allows one writer at a time anyway
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn connect(db_url: &str) -> Result<SqliteStore, StoreError> {
+        let mut conn = open_connection(db_url)?;
+        migrations::migrate(&mut conn).map_err(|e| StoreError::Backend(e.to_string()))?;
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+}
+
+// Accepts sqlite://path/to/file.db, or sqlite://:memory: for a throwaway database
+pub fn open_connection(db_url: &str) -> Result<Connection, SqliteError> {
+    let conn = Connection::open(db_url.trim_start_matches("sqlite://"))?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+    Ok(conn)
+}
+
+impl PetStore for SqliteStore {
+    // Duplicates are rejected by the (building, unit) index
+    fn register_apartment(&self, apt: &Apt, pets: &Pets, actor: &Actor) -> Result<Pets, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let key = insert_apt(&transaction, apt)?;
+        let pets = insert_pets(&transaction, key, pets).map_err(|e| microchip_taken(owner_not_found(e.into())))?;
+        record(&transaction, &AuditEntry::new(actor, Action::Create, apt, None, None, Some(pets.clone())))?;
+        transaction.commit()?;
+        Ok(pets)
+    }
+
+    fn list_pets(&self, apt: &Apt) -> Result<Pets, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let key = apt_key(&conn, apt)?.ok_or_else(not_registered)?;
+        Ok(live_pets(&conn, key)?)
+    }
+
+    // Takes the write lock up front so the pets compared are the ones replaced
+    fn update_pets(&self, apt: &Apt, pets: &Pets, replacing: &Pets, actor: &Actor) -> Result<Pets, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let key = apt_key(&transaction, apt)?.ok_or_else(not_registered)?;
+        let replaced = live_pets(&transaction, key)?;
+        if replaced != *replacing {
+            return Err(pets_changed());
+        }
+        transaction.execute("UPDATE pets SET deleted_at = ?2 WHERE apt = ?1 AND deleted_at IS NULL", (key, Utc::now()))?;
+        let pets = insert_pets(&transaction, key, pets).map_err(|e| microchip_taken(owner_not_found(e.into())))?;
+        record(&transaction, &AuditEntry::new(actor, Action::Update, apt, None, Some(replaced), Some(pets.clone())))?;
+        transaction.commit()?;
+        Ok(pets)
+    }
+
+    // Pets deleted along with the apartment get the same deleted_at
+    fn delete_apartment(&self, apt: &Apt, actor: &Actor) -> Result<(), StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let key = apt_key(&transaction, apt)?.ok_or_else(not_registered)?;
+        let deleted = live_pets(&transaction, key)?;
+        let now = Utc::now();
+        transaction.execute("UPDATE apts SET deleted_at = ?2 WHERE apt = ?1", (key, now))?;
+        transaction.execute("UPDATE pets SET deleted_at = ?2 WHERE apt = ?1 AND deleted_at IS NULL", (key, now))?;
+        record(&transaction, &AuditEntry::new(actor, Action::Delete, apt, None, Some(deleted), None))?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn delete_pet(&self, pet: &PetRef, actor: &Actor) -> Result<Pet, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let key = apt_key(&transaction, &pet.apt)?.ok_or_else(pet_not_found)?;
+        let deleted = transaction.prepare_cached(
+            "UPDATE pets SET deleted_at = ?4 WHERE apt = ?1 AND animal = ?2 AND id = ?3 AND deleted_at IS NULL RETURNING *"
+        )?.query_row((key, &pet.animal, pet.id, Utc::now()), self::pet).optional()?.ok_or_else(pet_not_found)?;
+        record(&transaction, &AuditEntry::new(actor, Action::Delete, &pet.apt, Some(pet), Some(Pets(vec![deleted.clone()])), None))?;
+        transaction.commit()?;
+        Ok(deleted)
+    }
+
+    fn list_deleted(&self, apt: &Apt) -> Result<Pets, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let pets = conn.prepare_cached(
+            "SELECT pets.* FROM pets JOIN apts ON apts.apt = pets.apt
+                WHERE apts.building = ?1 AND apts.unit = ?2 AND pets.deleted_at IS NOT NULL
+                ORDER BY pets.deleted_at DESC, pets.id, pets.animal"
+        )?.query_map((&apt.building, &apt.unit), pet)?.collect::<Result<_, _>>()?;
+        Ok(Pets(pets))
+    }
+
+    // Restoring an apartment while the unit is registered again breaks the (building, unit) index
+    fn restore_apartment(&self, apt: &Apt, actor: &Actor) -> Result<Pets, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let deleted: Option<(i32, String)> = transaction.prepare_cached(
+            "SELECT apt, deleted_at FROM apts WHERE building = ?1 AND unit = ?2 AND deleted_at IS NOT NULL
+                ORDER BY deleted_at DESC, apt DESC LIMIT 1"
+        )?.query_row((&apt.building, &apt.unit), |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+        let (key, deleted_at) = deleted.ok_or_else(nothing_to_restore)?;
+        transaction.execute("UPDATE apts SET deleted_at = NULL WHERE apt = ?1", [key])?;
+        transaction.execute("UPDATE pets SET deleted_at = NULL WHERE apt = ?1 AND deleted_at = ?2", (key, &deleted_at))
+            .map_err(|e| microchip_taken(e.into()))?;
+        let pets = live_pets(&transaction, key)?;
+        record(&transaction, &AuditEntry::new(actor, Action::Create, apt, None, None, Some(pets.clone())))?;
+        transaction.commit()?;
+        Ok(pets)
+    }
+
+    // The pet may have been deleted with an earlier apartment of the unit, it moves to the one
+    // registered now. Takes the write lock up front, like transfers, for the check.
+    fn restore_pet(&self, pet: &PetRef, allowed: &dyn Fn(&Pets) -> Result<(), String>, actor: &Actor) -> Result<Pet, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let key = apt_key(&transaction, &pet.apt)?.ok_or_else(not_registered)?;
+        let restored = transaction.prepare_cached(
+            "UPDATE pets SET deleted_at = NULL, apt = ?5
+                WHERE animal = ?3 AND id = ?4 AND deleted_at IS NOT NULL
+                    AND apt IN (SELECT apt FROM apts WHERE building = ?1 AND unit = ?2)
+                RETURNING *"
+        )?.query_row((&pet.apt.building, &pet.apt.unit, &pet.animal, pet.id, key), self::pet).optional()
+            .map_err(|e| microchip_taken(e.into()))?.ok_or_else(pet_not_found)?;
+        allowed(&live_pets(&transaction, key)?).map_err(StoreError::NotAllowed)?;
+        record(&transaction, &AuditEntry::new(actor, Action::Create, &pet.apt, Some(pet), None, Some(Pets(vec![restored.clone()]))))?;
+        transaction.commit()?;
+        Ok(restored)
+    }
+
+    // Records go with their pets through ON DELETE CASCADE. A deleted apartment only has
+    // deleted pets, none of them deleted after it.
+    fn purge_deleted(&self, before: DateTime<Utc>) -> Result<Pets, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let pets = transaction.prepare_cached("DELETE FROM pets WHERE deleted_at <= ?1 RETURNING *")?
+            .query_map([before], pet)?.collect::<Result<_, _>>()?;
+        transaction.execute("DELETE FROM apts WHERE deleted_at <= ?1", [before])?;
+        transaction.commit()?;
+        Ok(Pets(pets))
+    }
+
+    // Takes the write lock up front so another process can't change either apartment between
+    // reading the pets and the check
+    fn transfer_pets(
+        &self,
+        from: &Apt,
+        to: &Apt,
+        pets: &[PetId],
+        allowed: &dyn Fn(&Pets) -> Result<(), String>,
+        actor: &Actor
+    ) -> Result<Pets, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let from_key = apt_key(&transaction, from)?.ok_or_else(not_registered)?;
+        let source = live_pets(&transaction, from_key)?;
+        let (to_key, destination) = match apt_key(&transaction, to)? {
+            Some(key) => (key, Some(live_pets(&transaction, key)?)),
+            None => (insert_apt(&transaction, to)?, None)
+        };
+        for pet in pets {
+            let moved = transaction.prepare_cached(
+                "UPDATE pets SET apt = ?2 WHERE apt = ?1 AND animal = ?3 AND id = ?4 AND deleted_at IS NULL"
+            )?.execute((from_key, to_key, &pet.animal, pet.id))?;
+            if moved == 0 {
+                return Err(pet_not_found());
+            }
+        }
+        let staying = live_pets(&transaction, from_key)?;
+        let moved = live_pets(&transaction, to_key)?;
+        allowed(&moved).map_err(StoreError::NotAllowed)?;
+        record(&transaction, &AuditEntry::new(actor, Action::Update, from, None, Some(source), Some(staying)))?;
+        let action = match destination {
+            Some(_) => Action::Update,
+            None => Action::Create
+        };
+        record(&transaction, &AuditEntry::new(actor, action, to, None, destination, Some(moved.clone())))?;
+        transaction.commit()?;
+        Ok(moved)
+    }
+
+    fn find_microchip(&self, microchip: &str) -> Result<(Apt, Pet), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let found = conn.prepare_cached(
+            "SELECT apts.building, apts.unit, pets.* FROM pets JOIN apts ON apts.apt = pets.apt
+                WHERE pets.microchip = ?1 AND pets.deleted_at IS NULL"
+        )?.query_row([microchip], |row| Ok((Apt { building: row.get("building")?, unit: row.get("unit")? }, pet(row)?))).optional()?;
+        found.ok_or_else(microchip_not_found)
+    }
+
+    fn list_apartments(&self, page: &Page, building: Option<&str>) -> Result<Vec<ApartmentSummary>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare_cached(&format!(
+            "SELECT apts.building, apts.unit, pets.animal, COUNT(pets.id) AS count
+                FROM (SELECT * FROM apts WHERE deleted_at IS NULL AND (?3 IS NULL OR building = ?3)
+                    ORDER BY building {0}, length(unit) {0}, unit {0} LIMIT ?1 OFFSET ?2) AS apts
+                LEFT JOIN pets ON pets.apt = apts.apt AND pets.deleted_at IS NULL
+                GROUP BY apts.building, apts.unit, pets.animal ORDER BY apts.building {0}, length(apts.unit) {0}, apts.unit {0}",
+            if page.descending { "DESC" } else { "ASC" }
+        ))?;
+        // One row per apartment and animal, rows of the same apartment are adjacent
+        let mut apartments: Vec<ApartmentSummary> = Vec::new();
+        let mut rows = statement.query((page.limit, page.offset, building))?;
+        while let Some(row) = rows.next()? {
+            let apt = Apt { building: row.get("building")?, unit: row.get("unit")? };
+            if apartments.last().is_none_or(|a| a.apt != apt) {
+                apartments.push(ApartmentSummary { apt, pets: BTreeMap::new() });
+            }
+            if let Some(animal) = row.get::<_, Option<String>>("animal")? {
+                apartments.last_mut().unwrap().pets.insert(animal, row.get("count")?);
+            }
+        }
+        Ok(apartments)
+    }
+
+    fn search_pets(&self, search: &PetSearch) -> Result<Vec<ApartmentPets>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let (clause, params) = search_clause(search, Dialect::Sqlite);
+        let mut apartments: BTreeMap<Apt, Pets> = BTreeMap::new();
+        let mut statement = conn.prepare_cached(&format!(
+            "SELECT pets.*, apts.building, apts.unit FROM pets JOIN apts ON apts.apt = pets.apt
+                WHERE pets.deleted_at IS NULL AND {} ORDER BY apts.building, length(apts.unit), apts.unit, pets.id, pets.animal",
+            clause
+        ))?;
+        let mut rows = statement.query(params_from_iter(&params))?;
+        while let Some(row) = rows.next()? {
+            let apt = Apt { building: row.get("building")?, unit: row.get("unit")? };
+            apartments.entry(apt).or_default().0.push(pet(row)?);
+        }
+        Ok(apartments.into_iter().map(|(apt, pets)| ApartmentPets { apt, pets }).collect())
+    }
+
+    // Nothing is inserted unless the pet belongs to the apartment
+    fn add_vaccination(&self, pet: &PetRef, vaccination: &Vaccination) -> Result<Vaccination, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let id = conn.prepare_cached(
+            "INSERT INTO vaccinations (animal, pet_id, vaccine, given_on, expires_on, vet)
+                SELECT pets.animal, pets.id, ?5, ?6, ?7, ?8 FROM pets JOIN apts ON apts.apt = pets.apt
+                    WHERE apts.building = ?1 AND apts.unit = ?2 AND pets.animal = ?3 AND pets.id = ?4 AND pets.deleted_at IS NULL
+                RETURNING id"
+        )?.query_row(
+            (&pet.apt.building, &pet.apt.unit, &pet.animal, pet.id, &vaccination.vaccine, vaccination.given, vaccination.expires, &vaccination.vet),
+            |row| row.get(0)
+        ).optional()?;
+        match id {
+            Some(id) => Ok(Vaccination { id: Some(id), ..vaccination.clone() }),
+            None => Err(pet_not_found())
+        }
+    }
+
+    fn add_license(&self, pet: &PetRef, license: &License) -> Result<License, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let id = conn.prepare_cached(
+            "INSERT INTO licenses (animal, pet_id, number, issued_on, expires_on)
+                SELECT pets.animal, pets.id, ?5, ?6, ?7 FROM pets JOIN apts ON apts.apt = pets.apt
+                    WHERE apts.building = ?1 AND apts.unit = ?2 AND pets.animal = ?3 AND pets.id = ?4 AND pets.deleted_at IS NULL
+                RETURNING id"
+        )?.query_row(
+            (&pet.apt.building, &pet.apt.unit, &pet.animal, pet.id, &license.number, license.issued, license.expires),
+            |row| row.get(0)
+        ).optional().map_err(|e| license_taken(e.into()))?;
+        match id {
+            Some(id) => Ok(License { id: Some(id), ..license.clone() }),
+            None => Err(pet_not_found())
+        }
+    }
+
+    fn review_pet(&self, pet: &PetRef, review: &Review, actor: &Actor) -> Result<Pet, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let key = match apt_key(&transaction, &pet.apt)? {
+            Some(key) => key,
+            None => return Err(pet_not_found())
+        };
+        let stored = transaction.prepare_cached("SELECT * FROM pets WHERE apt = ?1 AND animal = ?2 AND id = ?3 AND deleted_at IS NULL")?
+            .query_row((key, &pet.animal, pet.id), self::pet).optional()?.ok_or_else(pet_not_found)?;
+        let reviewed = transaction.prepare_cached(
+            "UPDATE pets SET status = ?4, submitted_at = ?5, reviewed_by = ?6, reviewed_at = ?7, reason = ?8
+                WHERE apt = ?1 AND animal = ?2 AND id = ?3 AND deleted_at IS NULL RETURNING *"
+        )?.query_row(
+            (key, &pet.animal, pet.id, review.status.as_str(), review.submitted_at, &review.reviewed_by, review.reviewed_at, &review.reason),
+            self::pet
+        )?;
+        record(&transaction, &AuditEntry::new(actor, Action::Update, &pet.apt, Some(pet), Some(Pets(vec![stored])), Some(Pets(vec![reviewed.clone()]))))?;
+        transaction.commit()?;
+        Ok(reviewed)
+    }
+
+    // Timestamps are all UTC text in the same format, so they compare in order
+    fn history(&self, apt: &Apt, until: Option<DateTime<Utc>>) -> Result<Vec<AuditEntry>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let entries = conn.prepare_cached(
+            "SELECT * FROM audit_log WHERE building = ?1 AND unit = ?2 AND (?3 IS NULL OR at <= ?3) ORDER BY id"
+        )?.query_map((&apt.building, &apt.unit, until), audit_entry)?.collect::<Result<_, _>>()?;
+        Ok(entries)
+    }
+
+    fn list_records(&self, pet: &PetRef) -> Result<PetRecords, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        if !conn.prepare_cached(
+            "SELECT 1 FROM pets JOIN apts ON apts.apt = pets.apt
+                WHERE apts.building = ?1 AND apts.unit = ?2 AND pets.animal = ?3 AND pets.id = ?4 AND pets.deleted_at IS NULL"
+        )?.exists((&pet.apt.building, &pet.apt.unit, &pet.animal, pet.id))? {
+            return Err(pet_not_found());
+        }
+        let vaccinations = conn.prepare_cached("SELECT * FROM vaccinations WHERE animal = ?1 AND pet_id = ?2 ORDER BY given_on, id")?
+            .query_map((&pet.animal, pet.id), |row| Ok(Vaccination {
+                id: row.get("id")?,
+                vaccine: row.get("vaccine")?,
+                given: row.get("given_on")?,
+                expires: row.get("expires_on")?,
+                vet: row.get("vet")?,
+            }))?.collect::<Result<_, _>>()?;
+        let licenses = conn.prepare_cached("SELECT * FROM licenses WHERE animal = ?1 AND pet_id = ?2 ORDER BY issued_on, id")?
+            .query_map((&pet.animal, pet.id), |row| Ok(License {
+                id: row.get("id")?,
+                number: row.get("number")?,
+                issued: row.get("issued_on")?,
+                expires: row.get("expires_on")?,
+            }))?.collect::<Result<_, _>>()?;
+        Ok(PetRecords { vaccinations, licenses })
+    }
+
+    // Nothing is inserted unless the apartment is registered and has the pet, when there is one
+    fn add_incident(&self, incident: &Incident) -> Result<Incident, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let (animal, pet_id) = incident.pet.as_ref().map(|p| (p.animal.as_str(), p.id)).unzip();
+        let id = conn.prepare_cached(
+            "INSERT INTO incidents (building, unit, animal, pet_id, type, severity, occurred_on, description, reporter, resolution, resolved_on)
+                SELECT apts.building, apts.unit, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11 FROM apts
+                    WHERE apts.building = ?1 AND apts.unit = ?2 AND apts.deleted_at IS NULL AND (?3 IS NULL OR EXISTS (
+                        SELECT 1 FROM pets WHERE pets.apt = apts.apt AND pets.animal = ?3 AND pets.id = ?4 AND pets.deleted_at IS NULL
+                    ))
+                RETURNING id"
+        )?.query_row(
+            rusqlite::params![
+                &incident.apt.building, &incident.apt.unit, animal, pet_id, incident.kind.as_str(), incident.severity.as_str(),
+                incident.date, &incident.description, &incident.reporter, &incident.resolution, incident.resolved_on
+            ],
+            |row| row.get(0)
+        ).optional()?;
+        match (id, &incident.pet) {
+            (Some(id), _) => Ok(Incident { id: Some(id), ..incident.clone() }),
+            (None, Some(_)) => Err(pet_not_found()),
+            (None, None) => Err(not_registered())
+        }
+    }
+
+    fn get_incident(&self, id: i32) -> Result<Incident, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let incident = conn.prepare_cached("SELECT * FROM incidents WHERE id = ?1")?.query_row([id], incident).optional()?;
+        incident.ok_or_else(incident_not_found)
+    }
+
+    fn list_incidents(&self, apt: &Apt, pet: Option<&PetId>) -> Result<Vec<Incident>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let (animal, pet_id) = pet.map(|p| (p.animal.as_str(), p.id)).unzip();
+        let incidents = conn.prepare_cached(
+            "SELECT * FROM incidents WHERE (?3 IS NULL AND building = ?1 AND unit = ?2) OR (animal = ?3 AND pet_id = ?4)
+                ORDER BY occurred_on, id"
+        )?.query_map((&apt.building, &apt.unit, animal, pet_id), incident)?.collect::<Result<_, _>>()?;
+        Ok(incidents)
+    }
+
+    // An incident that isn't updated either doesn't exist or is resolved already
+    fn resolve_incident(&self, id: i32, resolution: &str, resolved_on: NaiveDate) -> Result<Incident, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let resolved = conn.prepare_cached(
+            "UPDATE incidents SET resolution = ?2, resolved_on = ?3 WHERE id = ?1 AND resolution IS NULL RETURNING *"
+        )?.query_row((id, resolution, resolved_on), incident).optional()?;
+        match resolved {
+            Some(resolved) => Ok(resolved),
+            None => match conn.prepare_cached("SELECT 1 FROM incidents WHERE id = ?1")?.exists([id])? {
+                true => Err(incident_resolved()),
+                false => Err(incident_not_found())
+            }
+        }
+    }
+
+    fn open_incident_counts(&self, apt: &Apt) -> Result<BTreeMap<PetId, i64>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let counts = conn.prepare_cached(
+            "SELECT pets.animal, pets.id, COUNT(*) AS count
+                FROM incidents JOIN pets ON pets.animal = incidents.animal AND pets.id = incidents.pet_id JOIN apts ON apts.apt = pets.apt
+                WHERE apts.building = ?1 AND apts.unit = ?2 AND apts.deleted_at IS NULL AND pets.deleted_at IS NULL
+                    AND incidents.resolution IS NULL
+                GROUP BY pets.animal, pets.id"
+        )?.query_map((&apt.building, &apt.unit), |row| Ok((PetId { animal: row.get("animal")?, id: row.get("id")? }, row.get("count")?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(counts)
+    }
+
+    fn expiring_records(&self, by: NaiveDate) -> Result<Vec<ExpiringRecord>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let records = conn.prepare_cached(&expiring_records_query(Dialect::Sqlite))?
+            .query_map([by], |row| Ok(ExpiringRecord {
+                apt: Apt { building: row.get("building")?, unit: row.get("unit")? },
+                animal: row.get("animal")?,
+                id: row.get("id")?,
+                name: row.get("name")?,
+                record: row.get("record")?,
+                detail: row.get("detail")?,
+                expires: row.get("expires_on")?,
+            }))?.collect::<Result<_, _>>()?;
+        Ok(records)
+    }
+
+    fn create_tenant(&self, tenant: &Tenant) -> Result<Tenant, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let id = transaction.prepare_cached("INSERT INTO tenants (name, email, phone) VALUES (?1, ?2, ?3) RETURNING id")?
+            .query_row((&tenant.name, &tenant.email, &tenant.phone), |row| row.get(0))?;
+        insert_tenancies(&transaction, id, &tenant.tenancies).map_err(|e| tenancy_building_not_found(e.into()))?;
+        let tenant = select_tenant(&transaction, id)?;
+        transaction.commit()?;
+        Ok(tenant)
+    }
+
+    fn get_tenant(&self, id: i32) -> Result<Tenant, StoreError> {
+        select_tenant(&self.conn.lock().unwrap(), id)
+    }
+
+    fn update_tenant(&self, id: i32, tenant: &Tenant) -> Result<Tenant, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        let updated = transaction.prepare_cached("UPDATE tenants SET name = ?2, email = ?3, phone = ?4 WHERE id = ?1")?
+            .execute((id, &tenant.name, &tenant.email, &tenant.phone))?;
+        if updated == 0 {
+            return Err(tenant_not_found());
+        }
+        transaction.execute("DELETE FROM tenancies WHERE tenant_id = ?1", [id])?;
+        insert_tenancies(&transaction, id, &tenant.tenancies).map_err(|e| tenancy_building_not_found(e.into()))?;
+        let tenant = select_tenant(&transaction, id)?;
+        transaction.commit()?;
+        Ok(tenant)
+    }
+
+    // Tenancies go through ON DELETE CASCADE, pet owners through ON DELETE SET NULL
+    fn delete_tenant(&self, id: i32) -> Result<(), StoreError> {
+        match self.conn.lock().unwrap().execute("DELETE FROM tenants WHERE id = ?1", [id])? {
+            0 => Err(tenant_not_found()),
+            _ => Ok(())
+        }
+    }
+
+    fn list_tenants(&self, apt: Option<&Apt>) -> Result<Vec<Tenant>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let ids: Vec<i32> = conn.prepare_cached(
+            "SELECT id FROM tenants
+                WHERE ?1 IS NULL OR EXISTS (SELECT 1 FROM tenancies WHERE tenant_id = tenants.id AND building = ?1 AND unit = ?2)
+                ORDER BY id"
+        )?.query_map((apt.map(|a| &a.building), apt.map(|a| &a.unit)), |row| row.get(0))?.collect::<Result<_, _>>()?;
+        ids.into_iter().map(|id| select_tenant(&conn, id)).collect()
+    }
+
+    fn create_building(&self, building: &Building) -> Result<Building, StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        transaction.execute("INSERT INTO properties (name) VALUES (?1) ON CONFLICT (name) DO NOTHING", [&building.property])?;
+        transaction.execute(
+            "INSERT INTO buildings (property_id, code, name) SELECT id, ?2, ?3 FROM properties WHERE name = ?1",
+            (&building.property, &building.code, &building.name)
+        ).map_err(|e| building_taken(e.into()))?;
+        transaction.commit()?;
+        Ok(building.clone())
+    }
+
+    fn list_buildings(&self) -> Result<Vec<Building>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let buildings = conn.prepare_cached(
+            "SELECT buildings.code, buildings.name, properties.name AS property
+                FROM buildings JOIN properties ON properties.id = buildings.property_id
+                ORDER BY buildings.code"
+        )?.query_map([], |row| Ok(Building {
+            code: row.get("code")?,
+            name: row.get("name")?,
+            property: row.get("property")?,
+        }))?.collect::<Result<_, _>>()?;
+        Ok(buildings)
+    }
+}
+
+fn select_tenant(conn: &Connection, id: i32) -> Result<Tenant, StoreError> {
+    let tenant = conn.prepare_cached("SELECT * FROM tenants WHERE id = ?1")?
+        .query_row([id], |row| Ok(Tenant {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            email: row.get("email")?,
+            phone: row.get("phone")?,
+            tenancies: Vec::new(),
+        })).optional()?;
+    let mut tenant = tenant.ok_or_else(tenant_not_found)?;
+    tenant.tenancies = conn.prepare_cached("SELECT * FROM tenancies WHERE tenant_id = ?1 ORDER BY move_in, building, length(unit), unit")?
+        .query_map([id], |row| Ok(Tenancy {
+            building: row.get("building")?,
+            unit: row.get("unit")?,
+            move_in: row.get("move_in")?,
+            move_out: row.get("move_out")?,
+        }))?.collect::<Result<_, _>>()?;
+    Ok(tenant)
+}
+
+fn insert_tenancies(transaction: &Transaction, id: i32, tenancies: &[Tenancy]) -> Result<(), SqliteError> {
+    for tenancy in tenancies {
+        transaction.prepare_cached("INSERT INTO tenancies (tenant_id, building, unit, move_in, move_out) VALUES (?1, ?2, ?3, ?4, ?5)")?
+            .execute((id, &tenancy.building, &tenancy.unit, tenancy.move_in, tenancy.move_out))?;
+    }
+    Ok(())
+}
+
+impl ToSql for SqlParam {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            SqlParam::Text(v) => v.to_sql(),
+            SqlParam::Int(v) => v.to_sql(),
+            SqlParam::Number(v) => v.to_sql(),
+        }
+    }
+}
+
+fn pet(row: &Row) -> Result<Pet, SqliteError> {
+    let attributes: String = row.get("attributes")?;
+    Ok(Pet {
+        id: row.get("id")?,
+        animal: row.get("animal")?,
+        name: row.get("name")?,
+        attributes: serde_json::from_str(&attributes)
+            .map_err(|e| SqliteError::FromSqlConversionFailure(3, Type::Text, Box::new(e)))?,
+        owner: row.get("owner_id")?,
+        microchip: row.get("microchip")?,
+        assistance: row.get::<_, Option<String>>("assistance")?
+            .map(|assistance| serde_json::from_str(&assistance))
+            .transpose()
+            .map_err(|e| SqliteError::FromSqlConversionFailure(6, Type::Text, Box::new(e)))?,
+        review: Review {
+            status: row.get_ref("status")?.as_str().ok().and_then(Status::parse)
+                .ok_or_else(|| SqliteError::InvalidColumnType(7, "status".to_string(), Type::Text))?,
+            submitted_at: row.get("submitted_at")?,
+            reviewed_by: row.get("reviewed_by")?,
+            reviewed_at: row.get("reviewed_at")?,
+            reason: row.get("reason")?,
+        },
+        deleted_at: row.get("deleted_at")?,
+    })
+}
+
+fn audit_entry(row: &Row) -> Result<AuditEntry, SqliteError> {
+    let pets = |column: &str, index: usize| -> Result<Option<Pets>, SqliteError> {
+        row.get::<_, Option<String>>(column)?
+            .map(|pets| serde_json::from_str(&pets).map(Pets))
+            .transpose()
+            .map_err(|e| SqliteError::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+    };
+    Ok(AuditEntry {
+        id: row.get("id")?,
+        at: row.get("at")?,
+        actor: row.get("actor")?,
+        manager: row.get("manager")?,
+        claimed_actor: row.get("claimed_actor")?,
+        action: row.get_ref("action")?.as_str().ok().and_then(Action::parse)
+            .ok_or_else(|| SqliteError::InvalidColumnType(4, "action".to_string(), Type::Text))?,
+        apt: Apt { building: row.get("building")?, unit: row.get("unit")? },
+        pet: row.get::<_, Option<String>>("animal")?.zip(row.get("pet_id")?),
+        before: pets("before", 9)?,
+        after: pets("after", 10)?,
+    })
+}
+
+fn incident(row: &Row) -> Result<Incident, SqliteError> {
+    Ok(Incident {
+        id: row.get("id")?,
+        apt: Apt { building: row.get("building")?, unit: row.get("unit")? },
+        pet: row.get::<_, Option<String>>("animal")?.zip(row.get("pet_id")?).map(|(animal, id)| PetId { animal, id }),
+        kind: row.get_ref("type")?.as_str().ok().and_then(IncidentType::parse)
+            .ok_or_else(|| SqliteError::InvalidColumnType(6, "type".to_string(), Type::Text))?,
+        severity: row.get_ref("severity")?.as_str().ok().and_then(Severity::parse)
+            .ok_or_else(|| SqliteError::InvalidColumnType(7, "severity".to_string(), Type::Text))?,
+        date: row.get("occurred_on")?,
+        description: row.get("description")?,
+        reporter: row.get("reporter")?,
+        resolution: row.get("resolution")?,
+        resolved_on: row.get("resolved_on")?,
+    })
+}
+
+// The key pets use to refer to the apartment, None when it isn't registered or has been deleted
+fn apt_key(conn: &Connection, apt: &Apt) -> Result<Option<i32>, SqliteError> {
+    conn.prepare_cached("SELECT apt FROM apts WHERE building = ?1 AND unit = ?2 AND deleted_at IS NULL")?
+        .query_row((&apt.building, &apt.unit), |row| row.get(0))
+        .optional()
+}
+
+fn live_pets(conn: &Connection, key: i32) -> Result<Pets, SqliteError> {
+    let pets = conn.prepare_cached("SELECT * FROM pets WHERE apt = ?1 AND deleted_at IS NULL ORDER BY id, animal")?
+        .query_map([key], pet)?.collect::<Result<_, _>>()?;
+    Ok(Pets(pets))
+}
+
+// Entries are only ever inserted, the audit_log_no_update trigger keeps them that way
+fn record(conn: &Connection, entry: &AuditEntry) -> Result<(), SqliteError> {
+    let (animal, pet_id) = entry.pet.clone().unzip();
+    let json = |pets: &Pets| serde_json::to_string(&pets.0).unwrap();
+    conn.prepare_cached(
+        "INSERT INTO audit_log (at, actor, manager, claimed_actor, action, building, unit, animal, pet_id, before, after)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"
+    )?.execute((
+        entry.at, &entry.actor, entry.manager, &entry.claimed_actor, entry.action.as_str(), &entry.apt.building, &entry.apt.unit, animal, pet_id,
+        entry.before.as_ref().map(json), entry.after.as_ref().map(json)
+    ))?;
+    Ok(())
+}
+
+// Registers the apartment without pets, NotFound if its building doesn't exist. Keys come from
+// apts_apt_seq.
+fn insert_apt(transaction: &Transaction, apt: &Apt) -> Result<i32, StoreError> {
+    if !transaction.prepare_cached("SELECT 1 FROM buildings WHERE code = ?1")?.exists([&apt.building])? {
+        return Err(building_not_found());
+    }
+    let key: i32 = transaction.prepare_cached("UPDATE apts_apt_seq SET last_apt = last_apt + 1 RETURNING last_apt")?
+        .query_row([], |row| row.get(0))?;
+    transaction.prepare_cached("INSERT INTO apts (apt, building, unit) VALUES (?1, ?2, ?3)")?
+        .execute((key, &apt.building, &apt.unit))?;
+    Ok(key)
+}
+
+// Row at a time is fine here, there is no network round trip to save. Ids come from
+// pets_id_seq since SQLite has no sequences.
+fn insert_pets(transaction: &Transaction, key: i32, pets: &Pets) -> Result<Pets, SqliteError> {
+    let mut pets = pets.clone();
+    for pet in &mut pets.0 {
+        let id = transaction.prepare_cached("UPDATE pets_id_seq SET last_id = last_id + 1 RETURNING last_id")?
+            .query_row([], |row| row.get(0))?;
+        transaction.prepare_cached(
+            "INSERT INTO pets (id, animal, name, attributes, owner_id, assistance, status, submitted_at, reviewed_by, reviewed_at, reason, microchip, apt)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"
+        )?.execute(rusqlite::params![
+            id,
+            &pet.animal,
+            &pet.name,
+            serde_json::Value::Object(pet.attributes.clone()).to_string(),
+            pet.owner,
+            pet.assistance.as_ref().map(|a| serde_json::to_string(a).unwrap()),
+            pet.review.status.as_str(),
+            pet.review.submitted_at,
+            &pet.review.reviewed_by,
+            pet.review.reviewed_at,
+            &pet.review.reason,
+            &pet.microchip,
+            key
+        ])?;
+        pet.id = Some(id);
+    }
+    Ok(pets)
+}
@@ -0,0 +1,616 @@
+// Runs the same checks against every storage backend, one test per check and backend.
+// The Postgres tests are ignored unless asked for, with TEST_DB_URL pointing at a database they are
+// allowed to write to.
+use apt_pets::models::{
+    Action, Actor, ApartmentPets, ApartmentSummary, Apt, Assistance, AssistanceKind, Building, Documentation, Incident, IncidentType, License, NameMatch, Page, Pet, PetId, PetRef,
+    PetSearch, Pets, Review, Severity, Status, Tenancy, Tenant, Vaccination, Verification, DEFAULT_BUILDING
+};
+use chrono::{NaiveDate, Utc};
+use apt_pets::store::{self, MemoryStore, PetStore, SqliteStore, StoreError};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+const APT: i32 = 990001;
+const OTHER_APT: i32 = 990002;
+const BUILDING: &str = "test-annex";
+
+fn apt() -> Apt {
+    Apt::numbered(APT)
+}
+
+fn other_apt() -> Apt {
+    Apt::numbered(OTHER_APT)
+}
+
+// Indexes into pets() below
+const SUNNY: usize = 0;
+const PARIS: usize = 1;
+const NOVA: usize = 2;
+const POLLY: usize = 3;
+const THUMPER: usize = 4;
+
+fn pets() -> Pets {
+    Pets(vec![
+        Pet::new("Dog", "Sunny", json!({"weight": 70, "breed": "Labrador"})),
+        // Assistance designations round-trip with their documentation
+        Pet {
+            assistance: Some(Assistance {
+                kind: AssistanceKind::Service,
+                verification: Verification::Verified,
+                documentation: Some(Documentation { provider: "Dr. Reyes".to_string(), issued: date("2024-02-01"), expires: None, reference: Some("SA-7".to_string()) }),
+            }),
+            ..Pet::new("Dog", "Paris", json!({"weight": 60, "breed": "Poodle"}))
+        },
+        Pet::new("Cat", "Nova", json!({"weight": 12.5, "hair": "LongHaired"})),
+        Pet::new("Bird", "Polly", json!({"species": "Parrot"})),
+        // Not a built-in species, the store keeps whatever the registry validated
+        Pet::new("Rabbit", "Thumper", json!({"weight": 4, "color": "Grey"})),
+    ])
+}
+
+fn actor() -> Actor {
+    Actor { name: "Jo".to_string(), manager: true, claimed: None }
+}
+
+fn allow_all(_: &Pets) -> Result<(), String> {
+    Ok(())
+}
+
+fn without_ids(pets: &Pets) -> Pets {
+    let mut pets = pets.clone();
+    pets.0.iter_mut().for_each(|p| p.id = None);
+    pets
+}
+
+fn only(registered: &Pets, indexes: &[usize]) -> Pets {
+    Pets(indexes.iter().map(|&i| registered.0[i].clone()).collect())
+}
+
+// Registers the test apartments afresh, whatever an earlier test or run left behind
+fn setup(store: &dyn PetStore) -> Pets {
+    let _ = store.delete_apartment(&apt(), &actor());
+    let _ = store.delete_apartment(&other_apt(), &actor());
+    store.purge_deleted(Utc::now()).unwrap();
+
+    let registered = store.register_apartment(&apt(), &pets(), &actor()).unwrap();
+    store.register_apartment(&other_apt(), &Pets::default(), &actor()).unwrap();
+    registered
+}
+
+fn teardown(store: &dyn PetStore) {
+    store.delete_apartment(&apt(), &actor()).unwrap();
+    store.delete_apartment(&other_apt(), &actor()).unwrap();
+}
+
+fn check_apartments(store: &dyn PetStore) {
+    let registered = setup(store);
+    assert_eq!(without_ids(&registered), pets());
+    assert_eq!(store.list_pets(&apt()).unwrap(), registered);
+    // Ids follow input order
+    assert!(registered.0.windows(2).all(|p| p[0].id < p[1].id));
+
+    // Units are unique within a building and a failed registration leaves the first one untouched
+    assert!(matches!(store.register_apartment(&apt(), &Pets::default(), &actor()), Err(StoreError::Conflict(_))));
+    assert_eq!(store.list_pets(&apt()).unwrap(), registered);
+    assert_eq!(store.list_pets(&other_apt()).unwrap(), Pets::default());
+
+    // The test apartments sort after anything else in a shared database
+    let last = store.list_apartments(&Page { limit: 2, offset: 0, descending: true }, Some(DEFAULT_BUILDING)).unwrap();
+    assert_eq!(last, vec![
+        ApartmentSummary { apt: other_apt(), pets: BTreeMap::new() },
+        ApartmentSummary { apt: apt(), pets: BTreeMap::from([
+            ("Bird".to_string(), 1), ("Cat".to_string(), 1), ("Dog".to_string(), 2), ("Rabbit".to_string(), 1),
+        ]) },
+    ]);
+    let next = store.list_apartments(&Page { limit: 1, offset: 1, descending: true }, Some(DEFAULT_BUILDING)).unwrap();
+    assert_eq!(next[0].apt, apt());
+
+    let sunny = PetRef { apt: apt(), animal: "Dog".to_string(), id: registered.0[SUNNY].id.unwrap() };
+    store.add_vaccination(&sunny, &rabies()).unwrap();
+    let update = Pets(vec![kiwi()]);
+    let updated = store.update_pets(&apt(), &update, &registered, &actor()).unwrap();
+    assert_eq!(without_ids(&updated), update);
+    // Ids aren't reused after the old pets are deleted
+    assert!(registered.0.iter().all(|p| p.id < updated.0[0].id));
+    assert_eq!(store.list_pets(&apt()).unwrap(), updated);
+    // Records went with the replaced pets
+    assert!(matches!(store.list_records(&sunny), Err(StoreError::NotFound(_))));
+    assert!(store.expiring_records(date("2100-01-01")).unwrap().iter().all(|r| r.apt != apt()));
+    assert!(matches!(store.update_pets(&Apt::numbered(990003), &update, &Pets::default(), &actor()), Err(StoreError::NotFound(_))));
+
+    store.delete_apartment(&apt(), &actor()).unwrap();
+    assert!(matches!(store.list_pets(&apt()), Err(StoreError::NotFound(_))));
+    assert!(matches!(store.delete_apartment(&apt(), &actor()), Err(StoreError::NotFound(_))));
+    assert_eq!(store.list_pets(&other_apt()).unwrap(), Pets::default());
+
+    // The apartment can be registered again once deleted
+    let registered = store.register_apartment(&apt(), &update, &actor()).unwrap();
+    assert_eq!(store.list_pets(&apt()).unwrap(), registered);
+    teardown(store);
+}
+
+fn date(s: &str) -> NaiveDate {
+    s.parse().unwrap()
+}
+
+fn kiwi() -> Pet {
+    Pet::new("Bird", "Kiwi", json!({"species": "Finch"}))
+}
+
+fn rabies() -> Vaccination {
+    Vaccination { id: None, vaccine: "Rabies".to_string(), given: date("2024-01-10"), expires: date("2027-01-10"), vet: "Dr. Reyes".to_string() }
+}
+
+fn check_reviews(store: &dyn PetStore) {
+    let registered = &setup(store);
+    let thumper = PetRef { apt: apt(), animal: "Rabbit".to_string(), id: registered.0[THUMPER].id.unwrap() };
+    let review = Review {
+        status: Status::Rejected,
+        submitted_at: Some("2024-03-01T09:30:00Z".parse().unwrap()),
+        reviewed_by: Some("Jo".to_string()),
+        reviewed_at: Some("2024-03-02T17:05:00Z".parse().unwrap()),
+        reason: Some("Not allowed in the lease".to_string()),
+    };
+    let reviewed = store.review_pet(&thumper, &review, &actor()).unwrap();
+    assert_eq!(reviewed, Pet { review: review.clone(), ..registered.0[THUMPER].clone() });
+    assert_eq!(store.list_pets(&apt()).unwrap().0[THUMPER], reviewed);
+    // Pets read before the review don't replace the reviewed ones
+    assert!(matches!(store.update_pets(&apt(), &pets(), registered, &actor()), Err(StoreError::Conflict(_))));
+    assert_eq!(store.list_pets(&apt()).unwrap().0[THUMPER], reviewed);
+
+    let rejected = store.search_pets(&PetSearch { status: Some(Status::Rejected), building: Some(DEFAULT_BUILDING.to_string()), ..PetSearch::default() }).unwrap();
+    assert_eq!(rejected, vec![ApartmentPets { apt: apt(), pets: Pets(vec![reviewed]) }]);
+
+    let missing = PetRef { id: registered.0[THUMPER].id.unwrap(), ..PetRef { apt: other_apt(), animal: "Rabbit".to_string(), id: 0 } };
+    assert!(matches!(store.review_pet(&missing, &review, &actor()), Err(StoreError::NotFound(_))));
+    teardown(store);
+}
+
+// Every change is logged by the store itself, with the pets as they were when it was made.
+// Uses a unit of its own so the entries are the ones made here.
+fn check_history(store: &dyn PetStore) {
+    let unit = Apt::numbered(990007);
+    let _ = store.delete_apartment(&unit, &actor());
+    // A persistent database keeps the entries of earlier runs
+    let earlier = store.history(&unit, None).unwrap().len();
+
+    let registered = store.register_apartment(&unit, &only(&pets(), &[SUNNY, NOVA]), &actor()).unwrap();
+    let nova = PetRef { apt: unit.clone(), animal: "Cat".to_string(), id: registered.0[1].id.unwrap() };
+    let review = Review { status: Status::Approved, reviewed_by: Some("Jo".to_string()), ..Review::default() };
+    let reviewed = store.review_pet(&nova, &review, &actor()).unwrap();
+    let deleted = store.delete_pet(&nova, &Actor { name: "anonymous".to_string(), manager: false, claimed: Some("Sam".to_string()) }).unwrap();
+    // Nothing is logged for a change that wasn't made
+    assert!(matches!(store.delete_pet(&nova, &actor()), Err(StoreError::NotFound(_))));
+    store.delete_apartment(&unit, &actor()).unwrap();
+
+    let history = store.history(&unit, None).unwrap();
+    let history = &history[earlier..];
+    assert!(history.windows(2).all(|e| e[0].id < e[1].id && e[0].at <= e[1].at));
+    let logged: Vec<_> = history.iter()
+        .map(|e| (e.actor.as_str(), e.manager, e.claimed_actor.as_deref(), e.action, e.pet.clone(), e.before.clone(), e.after.clone()))
+        .collect();
+    let pet = Some(("Cat".to_string(), nova.id));
+    assert_eq!(logged, vec![
+        ("Jo", true, None, Action::Create, None, None, Some(registered.clone())),
+        ("Jo", true, None, Action::Update, pet.clone(), Some(only(&registered, &[1])), Some(Pets(vec![reviewed]))),
+        ("anonymous", false, Some("Sam"), Action::Delete, pet, Some(Pets(vec![deleted])), None),
+        ("Jo", true, None, Action::Delete, None, Some(only(&registered, &[0])), None),
+    ]);
+    let until = store.history(&unit, Some(history[0].at)).unwrap();
+    assert!(until.contains(&history[0]) && until.iter().all(|e| e.at <= history[0].at));
+    assert_eq!(store.history(&Apt::new("nowhere", "1"), None).unwrap(), vec![]);
+}
+
+fn check_transfer(store: &dyn PetStore) {
+    let registered = &setup(store);
+    let moved = |i: usize| PetId { animal: registered.0[i].animal.clone(), id: registered.0[i].id.unwrap() };
+    let unit = Apt::numbered(990003);
+    let _ = store.delete_apartment(&unit, &actor());
+    let sunny = PetRef { apt: apt(), animal: "Dog".to_string(), id: registered.0[SUNNY].id.unwrap() };
+    let rabies = store.add_vaccination(&sunny, &rabies()).unwrap();
+
+    // Nothing moves unless every pet can
+    let missing = PetId { animal: "Dog".to_string(), id: 0 };
+    assert!(matches!(store.transfer_pets(&apt(), &other_apt(), &[moved(PARIS), missing], &allow_all, &actor()), Err(StoreError::NotFound(_))));
+    assert!(matches!(store.transfer_pets(&apt(), &Apt::new("nowhere", "1"), &[moved(PARIS)], &allow_all, &actor()), Err(StoreError::NotFound(_))));
+    assert!(matches!(store.transfer_pets(&unit, &apt(), &[moved(PARIS)], &allow_all, &actor()), Err(StoreError::NotFound(_))));
+    // The check sees the destination's pets as they would be after the move
+    let one_pet = |pets: &Pets| match pets.0.len() {
+        0 | 1 => Ok(()),
+        n => Err(format!("{} pets", n))
+    };
+    assert_eq!(store.transfer_pets(&apt(), &other_apt(), &[moved(PARIS), moved(SUNNY)], &one_pet, &actor()), Err(StoreError::NotAllowed("2 pets".to_string())));
+    assert_eq!(store.list_pets(&apt()).unwrap(), *registered);
+    assert_eq!(store.list_pets(&other_apt()).unwrap(), Pets::default());
+    assert!(matches!(store.list_pets(&unit), Err(StoreError::NotFound(_))));
+
+    // The destination is registered when it has to be, pets keep their ids and records
+    assert_eq!(store.transfer_pets(&apt(), &unit, &[moved(PARIS), moved(SUNNY)], &allow_all, &actor()).unwrap(), only(registered, &[SUNNY, PARIS]));
+    assert_eq!(store.transfer_pets(&unit, &other_apt(), &[moved(SUNNY)], &allow_all, &actor()).unwrap(), only(registered, &[SUNNY]));
+    assert_eq!(store.list_pets(&unit).unwrap(), only(registered, &[PARIS]));
+    assert_eq!(store.list_records(&PetRef { apt: other_apt(), ..sunny }).unwrap().vaccinations, vec![rabies]);
+
+    // And back, where they're listed in their old order
+    store.transfer_pets(&unit, &apt(), &[moved(PARIS)], &allow_all, &actor()).unwrap();
+    assert_eq!(store.transfer_pets(&other_apt(), &apt(), &[moved(SUNNY)], &allow_all, &actor()).unwrap(), *registered);
+    store.delete_apartment(&unit, &actor()).unwrap();
+    teardown(store);
+}
+
+fn check_incidents(store: &dyn PetStore) {
+    let registered = setup(store);
+    let sunny = PetId { animal: "Dog".to_string(), id: registered.0[SUNNY].id.unwrap() };
+    // A persistent database keeps the incidents of earlier runs
+    let earlier = store.list_incidents(&apt(), None).unwrap();
+    let incident = |pet: Option<&PetId>, kind, on: &str| Incident {
+        id: None,
+        apt: apt(),
+        pet: pet.cloned(),
+        kind,
+        severity: Severity::High,
+        date: date(on),
+        description: "Bit a neighbour".to_string(),
+        reporter: "Jo".to_string(),
+        resolution: None,
+        resolved_on: None,
+    };
+    let bite = store.add_incident(&incident(Some(&sunny), IncidentType::Bite, "2024-05-01")).unwrap();
+    assert_eq!(bite, Incident { id: bite.id, ..incident(Some(&sunny), IncidentType::Bite, "2024-05-01") });
+    let noise = store.add_incident(&incident(None, IncidentType::Noise, "2024-04-01")).unwrap();
+    let missing = PetId { animal: "Dog".to_string(), id: 0 };
+    assert!(matches!(store.add_incident(&incident(Some(&missing), IncidentType::Bite, "2024-05-01")), Err(StoreError::NotFound(_))));
+    assert!(matches!(
+        store.add_incident(&Incident { apt: Apt::numbered(990003), ..incident(None, IncidentType::Noise, "2024-04-01") }),
+        Err(StoreError::NotFound(_))
+    ));
+
+    let incidents = store.list_incidents(&apt(), None).unwrap();
+    assert_eq!(incidents.len(), earlier.len() + 2);
+    assert!(incidents.contains(&bite) && incidents.contains(&noise));
+    assert_eq!(store.list_incidents(&apt(), Some(&sunny)).unwrap(), vec![bite.clone()]);
+    assert_eq!(store.get_incident(noise.id.unwrap()).unwrap(), noise);
+    assert_eq!(store.open_incident_counts(&apt()).unwrap(), BTreeMap::from([(sunny.clone(), 1)]));
+
+    let resolved = store.resolve_incident(bite.id.unwrap(), "Muzzled", date("2024-05-03")).unwrap();
+    assert_eq!(resolved, Incident { resolution: Some("Muzzled".to_string()), resolved_on: Some(date("2024-05-03")), ..bite.clone() });
+    assert!(matches!(store.resolve_incident(bite.id.unwrap(), "Again", date("2024-05-04")), Err(StoreError::Conflict(_))));
+    assert!(matches!(store.resolve_incident(0, "Nothing", date("2024-05-04")), Err(StoreError::NotFound(_))));
+    assert_eq!(store.open_incident_counts(&apt()).unwrap(), BTreeMap::new());
+
+    // A pet's incidents follow it, the unit keeps those reported there
+    let barking = store.add_incident(&incident(Some(&sunny), IncidentType::Noise, "2024-06-01")).unwrap();
+    store.transfer_pets(&apt(), &other_apt(), std::slice::from_ref(&sunny), &allow_all, &actor()).unwrap();
+    assert_eq!(store.open_incident_counts(&other_apt()).unwrap(), BTreeMap::from([(sunny.clone(), 1)]));
+    assert_eq!(store.open_incident_counts(&apt()).unwrap(), BTreeMap::new());
+    assert_eq!(store.list_incidents(&other_apt(), Some(&sunny)).unwrap(), vec![resolved, barking.clone()]);
+    assert!(!store.list_incidents(&other_apt(), None).unwrap().contains(&barking));
+    teardown(store);
+}
+
+fn check_microchips(store: &dyn PetStore) {
+    let (first, second) = (Apt::numbered(990005), Apt::numbered(990006));
+    let _ = store.delete_apartment(&first, &actor());
+    let _ = store.delete_apartment(&second, &actor());
+    let (chip, other_chip) = ("990000000000001", "990000000000002");
+    let chipped = |name: &str, microchip: &str| Pet {
+        microchip: Some(microchip.to_string()),
+        ..Pet::new("Cat", name, json!({"weight": 10, "hair": "ShortHaired"}))
+    };
+
+    let registered = store.register_apartment(&first, &Pets(vec![chipped("Mochi", chip)]), &actor()).unwrap();
+    assert_eq!(store.find_microchip(chip).unwrap(), (first.clone(), registered.0[0].clone()));
+    assert!(matches!(store.find_microchip(other_chip), Err(StoreError::NotFound(_))));
+
+    // A chip is on one live pet and a failed registration registers nothing
+    assert!(matches!(store.register_apartment(&second, &Pets(vec![chipped("Taro", chip)]), &actor()), Err(StoreError::Conflict(_))));
+    assert!(matches!(
+        store.register_apartment(&second, &Pets(vec![chipped("Taro", other_chip), chipped("Kuro", other_chip)]), &actor()),
+        Err(StoreError::Conflict(_))
+    ));
+    assert!(matches!(store.list_pets(&second), Err(StoreError::NotFound(_))));
+    assert!(matches!(store.find_microchip(other_chip), Err(StoreError::NotFound(_))));
+
+    // Replaced pets give up their chips, and can't be restored while another pet has it
+    let updated = store.update_pets(&first, &Pets(vec![chipped("Mochi", chip)]), &registered, &actor()).unwrap();
+    assert_eq!(store.find_microchip(chip).unwrap().1, updated.0[0]);
+    let replaced = PetRef { apt: first.clone(), animal: "Cat".to_string(), id: registered.0[0].id.unwrap() };
+    assert!(matches!(store.restore_pet(&replaced, &allow_all, &actor()), Err(StoreError::Conflict(_))));
+
+    store.delete_apartment(&first, &actor()).unwrap();
+    store.register_apartment(&second, &Pets(vec![chipped("Taro", chip)]), &actor()).unwrap();
+    assert!(matches!(store.restore_apartment(&first, &actor()), Err(StoreError::Conflict(_))));
+    assert!(matches!(store.list_pets(&first), Err(StoreError::NotFound(_))));
+    assert_eq!(store.find_microchip(chip).unwrap().0, second);
+    store.delete_apartment(&second, &actor()).unwrap();
+}
+
+fn check_soft_delete(store: &dyn PetStore) {
+    let replaced = setup(store);
+    store.delete_apartment(&apt(), &actor()).unwrap();
+    let registered = &store.register_apartment(&apt(), &Pets(vec![kiwi()]), &actor()).unwrap();
+    let kiwi = PetRef { apt: apt(), animal: "Bird".to_string(), id: registered.0[0].id.unwrap() };
+    // Only one apartment of the unit can be registered at a time
+    assert!(matches!(store.restore_apartment(&apt(), &actor()), Err(StoreError::Conflict(_))));
+
+    let deleted = store.delete_pet(&kiwi, &actor()).unwrap();
+    assert!(deleted.deleted_at.is_some());
+    assert_eq!(Pet { deleted_at: None, ..deleted.clone() }, registered.0[0]);
+    assert_eq!(store.list_pets(&apt()).unwrap(), Pets::default());
+    assert!(matches!(store.delete_pet(&kiwi, &actor()), Err(StoreError::NotFound(_))));
+    assert!(matches!(store.list_records(&kiwi), Err(StoreError::NotFound(_))));
+    assert_eq!(store.list_deleted(&apt()).unwrap().0[0], deleted);
+
+    // The check sees the apartment's pets as they would be after the restore
+    let no_birds = |pets: &Pets| match pets.0.iter().any(|p| p.animal == "Bird") {
+        true => Err("No birds".to_string()),
+        false => Ok(())
+    };
+    assert_eq!(store.restore_pet(&kiwi, &no_birds, &actor()), Err(StoreError::NotAllowed("No birds".to_string())));
+    assert_eq!(store.list_pets(&apt()).unwrap(), Pets::default());
+    assert_eq!(store.list_deleted(&apt()).unwrap().0[0], deleted);
+
+    assert_eq!(&store.restore_pet(&kiwi, &allow_all, &actor()).unwrap(), &registered.0[0]);
+    assert_eq!(store.list_pets(&apt()).unwrap(), *registered);
+    assert!(matches!(store.restore_pet(&kiwi, &allow_all, &actor()), Err(StoreError::NotFound(_))));
+
+    // Deleted apartments are hidden until restored with the pets they had
+    store.delete_apartment(&apt(), &actor()).unwrap();
+    assert!(matches!(store.restore_pet(&kiwi, &allow_all, &actor()), Err(StoreError::NotFound(_))));
+    let units = store.list_apartments(&Page { limit: 2, offset: 0, descending: true }, Some(DEFAULT_BUILDING)).unwrap();
+    assert!(units.iter().all(|a| a.apt != apt()));
+    assert!(store.search_pets(&PetSearch { name: Some("kiwi".to_string()), ..PetSearch::default() }).unwrap().iter().all(|a| a.apt != apt()));
+    assert_eq!(store.restore_apartment(&apt(), &actor()).unwrap(), *registered);
+    assert_eq!(store.list_pets(&apt()).unwrap(), *registered);
+    assert!(matches!(store.restore_apartment(&Apt::numbered(990004), &actor()), Err(StoreError::NotFound(_))));
+
+    // Purging removes whatever was deleted before the cut-off and leaves live pets alone
+    let purged = store.purge_deleted(Utc::now()).unwrap();
+    assert!(replaced.0.iter().all(|p| purged.0.iter().any(|d| d.animal == p.animal && d.id == p.id)));
+    assert!(purged.0.iter().all(|p| p.id != Some(kiwi.id)));
+    assert_eq!(store.list_deleted(&apt()).unwrap(), Pets::default());
+    assert_eq!(store.list_pets(&apt()).unwrap(), *registered);
+    teardown(store);
+}
+
+fn check_records(store: &dyn PetStore) {
+    let registered = &setup(store);
+    let pet = |i: usize| PetRef { apt: apt(), animal: registered.0[i].animal.clone(), id: registered.0[i].id.unwrap() };
+    let vaccination = |vaccine: &str, given: &str, expires: &str| Vaccination {
+        id: None, vaccine: vaccine.to_string(), given: date(given), expires: date(expires), vet: "Dr. Reyes".to_string(),
+    };
+
+    let rabies = store.add_vaccination(&pet(SUNNY), &vaccination("Rabies", "2020-03-01", "2021-03-01")).unwrap();
+    assert!(rabies.id.is_some());
+    store.add_vaccination(&pet(SUNNY), &vaccination("rabies", "2021-02-20", "2024-02-20")).unwrap();
+    store.add_vaccination(&pet(NOVA), &vaccination("FVRCP", "2022-05-01", "2023-05-01")).unwrap();
+    let license = License { id: None, number: "TEST-990001".to_string(), issued: date("2022-01-01"), expires: date("2022-12-31") };
+    let added = store.add_license(&pet(PARIS), &license).unwrap();
+    assert_eq!(License { id: None, ..added.clone() }, license);
+
+    // License numbers are unique across the building
+    assert!(matches!(store.add_license(&pet(SUNNY), &license), Err(StoreError::Conflict(_))));
+    // The pet has to exist in that apartment
+    let elsewhere = PetRef { apt: other_apt(), ..pet(SUNNY) };
+    assert!(matches!(store.add_vaccination(&elsewhere, &vaccination("Rabies", "2020-01-01", "2021-01-01")), Err(StoreError::NotFound(_))));
+    assert!(matches!(store.list_records(&PetRef { animal: "Cat".to_string(), ..pet(SUNNY) }), Err(StoreError::NotFound(_))));
+
+    let records = store.list_records(&pet(SUNNY)).unwrap();
+    assert_eq!(records.vaccinations.iter().map(|v| v.expires).collect::<Vec<_>>(), vec![date("2021-03-01"), date("2024-02-20")]);
+    assert!(records.licenses.is_empty());
+    assert_eq!(store.list_records(&pet(PARIS)).unwrap().licenses, vec![added]);
+
+    let expiring = |by: &str| -> Vec<(String, String)> {
+        store.expiring_records(date(by)).unwrap().into_iter()
+            .filter(|r| r.apt == apt())
+            .map(|r| (r.name, r.detail))
+            .collect()
+    };
+    // Sunny's first rabies shot was replaced by a booster, so it only shows up once that runs out
+    assert_eq!(expiring("2023-06-01"), vec![
+        ("Paris".to_string(), "TEST-990001".to_string()),
+        ("Nova".to_string(), "FVRCP".to_string()),
+    ]);
+    assert_eq!(expiring("2024-12-31").len(), 3);
+    assert!(expiring("2021-12-31").is_empty());
+    teardown(store);
+}
+
+fn check_tenants(store: &dyn PetStore) {
+    setup(store);
+    let tenancy = |move_in: &str, move_out: Option<&str>| Tenancy {
+        building: DEFAULT_BUILDING.to_string(), unit: OTHER_APT.to_string(), move_in: date(move_in), move_out: move_out.map(date),
+    };
+    let tenant = Tenant {
+        id: None,
+        name: "Ana Ruiz".to_string(),
+        email: Some("ana@example.com".to_string()),
+        phone: None,
+        tenancies: vec![tenancy("2023-01-01", None), Tenancy { unit: "990003".to_string(), ..tenancy("2020-06-01", Some("2022-12-31")) }],
+    };
+    let created = store.create_tenant(&tenant).unwrap();
+    let id = created.id.unwrap();
+    // Tenancies come back oldest first
+    assert_eq!(created.tenancies, vec![tenant.tenancies[1].clone(), tenant.tenancies[0].clone()]);
+    assert_eq!(store.get_tenant(id).unwrap(), created);
+    assert!(store.list_tenants(Some(&other_apt())).unwrap().contains(&created));
+    assert!(store.list_tenants(None).unwrap().contains(&created));
+    assert!(store.list_tenants(Some(&Apt::numbered(990004))).unwrap().iter().all(|t| t.id != Some(id)));
+
+    let update = Tenant { phone: Some("555-0100".to_string()), tenancies: vec![tenancy("2023-01-01", Some("2024-06-30"))], ..tenant.clone() };
+    let updated = store.update_tenant(id, &update).unwrap();
+    assert_eq!(updated, Tenant { id: Some(id), ..update.clone() });
+    assert!(store.list_tenants(Some(&Apt::numbered(990003))).unwrap().iter().all(|t| t.id != Some(id)));
+
+    // Pets keep their owner until the tenant is removed
+    let owned = Pets(vec![Pet { owner: Some(id), ..Pet::new("Cat", "Mochi", json!({"weight": 9, "hair": "ShortHaired"})) }]);
+    let stored = store.update_pets(&other_apt(), &owned, &Pets::default(), &actor()).unwrap();
+    assert_eq!(store.list_pets(&other_apt()).unwrap(), stored);
+    assert_eq!(stored.0[0].owner, Some(id));
+
+    store.delete_tenant(id).unwrap();
+    assert_eq!(store.list_pets(&other_apt()).unwrap().0[0].owner, None);
+    assert!(matches!(store.get_tenant(id), Err(StoreError::NotFound(_))));
+    assert!(matches!(store.update_tenant(id, &update), Err(StoreError::NotFound(_))));
+    assert!(matches!(store.delete_tenant(id), Err(StoreError::NotFound(_))));
+    let unowned = store.list_pets(&other_apt()).unwrap();
+    assert!(matches!(store.update_pets(&other_apt(), &owned, &unowned, &actor()), Err(StoreError::NotFound(_))));
+
+    teardown(store);
+}
+
+fn check_buildings(store: &dyn PetStore) {
+    let registered = &setup(store);
+    let building = Building { code: BUILDING.to_string(), name: "Annex".to_string(), property: "Test Property".to_string() };
+    // Left over from an earlier run against a persistent database, the code is taken either way afterwards
+    assert!(matches!(store.create_building(&building), Ok(_) | Err(StoreError::Conflict(_))));
+    assert!(matches!(store.create_building(&building), Err(StoreError::Conflict(_))));
+    assert!(store.list_buildings().unwrap().contains(&building));
+
+    let unit = |unit: &str| Apt::new(BUILDING, unit);
+    for u in ["12B", "PH-2", &APT.to_string()] {
+        let _ = store.delete_apartment(&unit(u), &actor());
+    }
+    let kiwi = store.register_apartment(&unit("PH-2"), &Pets(vec![Pet::new("Bird", "Kiwi", json!({"species": "Finch"}))]), &actor()).unwrap();
+    store.register_apartment(&unit("12B"), &Pets::default(), &actor()).unwrap();
+    // Numbers are only unique within a building
+    store.register_apartment(&unit(&APT.to_string()), &Pets::default(), &actor()).unwrap();
+    assert_eq!(store.list_pets(&apt()).unwrap(), *registered);
+    assert_eq!(store.list_pets(&unit("PH-2")).unwrap(), kiwi);
+    assert!(matches!(store.register_apartment(&unit("12B"), &Pets::default(), &actor()), Err(StoreError::Conflict(_))));
+    assert!(matches!(store.register_apartment(&Apt::new("no-such-building", "1"), &Pets::default(), &actor()), Err(StoreError::NotFound(_))));
+
+    // Shorter units first, so numbered units sort by number
+    let units = store.list_apartments(&Page::default(), Some(BUILDING)).unwrap();
+    assert_eq!(units.iter().map(|a| a.apt.unit.as_str()).collect::<Vec<_>>(), vec!["12B", "PH-2", "990001"]);
+    assert_eq!(units[1].pets, BTreeMap::from([("Bird".to_string(), 1)]));
+
+    // Apartment ranges only cover numbered units
+    let in_building = store.search_pets(&PetSearch { building: Some(BUILDING.to_string()), ..PetSearch::default() }).unwrap();
+    assert_eq!(in_building, vec![ApartmentPets { apt: unit("PH-2"), pets: kiwi.clone() }]);
+    let in_range = store.search_pets(&PetSearch { building: Some(BUILDING.to_string()), from_apt: Some(0), ..PetSearch::default() }).unwrap();
+    assert!(in_range.is_empty());
+
+    let tenancy = Tenancy { building: "no-such-building".to_string(), unit: "1".to_string(), move_in: date("2024-01-01"), move_out: None };
+    let tenant = Tenant { id: None, name: "Bo".to_string(), email: None, phone: None, tenancies: vec![tenancy] };
+    assert_eq!(store.create_tenant(&tenant), Err(StoreError::NotFound("Building not found".to_string())));
+
+    for u in ["12B", "PH-2", &APT.to_string()] {
+        store.delete_apartment(&unit(u), &actor()).unwrap();
+    }
+    teardown(store);
+}
+
+fn check_search(store: &dyn PetStore) {
+    let registered = &setup(store);
+    // Restricted to the test apartment so other rows in a shared database don't interfere
+    let search = |search: PetSearch| -> Pets {
+        store.search_pets(&search).unwrap().into_iter()
+            .find(|a| a.apt == apt())
+            .map(|a| a.pets)
+            .unwrap_or_default()
+    };
+
+    let text = |field: &str, value: &str| vec![(field.to_string(), value.to_string())];
+
+    let found = search(PetSearch { equals: text("breed", "labrador"), ..PetSearch::default() });
+    assert_eq!(found, only(registered, &[SUNNY]));
+
+    let found = search(PetSearch {
+        name: Some("PA".to_string()),
+        name_match: NameMatch::Prefix,
+        ..PetSearch::default()
+    });
+    assert_eq!(found, only(registered, &[PARIS]));
+
+    let found = search(PetSearch { name: Some("o".to_string()), ..PetSearch::default() });
+    assert_eq!(found, only(registered, &[NOVA, POLLY]));
+
+    // Birds have no weight so they never match a weight range
+    let found = search(PetSearch {
+        min: vec![("weight".to_string(), 12.5)],
+        max: vec![("weight".to_string(), 60.0)],
+        ..PetSearch::default()
+    });
+    assert_eq!(found, only(registered, &[PARIS, NOVA]));
+
+    let found = search(PetSearch { min: vec![("weight".to_string(), 12.75)], max: vec![("weight".to_string(), 60.0)], ..PetSearch::default() });
+    assert_eq!(found, only(registered, &[PARIS]));
+
+    let found = search(PetSearch { animal: Some("Bird".to_string()), equals: text("species", "PARROT"), ..PetSearch::default() });
+    assert_eq!(found, only(registered, &[POLLY]));
+
+    let found = search(PetSearch { equals: text("hair", "ShortHaired"), ..PetSearch::default() });
+    assert_eq!(found, Pets::default());
+
+    let found = search(PetSearch { animal: Some("Rabbit".to_string()), equals: text("color", "grey"), ..PetSearch::default() });
+    assert_eq!(found, only(registered, &[THUMPER]));
+
+    // Wildcards in the search text are matched literally
+    let found = search(PetSearch { name: Some("%".to_string()), ..PetSearch::default() });
+    assert_eq!(found, Pets::default());
+
+    let apartments = store.search_pets(&PetSearch { from_apt: Some(APT), to_apt: Some(OTHER_APT), ..PetSearch::default() }).unwrap();
+    assert_eq!(apartments, vec![ApartmentPets { apt: apt(), pets: registered.clone() }]);
+    let apartments = store.search_pets(&PetSearch { from_apt: Some(OTHER_APT), ..PetSearch::default() }).unwrap();
+    assert!(apartments.iter().all(|a| a.apt.number().is_some_and(|n| n >= OTHER_APT)));
+
+    let apartments = store.search_pets(&PetSearch { name: Some("sunny".to_string()), ..PetSearch::default() }).unwrap();
+    assert!(apartments.contains(&ApartmentPets { apt: apt(), pets: only(registered, &[SUNNY]) }));
+    teardown(store);
+}
+
+#[test]
+fn sqlite_store_persists_to_file() {
+    let path = std::env::temp_dir().join(format!("apt-pets-test-{}.db", std::process::id()));
+    let db_url = format!("sqlite://{}", path.display());
+
+    let registered = store::open(&db_url).unwrap().register_apartment(&apt(), &pets(), &actor()).unwrap();
+    // Reopening runs the migrations again, which must be a no-op
+    assert_eq!(store::open(&db_url).unwrap().list_pets(&apt()).unwrap(), registered);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+// Every check runs against a fresh store of each backend. Postgres is one shared database,
+// so its checks take turns.
+static POSTGRES: Mutex<()> = Mutex::new(());
+
+fn with_postgres(check: fn(&dyn PetStore)) {
+    let db_url = std::env::var("TEST_DB_URL").expect("TEST_DB_URL has to point at a Postgres database");
+    let _turn = POSTGRES.lock().unwrap_or_else(|e| e.into_inner());
+    check(store::open(&db_url).unwrap().as_ref())
+}
+
+macro_rules! store_tests {
+    ($($name:ident => $check:ident),* $(,)?) => {
+        mod memory {
+            use super::*;
+            $(#[test] fn $name() { $check(&MemoryStore::new()) })*
+        }
+
+        mod sqlite {
+            use super::*;
+            $(#[test] fn $name() { $check(&SqliteStore::connect("sqlite://:memory:").unwrap()) })*
+        }
+
+        mod postgres {
+            use super::*;
+            $(#[test] #[ignore = "needs a Postgres database in TEST_DB_URL"] fn $name() { with_postgres($check) })*
+        }
+    };
+}
+
+store_tests! {
+    apartments => check_apartments,
+    search => check_search,
+    records => check_records,
+    tenants => check_tenants,
+    buildings => check_buildings,
+    reviews => check_reviews,
+    history => check_history,
+    transfer => check_transfer,
+    incidents => check_incidents,
+    microchips => check_microchips,
+    soft_delete => check_soft_delete,
+}